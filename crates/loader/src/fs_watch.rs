@@ -0,0 +1,204 @@
+use std::{
+    collections::HashSet,
+    ffi::OsString,
+    fs, iter, mem,
+    os::windows::ffi::{OsStrExt as _, OsStringExt as _},
+    path::{Path, PathBuf},
+    ptr, slice,
+    sync::{LazyLock, Mutex, Once},
+    thread,
+    time::Duration,
+};
+
+use shared::{
+    config::Config,
+    manifest::get_manifest,
+    utils::{OwnedHandle, SuperLock as _},
+};
+use tracing::{error, info, trace, warn};
+use windows::{
+    core::PCWSTR,
+    Win32::Storage::FileSystem::{
+        CreateFileW, ReadDirectoryChangesW, FILE_ACTION_ADDED, FILE_ACTION_MODIFIED,
+        FILE_ACTION_RENAMED_NEW_NAME, FILE_FLAG_BACKUP_SEMANTICS, FILE_LIST_DIRECTORY,
+        FILE_NOTIFY_CHANGE_FILE_NAME, FILE_NOTIFY_CHANGE_LAST_WRITE, FILE_NOTIFY_INFORMATION,
+        FILE_SHARE_DELETE, FILE_SHARE_READ, FILE_SHARE_WRITE, OPEN_EXISTING,
+    },
+};
+
+use crate::loader::load_live;
+
+/// how long to wait for changes to settle before injecting, since a build usually writes a file many times
+const DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// lowercased names (filename without extension) of every plugin which was already loaded
+static INJECTED: LazyLock<Mutex<HashSet<String>>> = LazyLock::new(Mutex::default);
+
+/// Remember that a plugin was loaded, so the watcher doesn't load it again
+pub fn mark_injected(name: &str) {
+    INJECTED.super_lock().insert(name.to_lowercase());
+}
+
+/// Watch the plugins dir, and load plugins which are added to it while the game runs
+///
+/// Only the first call starts the watcher
+pub fn spawn(config: &'static Config, plugins_dir: PathBuf) {
+    static SPAWN: Once = Once::new();
+
+    SPAWN.call_once(|| {
+        info!(dir = %plugins_dir.display(), "Watching plugins dir for new plugins");
+
+        thread::spawn(move || {
+            if let Err(e) = watch(config, &plugins_dir) {
+                error!(%e, "plugins dir watcher failed; new plugins will no longer be loaded");
+            }
+        });
+    });
+}
+
+fn watch(config: &Config, plugins_dir: &Path) -> windows::core::Result<()> {
+    let dir = plugins_dir
+        .as_os_str()
+        .encode_wide()
+        .chain(iter::once(0))
+        .collect::<Vec<_>>();
+
+    let handle = unsafe {
+        CreateFileW(
+            PCWSTR::from_raw(dir.as_ptr()),
+            FILE_LIST_DIRECTORY.0,
+            FILE_SHARE_READ | FILE_SHARE_WRITE | FILE_SHARE_DELETE,
+            None,
+            OPEN_EXISTING,
+            // required to open a directory
+            FILE_FLAG_BACKUP_SEMANTICS,
+            None,
+        )
+    };
+
+    let handle = OwnedHandle::new(handle?);
+
+    // u32 so the FILE_NOTIFY_INFORMATION entries are aligned
+    let mut buffer = vec![0u32; 16 * 1024];
+
+    loop {
+        let mut returned = 0u32;
+
+        // changes made between calls are buffered, so nothing is missed while injecting
+        unsafe {
+            ReadDirectoryChangesW(
+                handle.as_raw_handle(),
+                buffer.as_mut_ptr().cast(),
+                (buffer.len() * size_of::<u32>()) as u32,
+                false,
+                FILE_NOTIFY_CHANGE_FILE_NAME | FILE_NOTIFY_CHANGE_LAST_WRITE,
+                Some(&mut returned),
+                None,
+                None,
+            )?;
+        }
+
+        // 0 means the buffer overflowed and the changes were lost
+        let changed = parse_changes(&buffer, returned as usize);
+
+        // let the writes settle. anything changed in the meantime is returned by the next call,
+        // and loading it again is skipped
+        thread::sleep(DEBOUNCE);
+
+        let new = changed
+            .into_iter()
+            .map(|file_name| plugins_dir.join(file_name))
+            .filter(|path| is_new_plugin(config, path))
+            .collect::<Vec<_>>();
+
+        if !new.is_empty() {
+            inject(config, new);
+        }
+    }
+}
+
+fn parse_changes(buffer: &[u32], len: usize) -> HashSet<OsString> {
+    let mut changed = HashSet::new();
+
+    if len == 0 {
+        warn!("plugins dir watcher buffer overflowed; some changes may have been missed");
+        return changed;
+    }
+
+    let base = buffer.as_ptr().cast::<u8>();
+    let mut offset = 0usize;
+
+    loop {
+        // the entries must fit inside of what was returned
+        if offset + size_of::<FILE_NOTIFY_INFORMATION>() > len {
+            break;
+        }
+
+        let info =
+            unsafe { ptr::read_unaligned(base.add(offset).cast::<FILE_NOTIFY_INFORMATION>()) };
+
+        let name_len = info.FileNameLength as usize / size_of::<u16>();
+        let name_offset = offset + mem::offset_of!(FILE_NOTIFY_INFORMATION, FileName);
+
+        if name_offset + name_len * size_of::<u16>() > len {
+            break;
+        }
+
+        let name = unsafe { slice::from_raw_parts(base.add(name_offset).cast::<u16>(), name_len) };
+
+        if matches!(
+            info.Action,
+            FILE_ACTION_ADDED | FILE_ACTION_MODIFIED | FILE_ACTION_RENAMED_NEW_NAME
+        ) {
+            changed.insert(OsString::from_wide(name));
+        }
+
+        if info.NextEntryOffset == 0 {
+            break;
+        }
+
+        offset += info.NextEntryOffset as usize;
+    }
+
+    changed
+}
+
+/// Whether a changed file should be live loaded. Everything else about it is checked by the loader
+fn is_new_plugin(config: &Config, path: &Path) -> bool {
+    if !path.is_file() || !config.core.has_plugin_extension(path) {
+        return false;
+    }
+
+    let Some(name) = path.file_stem().and_then(|n| n.to_str()) else {
+        return false;
+    };
+
+    if INJECTED.super_lock().contains(&name.to_lowercase()) {
+        trace!(%name, "changed plugin was already loaded; restart the game to load the new version");
+        return false;
+    }
+
+    // with a manifest, only the plugins listed in it are ever loaded
+    if let Ok(Some(manifest)) = get_manifest() {
+        if !manifest.plugin(name).is_some_and(|p| p.enabled) {
+            trace!(%name, "skipping new plugin which isn't enabled in manifest.toml");
+            return false;
+        }
+    }
+
+    true
+}
+
+/// Load new plugins the same way the plugins were loaded when the game started
+fn inject(config: &Config, paths: Vec<PathBuf>) {
+    info!(plugins = ?paths, "Live loading new plugins");
+
+    let Some(report) = load_live(config, paths) else {
+        return;
+    };
+
+    // it may still have been in the middle of being written, so let the next change retry it
+    for result in report.results.iter().filter(|r| r.error.is_some()) {
+        INJECTED.super_lock().remove(&result.name.to_lowercase());
+    }
+}
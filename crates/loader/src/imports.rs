@@ -0,0 +1,154 @@
+use tracing::{trace, warn};
+use unicase::UniCase;
+
+/// Reorder modules so every module comes after the ones it imports from. Modules are matched by filename
+/// (case-insensitive). Otherwise the existing order is kept, so an explicit order (e.g. the manifest's) is only
+/// changed where it has to be
+///
+/// Import cycles can't be ordered; they're logged, and the modules in them are ordered as if the import which
+/// closes the cycle didn't exist
+///
+/// `file_name` is a module's filename, and `imports` the dll names it imports from
+pub fn order_by_imports<T>(
+    modules: Vec<T>,
+    file_name: impl Fn(&T) -> String,
+    imports: impl Fn(&T) -> &[String],
+) -> Vec<T> {
+    #[derive(Copy, Clone, PartialEq)]
+    enum Mark {
+        None,
+        Visiting,
+        Done,
+    }
+
+    // for each module, the indices of the other modules it imports from
+    let names = modules.iter().map(&file_name).collect::<Vec<_>>();
+    let deps = modules
+        .iter()
+        .enumerate()
+        .map(|(i, module)| {
+            imports(module)
+                .iter()
+                .filter_map(|import| {
+                    let import = UniCase::new(import.as_str());
+                    names
+                        .iter()
+                        .position(|n| UniCase::new(n.as_str()) == import)
+                })
+                .filter(|&dep| dep != i)
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+
+    fn visit(
+        i: usize,
+        deps: &[Vec<usize>],
+        names: &[String],
+        marks: &mut [Mark],
+        order: &mut Vec<usize>,
+    ) {
+        if marks[i] != Mark::None {
+            return;
+        }
+
+        marks[i] = Mark::Visiting;
+
+        for &dep in &deps[i] {
+            if marks[dep] == Mark::Visiting {
+                warn!(
+                    module = names[i],
+                    dependency = names[dep],
+                    "plugins import from each other in a cycle, so they can't be ordered by their imports"
+                );
+                continue;
+            }
+
+            trace!(
+                module = names[i],
+                dependency = names[dep],
+                "ordering plugin after a plugin it imports from"
+            );
+
+            visit(dep, deps, names, marks, order);
+        }
+
+        marks[i] = Mark::Done;
+        order.push(i);
+    }
+
+    let mut marks = vec![Mark::None; modules.len()];
+    let mut order = Vec::with_capacity(modules.len());
+    for i in 0..modules.len() {
+        visit(i, &deps, &names, &mut marks, &mut order);
+    }
+
+    let mut modules = modules.into_iter().map(Some).collect::<Vec<_>>();
+    order
+        .into_iter()
+        .filter_map(|i| modules[i].take())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn order(modules: &[(&str, &[&str])]) -> Vec<String> {
+        let modules = modules
+            .iter()
+            .map(|&(name, imports)| {
+                let imports = imports.iter().map(|i| i.to_string()).collect::<Vec<_>>();
+                (name.to_owned(), imports)
+            })
+            .collect();
+
+        order_by_imports(modules, |m| m.0.clone(), |m| &m.1)
+            .into_iter()
+            .map(|m| m.0)
+            .collect()
+    }
+
+    #[test]
+    fn imported_module_loads_first() {
+        let order = order(&[("B.dll", &["A.dll"]), ("A.dll", &[])]);
+        assert_eq!(order, ["A.dll", "B.dll"]);
+    }
+
+    #[test]
+    fn imports_are_case_insensitive() {
+        let order = order(&[("B.dll", &["a.DLL"]), ("A.dll", &[])]);
+        assert_eq!(order, ["A.dll", "B.dll"]);
+    }
+
+    #[test]
+    fn orders_chains() {
+        let order = order(&[("C.dll", &["B.dll"]), ("B.dll", &["A.dll"]), ("A.dll", &[])]);
+        assert_eq!(order, ["A.dll", "B.dll", "C.dll"]);
+    }
+
+    #[test]
+    fn keeps_order_without_imports_between_them() {
+        let order = order(&[
+            ("C.dll", &["KERNEL32.dll"]),
+            ("A.dll", &[]),
+            ("B.dll", &["C.dll"]),
+        ]);
+        assert_eq!(order, ["C.dll", "A.dll", "B.dll"]);
+    }
+
+    #[test]
+    fn cycles_keep_every_module() {
+        let order = order(&[
+            ("A.dll", &["B.dll"]),
+            ("B.dll", &["A.dll"]),
+            ("C.dll", &["A.dll", "C.dll"]),
+        ]);
+
+        let pos = |name| order.iter().position(|m| m == name).unwrap();
+
+        assert_eq!(order.len(), 3);
+        // the cycle can't be ordered, but what depends on it still comes after it
+        assert!(pos("C.dll") > pos("A.dll"));
+        assert!(pos("C.dll") > pos("B.dll"));
+    }
+}
@@ -1,23 +1,33 @@
 mod client;
+mod fs_watch;
+mod imports;
 mod loader;
 mod logging;
 mod panic_hook;
+mod report;
+mod script_extender;
+mod staging;
 mod utils;
 
 use std::{
-    ffi::c_void,
-    mem, panic,
+    ffi::{c_void, OsString},
+    mem,
+    os::windows::ffi::OsStringExt as _,
+    panic,
+    path::{Path, PathBuf},
     sync::{LazyLock, Mutex, Once, OnceLock},
     thread,
 };
 
-use eyre::{Context as _, Error};
+use eyre::{eyre, Context as _, Error};
 use native_plugin_lib::declare_plugin;
 use shared::{
+    config::disable_config_writeback,
+    paths::set_portable_dir,
     pipe::commands::Request,
     popup::warn_popup,
     thread_data::ThreadData,
-    utils::{OwnedHandle, SuperLock as _},
+    utils::{fill_path_buf, OwnedHandle, SuperLock as _},
 };
 use tracing::{error, trace};
 use windows::{
@@ -26,7 +36,7 @@ use windows::{
         Foundation::{HINSTANCE, HMODULE},
         System::{
             LibraryLoader::{
-                GetModuleHandleExW, GET_MODULE_HANDLE_EX_FLAG_FROM_ADDRESS,
+                GetModuleFileNameW, GetModuleHandleExW, GET_MODULE_HANDLE_EX_FLAG_FROM_ADDRESS,
                 GET_MODULE_HANDLE_EX_FLAG_PIN,
             },
             SystemServices::{DLL_PROCESS_ATTACH, DLL_PROCESS_DETACH},
@@ -129,10 +139,28 @@ unsafe extern "system-unwind" fn Init(data: &ThreadData) -> u32 {
         // extract and process thread data
         _ = CLIENT.try_send(Request::Auth(data.auth).into());
 
-        setup_logging(&data.log).context("failed to setup logging")?;
+        // loader.dll sits next to the tool, which is where portable mode keeps everything
+        if data.portable {
+            let dir = module_path(module.into()).and_then(|p| p.parent().map(Path::to_path_buf));
+            match dir {
+                Some(dir) => set_portable_dir(dir),
+                None => return Err(eyre!("failed to find loader.dll's dir for portable mode")),
+            }
+        }
+
+        // must be done before the config is first read
+        if data.no_config_writeback {
+            disable_config_writeback();
+        }
+
+        // Init is called once per load phase, but logging can only be set up once
+        static LOGGING: Once = Once::new();
+        let mut logging = Ok(());
+        LOGGING.call_once(|| logging = setup_logging(&data.log));
+        logging.context("failed to setup logging")?;
 
         // blocking call which waits for all plugins to finish DllMain/Init
-        load_plugins()?;
+        load_plugins(data.phase, data.quiet, data.force)?;
 
         Ok::<_, Error>(())
     });
@@ -150,6 +178,21 @@ unsafe extern "system-unwind" fn Init(data: &ThreadData) -> u32 {
     0
 }
 
+/// The full path of a module
+fn module_path(module: HMODULE) -> Option<PathBuf> {
+    let mut buf = Vec::new();
+
+    let len = fill_path_buf(&mut buf, |buf| {
+        match unsafe { GetModuleFileNameW(Some(module), buf) } {
+            0 => Err(()),
+            len => Ok(Some(len as usize)),
+        }
+    })
+    .ok()?;
+
+    Some(PathBuf::from(OsString::from_wide(&buf[..len])))
+}
+
 /// Detects if yabg3nml injected this dll.
 /// This is safe to use from DllMain
 fn is_yabg3nml() -> bool {
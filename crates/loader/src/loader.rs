@@ -1,28 +1,69 @@
-use std::{fs, iter, mem, os::windows::ffi::OsStrExt, path::PathBuf};
+use std::{
+    collections::{hash_map, HashMap},
+    env,
+    ffi::CString,
+    fs, io,
+    iter::{self, Peekable},
+    mem,
+    num::NonZeroUsize,
+    os::windows::ffi::OsStrExt,
+    path::{Path, PathBuf},
+    process,
+    sync::{Arc, LazyLock, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
 
 use eyre::{Context as _, Report, Result};
 use native_plugin_lib::Version;
 use shared::{
-    config::get_config,
-    paths::get_bg3_plugins_dir,
-    popup::warn_popup,
-    utils::{tri, SuperLock as _},
+    config::{get_config, Config, LoadLibrary},
+    discovery::{list_plugin_files, plugins_dir_for},
+    manifest::{get_manifest, Manifest},
+    paths::{get_bg3_plugins_dir, get_plugin_override_path, to_extended_path, LAUNCHER_EXE},
+    pe::read_imports,
+    pipe::commands::Receive,
+    popup::{confirm_popup, warn_popup},
+    signature,
+    thread_data::LoadPhase,
+    utils::{process_creation_time, tri, SuperLock as _},
+    version_info::{FileVersion, VersionInfo, VersionRange},
 };
 use tracing::{error, info, trace, warn};
+use unicase::UniCase;
 use windows::{
-    core::{s, PCWSTR},
-    Win32::System::LibraryLoader::{GetProcAddress, LoadLibraryW},
+    core::{s, PCSTR, PCWSTR},
+    Win32::{
+        Foundation::HMODULE,
+        System::{
+            LibraryLoader::{GetProcAddress, LoadLibraryExW, LoadLibraryW, LOAD_LIBRARY_FLAGS},
+            ProcessStatus::{GetModuleInformation, MODULEINFO},
+            Threading::GetCurrentProcess,
+        },
+    },
+};
+
+use crate::{
+    client::{TrySend as _, CLIENT},
+    fs_watch,
+    imports::order_by_imports,
+    report::{FileInfo, InjectReport},
+    script_extender,
+    staging::{stage_plugin, staging_dir},
+    utils::ThreadManager,
+    Plugin, LOADED_PLUGINS,
 };
 
-use crate::{utils::ThreadManager, Plugin, LOADED_PLUGINS};
+/// sha256 -> name of every plugin which was loaded, so plugins loaded later on aren't duplicates of them
+static LOADED_HASHES: LazyLock<Mutex<HashMap<String, String>>> = LazyLock::new(Mutex::default);
 
-pub fn load_plugins() -> Result<()> {
+/// quiet skips the `prompt_on_failure` prompt. force loads plugins regardless of `supported_game_versions`
+pub fn load_plugins(phase: LoadPhase, quiet: bool, force: bool) -> Result<()> {
     // # Safety
     // Any spawned threads MUST be joined. This is taken care of by ThreadManager,
     // but it is still an unsafe requirement that could be circumvented.
     // This function is safe because we upheld this requirement
 
-    let plugins_dir = get_bg3_plugins_dir()?;
     let config = get_config()?.get();
 
     if !config.core.enabled {
@@ -30,32 +71,235 @@ pub fn load_plugins() -> Result<()> {
         return Ok(());
     }
 
-    let read_dir = fs::read_dir(plugins_dir).context("failed to read plugins_dir {plugins_dir}");
-    let Ok(read_dir) = read_dir else {
-        error!(?read_dir, "failed to read plugins dir");
+    // each game exe may have its own plugins dir
+    let plugins_dir = {
+        let exe = env::current_exe()
+            .ok()
+            .and_then(|exe| exe.file_name().map(|n| n.to_string_lossy().into_owned()))
+            .unwrap_or_default();
 
-        warn_popup(
-            "Failed to read plugins dir",
-            "Attempted to read plugins dir, but failed opening it\n\nDo you have correct perms? See log for more details",
-        );
+        let dir = plugins_dir_for(config, &exe)?;
+        if dir != get_bg3_plugins_dir()? {
+            info!(%exe, dir = %dir.display(), "Loading plugins from the plugins dir for this exe");
+        }
 
+        dir
+    };
+
+    // the tools check this before loading, but autostart starts the game without them
+    let gated = !force && !in_launcher() && !config.core.supported_game_versions.is_empty();
+    if gated && !is_game_version_supported(config, phase) {
         return Ok(());
+    }
+
+    match phase {
+        LoadPhase::All => (),
+        LoadPhase::Reinject => info!("Loading the plugins which aren't loaded yet"),
+        _ => info!(?phase, "Loading plugins in phases"),
+    }
+
+    // --plugin replaces searching the plugins dir entirely. the tool doesn't reinject with it
+    let overrides = match phase {
+        LoadPhase::Reinject => None,
+        _ => read_plugin_overrides(phase),
     };
 
-    let mut m = ThreadManager::new();
+    let manifest = match get_manifest() {
+        Ok(manifest) => manifest,
+        Err(e) => {
+            error!(%e, "failed to read manifest");
 
-    for entry in read_dir {
-        let Ok(entry) = entry else {
-            warn!(?entry, "skipping unreadable dir entry");
-            continue;
-        };
+            warn_popup(
+                "Invalid manifest",
+                format!("`manifest.toml` in the plugins folder could not be read, so no plugins have been loaded. Please fix it, or remove it to load every plugin in the plugins folder.\n\nError: {e}"),
+            );
+
+            return Ok(());
+        }
+    };
+
+    let candidates = match (&overrides, manifest) {
+        (Some(paths), _) => {
+            info!(plugins = ?paths, "Loading only the plugins given with --plugin; the plugins dir is not searched");
+            paths.clone()
+        }
+
+        (None, Some(manifest)) => {
+            info!("Loading the plugins listed in manifest.toml");
+            manifest_candidates(manifest, &plugins_dir, phase)
+        }
 
-        let mut path = entry.path();
+        (None, None) => {
+            let Ok(paths) = list_plugin_files(config, &plugins_dir) else {
+                warn_popup(
+                    "Failed to read plugins dir",
+                    "Attempted to read plugins dir, but failed opening it\n\nDo you have correct perms? See log for more details",
+                );
+
+                return Ok(());
+            };
+
+            if config.core.recursive {
+                for path in &paths {
+                    let relative = path.strip_prefix(&plugins_dir).unwrap_or(path);
+                    info!(path = %relative.display(), "Found plugin");
+                }
+            }
+
+            paths
+        }
+    };
+
+    let overridden = overrides.is_some();
+
+    // reinjecting loads whatever isn't loaded yet, the same as plugins added while the game runs
+    let kind = match phase {
+        LoadPhase::Reinject => LoadKind::Live,
+        _ => LoadKind::Phase(phase),
+    };
+
+    let Some(report) = load_candidates(config, candidates, kind, overridden, quiet) else {
+        return Ok(());
+    };
+
+    // let yabg3nml know we're done
+    let last_phase = matches!(phase, LoadPhase::All | LoadPhase::Resumed);
+    _ = CLIENT.try_send(Receive::Loaded(report.summary(last_phase)).into());
+
+    // the resumed phase still has to load its plugins
+    if config.core.fs_watch && !matches!(phase, LoadPhase::Suspended | LoadPhase::Reinject) {
+        fs_watch::spawn(config, plugins_dir);
+    }
+
+    // the late plugins are loaded once every other phase is done
+    let has_late = !overridden && !config.core.late_plugins.is_empty();
+    if has_late && matches!(phase, LoadPhase::All | LoadPhase::Resumed) {
+        spawn_late(config, quiet, force);
+    }
+
+    Ok(())
+}
+
+/// Whether this is the launcher instead of the game, which it can be with inject_into_launcher
+fn in_launcher() -> bool {
+    env::current_exe().is_ok_and(|exe| {
+        exe.file_name()
+            .is_some_and(|n| n.to_string_lossy().eq_ignore_ascii_case(LAUNCHER_EXE))
+    })
+}
+
+/// The game's version. None in the launcher, or if it couldn't be detected
+fn game_version() -> Option<FileVersion> {
+    if in_launcher() {
+        return None;
+    }
+
+    // inside the game, this is the game's exe
+    env::current_exe()
+        .ok()
+        .and_then(VersionInfo::from_file)
+        .and_then(|v| v.fixed_version)
+}
+
+/// Whether the game's version is in `supported_game_versions`. The first phase shows a popup if it isn't
+fn is_game_version_supported(config: &Config, phase: LoadPhase) -> bool {
+    let Some(version) = game_version() else {
+        warn!("failed to detect game version; not loading plugins, since supported_game_versions is set");
+
+        if phase.is_first() {
+            warn_popup(
+                "Unknown game version",
+                "`supported_game_versions` is set in config.toml, but the game's version could not be detected, so no plugins have been loaded into it.\n\nTo load plugins anyways, start the tool with --force.",
+            );
+        }
+
+        return false;
+    };
+
+    if let Some(range) = config.core.supported_game_range(version) {
+        info!(%version, %range, "Game version is supported");
+        return true;
+    }
+
+    warn!(%version, supported = ?config.core.supported_game_versions, "game version is not in supported_game_versions; not loading plugins");
+
+    if phase.is_first() {
+        warn_popup(
+            "Unsupported game version",
+            format!("This game version ({version}) is not in `supported_game_versions` in config.toml, so no plugins have been loaded into it. The mod loader and your plugins have not been checked against this version, and may crash it.\n\nOnce you've checked that they work, add this version to `supported_game_versions`, or start the tool with --force to load plugins anyways."),
+        );
+    }
+
+    false
+}
+
+/// What plugins are being loaded for
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum LoadKind {
+    /// one of the load phases, when the loader starts
+    Phase(LoadPhase),
+    /// plugins which were added to the plugins dir while the game runs
+    Live,
+}
+
+impl LoadKind {
+    /// Whether problems with the plugins are reported. Every phase finds the same problems,
+    /// so only the first one reports them
+    fn reports_problems(self) -> bool {
+        match self {
+            Self::Phase(phase) => phase.is_first(),
+            Self::Live => true,
+        }
+    }
+}
+
+/// Load plugins which were added to the plugins dir while the game runs. They get the same checks, ordering,
+/// and staging as the plugins loaded when the game started. Returns None if loading was aborted
+pub fn load_live(config: &Config, candidates: Vec<PathBuf>) -> Option<InjectReport> {
+    // there's nobody to ask while the game runs
+    load_candidates(config, candidates, LoadKind::Live, false, true)
+}
+
+/// Check, order, and load `candidates`. `overridden` is whether they were given with --plugin, which
+/// skips the config's plugin lists. Returns None if loading was aborted
+fn load_candidates(
+    config: &Config,
+    candidates: Vec<PathBuf>,
+    kind: LoadKind,
+    overridden: bool,
+    quiet: bool,
+) -> Option<InjectReport> {
+    let in_launcher = in_launcher();
+    let host = if in_launcher { "launcher" } else { "game" };
+    let game_version = game_version();
+
+    match game_version {
+        Some(version) => info!(%version, "Detected game version"),
+        None if in_launcher => info!("Loading launcher plugins into the launcher"),
+        None => warn!("failed to detect game version; plugin game version checks are disabled"),
+    }
+
+    if config.core.stage_plugins {
+        info!(dir = %staging_dir().display(), "Staging plugins");
+    }
+
+    let mut plugins = Vec::new();
+    let mut incompatible = Vec::new();
+    // hash -> name_formatted of the first plugin found with it
+    // live loaded plugins are also checked against every plugin that was already loaded
+    let mut hashes = match kind {
+        LoadKind::Phase(_) => HashMap::new(),
+        LoadKind::Live => LOADED_HASHES.super_lock().clone(),
+    };
+    let mut duplicates = Vec::new();
+    let mut untrusted = Vec::new();
+
+    for mut path in candidates {
         // lowercase the path for comparisons
         path.as_mut_os_str().make_ascii_lowercase();
 
-        // not a file or dll
-        if !path.is_file() || path.extension().unwrap_or_default() != "dll" {
+        // not a file or plugin. plugins given with --plugin are used as-is
+        if !path.is_file() || !(overridden || config.core.has_plugin_extension(&path)) {
             continue;
         }
 
@@ -72,7 +316,16 @@ pub fn load_plugins() -> Result<()> {
             } else {
                 name
             }
-        };
+        }
+        .to_owned();
+
+        let file_name = path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned();
+
+        let version_info = VersionInfo::from_file(&path);
 
         let name_formatted = {
             let data = native_plugin_lib::get_plugin_data(&path);
@@ -90,49 +343,602 @@ pub fn load_plugins() -> Result<()> {
                     let p_name = data.name;
                     let author = data.author;
 
-                    format!("{p_name} by {author} v{major}.{minor}.{patch} ({name}.dll)")
+                    format!("{p_name} by {author} v{major}.{minor}.{patch} ({file_name})")
                 }
 
-                Err(_) => format!("{name}.dll"),
+                // not a native plugin, so try the dll's version resource instead
+                Err(_) => match version_info.as_ref() {
+                    Some(info) => {
+                        let p_name = info.product_name.as_deref().unwrap_or("unknown");
+                        let version = info.file_version.as_deref().unwrap_or("unknown");
+                        let author = info.company_name.as_deref().unwrap_or("unknown");
+
+                        format!("{p_name} v{version} by {author} ({file_name})")
+                    }
+
+                    None => format!("{file_name} (version info unknown)"),
+                },
             }
         };
 
-        if config.core.is_plugin_disabled(name) {
+        if !overridden && config.core.is_plugin_disabled(&name) {
             info!("Skipping disabled plugin {name_formatted}");
             continue;
         }
 
-        info!("Loading plugin {name_formatted}");
+        // the launcher and the game each only get their own plugins. without inject_into_launcher,
+        // nothing is loaded into the launcher, so the game gets every plugin
+        let split = config.core.inject_into_launcher && !overridden;
+        if split && config.core.is_launcher_plugin(&name) != in_launcher {
+            trace!(
+                host,
+                "Skipping {name_formatted}, since it belongs to the other target"
+            );
+            continue;
+        }
+
+        // plugins may declare which game versions they work on
+        let range = version_info
+            .as_ref()
+            .and_then(|v| v.game_versions.as_deref());
+
+        if let (Some(range), Some(game_version)) = (range, game_version) {
+            match VersionRange::parse(range) {
+                Some(range) if !range.contains(game_version) => {
+                    if config.core.ignore_version_checks {
+                        warn!(%range, %game_version, "Loading {name_formatted} even though it declares it's incompatible with this game version, since ignore_version_checks is enabled");
+                    } else {
+                        info!(%range, %game_version, "Skipping {name_formatted}, since it declares it's incompatible with this game version");
+                        incompatible.push(format!("{name_formatted} (supports {range})"));
+                        continue;
+                    }
+                }
+
+                Some(_) => (),
+
+                None => warn!(
+                    range,
+                    "{name_formatted} has an invalid game version range; loading it anyways"
+                ),
+            }
+        }
+
+        // read and hashed once, for dedupe_by_hash, staging, and the load report
+        let (data, file) = match read_file(&path) {
+            Ok((data, file)) => (Some(data), Some(file)),
 
-        // do not join the handle, or it will panic
-        // this is because we use ExitThread which yanks the thread out from
-        // underneath rust. it does not expect this
-        m.spawn({
-            let name = name.to_owned();
-            move || load_plugin(name, path)
+            Err(e) => {
+                warn!(%e, "failed to hash {name_formatted}; it can't be checked for duplicates");
+                (None, None)
+            }
+        };
+
+        // the same plugin twice under different names can crash the game
+        // this is done before phase filtering so both phases agree on which copy gets loaded
+        if let Some(file) = file.as_ref().filter(|_| config.core.dedupe_by_hash) {
+            if let Some(original) = duplicate_of(&mut hashes, file, &name_formatted) {
+                warn!(
+                    hash = file.sha256,
+                    "Skipping {name_formatted}, since it is a duplicate of {original}"
+                );
+                duplicates.push(format!("{name_formatted} (duplicate of {original})"));
+                continue;
+            }
+        }
+
+        // load from a copy, so the originals don't get locked while the game runs
+        // this is done before phase filtering, so every phase loads the same copy
+        let path = match (&data, &file) {
+            (Some(data), Some(file)) if config.core.stage_plugins => {
+                match stage_plugin(&path, data, &file.sha256) {
+                    Ok(staged) => staged,
+                    Err(e) => {
+                        warn!(%e, %name, "failed to stage plugin; loading it from the plugins dir instead");
+                        path
+                    }
+                }
+            }
+
+            _ if config.core.stage_plugins => {
+                warn!(%name, "plugin couldn't be read, so it can't be staged; loading it from the plugins dir instead");
+                path
+            }
+
+            _ => path,
+        };
+
+        // the file that gets loaded is the one that's checked. a staged copy is exactly what was read
+        // above, so the plugin can't be swapped out between checking and loading it
+        if config.core.require_signed {
+            if let Some(reason) = signature::untrusted_reason(&path, &config.core) {
+                warn!(%reason, "Skipping {name_formatted}, since require_signed is enabled and it failed signature verification");
+                untrusted.push(format!("{file_name}: {reason}"));
+                continue;
+            }
+        }
+
+        // the other phase takes care of this one. --plugin loads everything it lists right away,
+        // and so does live loading
+        if let LoadKind::Phase(phase) = kind {
+            let late = !overridden && config.core.is_plugin_late(&name);
+            let suspended = config.core.is_plugin_suspended(&name);
+            match phase {
+                LoadPhase::Late if !late => continue,
+                LoadPhase::Late => (),
+                _ if late => continue,
+                LoadPhase::Suspended if !suspended => continue,
+                LoadPhase::Resumed if suspended => continue,
+                _ => (),
+            }
+        }
+
+        plugins.push(DiscoveredPlugin {
+            name,
+            name_formatted,
+            imports: read_imports(&path),
+            path,
+            file,
         });
     }
 
-    Ok(())
+    // plugins which import from another plugin need that one to be loaded first
+    let mut plugins = order_by_imports(
+        plugins,
+        |p| {
+            p.path
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned()
+        },
+        |p| &p.imports,
+    );
+
+    // nearly everything depends on the Script Extender, so it has to be loaded before any other plugin
+    plugins.sort_by_key(|p| !script_extender::is_script_extender(&p.name));
+
+    let has_script_extender = plugins
+        .first()
+        .is_some_and(|p| script_extender::is_script_extender(&p.name));
+
+    if has_script_extender {
+        info!("Found the Script Extender; it will be loaded first");
+    } else if !script_extender::is_loaded() {
+        for plugin in plugins
+            .iter()
+            .filter(|p| script_extender::depends_on(&p.path))
+        {
+            warn!(
+                "{} appears to depend on the Script Extender, but it wasn't found. The plugin may fail to load",
+                plugin.name_formatted
+            );
+        }
+    }
+
+    // the first phase already showed this
+    if !incompatible.is_empty() && kind.reports_problems() {
+        let game_version = game_version.map(|v| v.to_string()).unwrap_or_default();
+
+        warn_popup(
+            "Incompatible plugins",
+            format!(
+                "Some plugins declare they are incompatible with this game version ({game_version}), and have been skipped:\n\n{}\n\nCheck for updated versions of these plugins. To load them anyways, set `ignore_version_checks` to true in config.toml.",
+                incompatible.join("\n")
+            ),
+        );
+    }
+
+    // the first phase already showed this
+    if !duplicates.is_empty() && kind.reports_problems() {
+        warn_popup(
+            "Duplicate plugins",
+            format!(
+                "Some plugins are identical copies of other plugins, and have been skipped:\n\n{}\n\nYou should remove the duplicates from the plugins folder.",
+                duplicates.join("\n")
+            ),
+        );
+    }
+
+    // the first phase already showed this
+    if !untrusted.is_empty() && kind.reports_problems() {
+        warn_popup(
+            "Untrusted plugins",
+            format!(
+                "`require_signed` is enabled in config.toml, but some plugins failed signature verification and have been skipped:\n\n{}",
+                untrusted.join("\n")
+            ),
+        );
+    }
+
+    // catch the case where plugins dir is full of things that aren't plugins,
+    // before we try to load every single one of them
+    if let Some(max) = config.core.max_plugins {
+        // live loaded plugins come on top of the ones which are already loaded
+        let already_loaded = match kind {
+            LoadKind::Phase(_) => 0,
+            LoadKind::Live => LOADED_PLUGINS.super_lock().len(),
+        };
+
+        if already_loaded + plugins.len() > max {
+            warn!(
+                found = already_loaded + plugins.len(),
+                max_plugins = max,
+                "plugin count exceeds max_plugins; aborting plugin load"
+            );
+
+            warn_popup(
+                "Too many plugins",
+                format!(
+                    "Found {} plugins, but `max_plugins` in config.toml is set to {max}. No plugins have been loaded.\n\nPlease double-check that your plugins folder only contains plugin dlls (and that it is the right folder). If you really have this many plugins, raise `max_plugins`.",
+                    already_loaded + plugins.len()
+                ),
+            );
+
+            return None;
+        }
+    }
+
+    let report = Arc::new(Mutex::new(InjectReport::default()));
+
+    // plugins are loaded in batches. every plugin in a batch loads concurrently, and
+    // each batch must fully finish before the next one starts. serial loading is a batch of 1
+    let batch_size = batch_size(config);
+
+    trace!(batch_size, "loading plugins");
+
+    match config.core.load_library {
+        LoadLibrary::LoadLibraryW => info!("Loading plugins with LoadLibraryW"),
+        LoadLibrary::LoadLibraryExW => info!(
+            flags = ?config.core.load_library_flags,
+            bits = %format!("0x{:X}", config.core.load_library_flag_bits()),
+            "Loading plugins with LoadLibraryExW"
+        ),
+    }
+
+    let load = {
+        let report = report.clone();
+        move |DiscoveredPlugin {
+                  name,
+                  name_formatted,
+                  path,
+                  file,
+                  ..
+              }| {
+            // plugins in a batch log over each other, and plugins' own logs end up in the same place,
+            // so every line about a plugin carries an id that stays the same across runs
+            let id = plugin_id(&name, file.as_ref());
+
+            info!(host, %id, "=== injecting {name_formatted} ===");
+
+            fs_watch::mark_injected(&name);
+
+            let start = Instant::now();
+            let result = load_plugin(&name, &path);
+
+            match &result {
+                Ok(()) => {
+                    info!(%id, took = ?start.elapsed(), "=== loaded {name_formatted} ===")
+                }
+                Err(e) => error!(%id, %e, "=== failed to load {name_formatted} ==="),
+            }
+
+            report.super_lock().push(name, path, file, result);
+        }
+    };
+
+    let prompt_on_failure = config.core.prompt_on_failure && !quiet;
+    // results before this were already asked about
+    let mut checked = 0;
+    let proceed = || {
+        if !prompt_on_failure {
+            return true;
+        }
+
+        let failed = {
+            let report = report.super_lock();
+            let failed = report.results[checked..]
+                .iter()
+                .filter_map(|r| r.error.as_ref().map(|e| format!("{}: {e}", r.name)))
+                .collect::<Vec<_>>();
+            checked = report.results.len();
+            failed
+        };
+
+        if failed.is_empty() {
+            return true;
+        }
+
+        confirm_popup(
+            "Plugin failed to load",
+            format!(
+                "Some plugins failed to load:\n\n{}\n\nDo you want to continue loading the remaining plugins?",
+                failed.join("\n")
+            ),
+            true,
+        )
+    };
+
+    load_in_batches(plugins, batch_size, load, proceed);
+
+    let report = mem::take(&mut *report.super_lock());
+    report.log();
+
+    // so plugins added later on can be checked for being duplicates of these
+    let mut loaded_hashes = LOADED_HASHES.super_lock();
+    for result in report.results.iter().filter(|r| r.error.is_none()) {
+        if let Some(file) = &result.file {
+            loaded_hashes
+                .entry(file.sha256.clone())
+                .or_insert_with(|| result.name.clone());
+        }
+    }
+
+    Some(report)
+}
+
+/// Load `late_plugins` on a thread of its own, once `late_plugin_delay` passed
+fn spawn_late(config: &Config, quiet: bool, force: bool) {
+    let delay = Duration::from_millis(config.core.late_plugin_delay);
+    info!(?delay, plugins = ?config.core.late_plugins, "Loaded plugins; late plugins will be loaded after a delay");
+
+    thread::spawn(move || {
+        thread::sleep(delay);
+
+        info!("Loading late plugins");
+        if let Err(e) = load_plugins(LoadPhase::Late, quiet, force) {
+            error!(%e, "failed to load late plugins");
+        }
+    });
+}
+
+/// The enabled plugins in the manifest, in the order they're listed. Listed plugins which don't exist
+/// and plugins which aren't listed are reported
+fn manifest_candidates(manifest: &Manifest, plugins_dir: &Path, phase: LoadPhase) -> Vec<PathBuf> {
+    let config = get_config().map(|c| c.get());
+
+    let mut missing = Vec::new();
+    let mut candidates = Vec::new();
+
+    for plugin in &manifest.plugins {
+        let path = plugins_dir.join(&plugin.file);
+
+        if !plugin.enabled {
+            info!(file = %plugin.file.display(), "Skipping plugin disabled in manifest.toml");
+            continue;
+        }
+
+        if !path.is_file() {
+            warn!(path = %path.display(), "plugin listed in manifest.toml does not exist");
+            missing.push(plugin.file.display().to_string());
+            continue;
+        }
+
+        candidates.push(path);
+    }
+
+    if let Ok(paths) = config.and_then(|config| list_plugin_files(config, plugins_dir)) {
+        let unlisted = paths.iter().filter(|p| {
+            !manifest.plugins.iter().any(|m| {
+                let listed = plugins_dir.join(&m.file);
+                UniCase::new(listed.to_string_lossy()) == UniCase::new(p.to_string_lossy())
+            })
+        });
+
+        for path in unlisted {
+            warn!(path = %path.display(), "plugin is not listed in manifest.toml, so it won't be loaded");
+        }
+    }
+
+    // the first phase already showed this
+    if !missing.is_empty() && phase.is_first() {
+        warn_popup(
+            "Missing plugins",
+            format!(
+                "Some plugins listed in `manifest.toml` don't exist in the plugins folder, and have been skipped:\n\n{}",
+                missing.join("\n")
+            ),
+        );
+    }
+
+    candidates
 }
 
-fn load_plugin(name: String, path: PathBuf) {
+/// The plugins given with `--plugin` for this process, if any
+fn read_plugin_overrides(phase: LoadPhase) -> Option<Vec<PathBuf>> {
+    let created = match process_creation_time(unsafe { GetCurrentProcess() }) {
+        Ok(created) => created,
+        Err(e) => {
+            warn!(%e, "failed to get process creation time; searching the plugins dir instead of using a --plugin list");
+            return None;
+        }
+    };
+
+    let path = get_plugin_override_path(process::id(), created).ok()?;
+
+    let list = match fs::read_to_string(&path) {
+        Ok(list) => list,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return None,
+        Err(e) => {
+            warn!(%e, path = %path.display(), "failed to read --plugin list; searching the plugins dir instead");
+            return None;
+        }
+    };
+
+    // the resumed phase still needs it
+    if phase != LoadPhase::Suspended {
+        if let Err(e) = fs::remove_file(&path) {
+            warn!(%e, path = %path.display(), "failed to remove --plugin list");
+        }
+    }
+
+    let paths = list
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty())
+        .map(PathBuf::from)
+        .collect();
+
+    Some(paths)
+}
+
+struct DiscoveredPlugin {
+    name: String,
+    name_formatted: String,
+    path: PathBuf,
+    /// The dll names this plugin imports from
+    imports: Vec<String>,
+    /// None if the file couldn't be read
+    file: Option<FileInfo>,
+}
+
+impl DiscoveredPlugin {
+    /// Whether this plugin imports from the dll at `path`
+    fn imports_from(&self, path: &Path) -> bool {
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+        let file_name = UniCase::new(file_name.as_ref());
+
+        self.imports
+            .iter()
+            .any(|i| UniCase::new(i.as_str()) == file_name)
+    }
+}
+
+/// A plugin's contents, and its size and hash
+fn read_file(path: &Path) -> io::Result<(Vec<u8>, FileInfo)> {
+    let data = fs::read(path)?;
+    let file = FileInfo {
+        size: data.len() as u64,
+        sha256: sha256::digest(&data),
+    };
+
+    Ok((data, file))
+}
+
+/// The name of the plugin in `hashes` which `file` is identical to. If there is none,
+/// `file` is added to `hashes` as `name`
+fn duplicate_of(
+    hashes: &mut HashMap<String, String>,
+    file: &FileInfo,
+    name: &str,
+) -> Option<String> {
+    match hashes.entry(file.sha256.clone()) {
+        hash_map::Entry::Occupied(original) => Some(original.get().clone()),
+        hash_map::Entry::Vacant(entry) => {
+            entry.insert(name.to_owned());
+            None
+        }
+    }
+}
+
+/// The most plugins loaded at once
+fn batch_size(config: &Config) -> usize {
+    if config.core.parallel_inject {
+        thread::available_parallelism()
+            .map_or(4, NonZeroUsize::get)
+            .min(config.core.max_concurrent_injections)
+            .max(1)
+    } else {
+        1
+    }
+}
+
+/// Load `plugins` with `load`, in batches of at most `batch_size`. Every plugin in a batch loads concurrently,
+/// and each batch must fully finish before the next one starts. Between batches, stops if `proceed` returns false
+fn load_in_batches(
+    plugins: Vec<DiscoveredPlugin>,
+    batch_size: usize,
+    load: impl Fn(DiscoveredPlugin) + Clone + Send + 'static,
+    mut proceed: impl FnMut() -> bool,
+) {
+    let mut plugins = plugins.into_iter().peekable();
+    while plugins.peek().is_some() {
+        let mut m = ThreadManager::new();
+
+        for plugin in next_batch(&mut plugins, batch_size) {
+            let load = load.clone();
+            // do not join the handle, or it will panic
+            // this is because we use ExitThread which yanks the thread out from
+            // underneath rust. it does not expect this
+            m.spawn(move || load(plugin));
+        }
+
+        // waits for the whole batch to finish
+        drop(m);
+
+        if plugins.peek().is_some() && !proceed() {
+            warn!(
+                remaining = plugins.len(),
+                "Not loading the remaining plugins, since loading was aborted after a failure"
+            );
+            break;
+        }
+    }
+}
+
+/// The next plugins to load at once, at most `batch_size` of them
+fn next_batch(
+    plugins: &mut Peekable<impl Iterator<Item = DiscoveredPlugin>>,
+    batch_size: usize,
+) -> Vec<DiscoveredPlugin> {
+    // the Script Extender must be fully loaded before anything else starts loading
+    let batch_size = match plugins.peek() {
+        Some(p) if script_extender::is_script_extender(&p.name) => 1,
+        _ => batch_size,
+    };
+
+    // a plugin can't load in the same batch as a plugin it imports from
+    let mut batch = Vec::new();
+    while batch.len() < batch_size {
+        let Some(next) = plugins.next_if(|p| {
+            !batch
+                .iter()
+                .any(|b: &DiscoveredPlugin| p.imports_from(&b.path))
+        }) else {
+            break;
+        };
+
+        batch.push(next);
+    }
+
+    batch
+}
+
+/// A short id for a plugin, to tell its log lines apart. The start of its sha256,
+/// or its name if it couldn't be hashed
+fn plugin_id(name: &str, file: Option<&FileInfo>) -> String {
+    match file {
+        Some(file) => file.sha256.chars().take(8).collect(),
+        None => name.to_owned(),
+    }
+}
+
+pub fn load_plugin(name: &str, path: &Path) -> Result<()> {
     // wrap this in try{} block and return result
     // by doing this we can return the self library guard and
     // prevent a shutdown until the end of this scope
     //
     // The purpose of doing that so we can
     let result = tri! {
-        let plugin_path = path
+        // plugins in deeply nested folders can go over MAX_PATH
+        let plugin_path = to_extended_path(path);
+        let plugin_path = plugin_path
             .as_os_str()
             .encode_wide()
             .chain(iter::once(0))
             .collect::<Vec<_>>();
 
+        let config = get_config()?.get();
+
         // SAFETY: Standard function, and our string is formatted properly
         let main_module = {
             let path = PCWSTR::from_raw(plugin_path.as_ptr());
-            let res = unsafe { LoadLibraryW(path) };
+            let res = match config.core.load_library {
+                LoadLibrary::LoadLibraryW => unsafe { LoadLibraryW(path) },
+                LoadLibrary::LoadLibraryExW => {
+                    let flags = LOAD_LIBRARY_FLAGS(config.core.load_library_flag_bits());
+                    unsafe { LoadLibraryExW(path, None, flags) }
+                }
+            };
 
             match res {
                 Ok(v) => v,
@@ -167,12 +973,289 @@ fn load_plugin(name: String, path: PathBuf) {
             trace!(%name, "finished Init");
         }
 
+        // the manifest's options take precedence over config.toml's
+        let manifest = get_manifest().ok().flatten();
+        let export = manifest
+            .and_then(|m| m.plugin(name))
+            .and_then(|p| p.options.call_after_load.as_deref())
+            .or_else(|| config.plugin(name).and_then(|p| p.call_after_load.as_deref()));
+
+        if let Some(export) = export {
+            call_export(name, main_module, export);
+        }
+
         Ok::<_, Report>(())
     };
 
-    if let Err(e) = result {
+    if let Err(e) = &result {
         error!(%name, path = %path.display(), %e, "load_plugin failed");
     }
 
     trace!(%name, "exit load plugin");
+
+    result
+}
+
+/// Call a plugin's `call_after_load` export. A missing export is only logged, since the plugin already loaded
+///
+/// This runs on the plugin's load thread after LoadLibrary returned, so the loader lock is not held
+fn call_export(name: &str, module: HMODULE, export: &str) {
+    let entry = match resolve_export(module, export) {
+        Ok(entry) => entry,
+
+        Err(ExportError::Missing) => {
+            warn!(%name, export, "plugin has no such export to call after load; skipping it");
+            return;
+        }
+
+        Err(ExportError::Forwarded) => {
+            warn!(%name, export, "plugin's export is forwarded to another module; only a plugin's own export is called after load, so skipping it");
+            return;
+        }
+    };
+
+    info!(%name, export, "Calling export after load");
+
+    // SAFETY: Guaranteed by implementer to not be UB
+    unsafe {
+        entry();
+    }
+
+    trace!(%name, export, "finished export");
+}
+
+type Entry = unsafe extern "C" fn();
+
+#[derive(Debug, PartialEq)]
+enum ExportError {
+    Missing,
+    Forwarded,
+}
+
+/// Find `export` in `module`'s own image. Other modules exporting the same name are never found
+fn resolve_export(module: HMODULE, export: &str) -> Result<Entry, ExportError> {
+    // validated with the config, so this can't contain a nul
+    let c_export = CString::new(export).map_err(|_| ExportError::Missing)?;
+
+    // SAFETY: Standard function, and the string is nul terminated
+    let addr = unsafe { GetProcAddress(module, PCSTR::from_raw(c_export.as_ptr().cast())) };
+    let addr = addr.ok_or(ExportError::Missing)?;
+
+    // GetProcAddress follows forwarded exports into other modules, which could be another plugin
+    // exporting the same name. only this plugin's own export may be called
+    if !is_in_module(module, addr as usize) {
+        return Err(ExportError::Forwarded);
+    }
+
+    type FarProc = unsafe extern "system" fn() -> isize;
+
+    // SAFETY: The export is declared by the user to be `unsafe extern "C" fn()`. The plugin must abide by this
+    Ok(unsafe { mem::transmute::<FarProc, Entry>(addr) })
+}
+
+/// Whether `addr` is inside of `module`'s image
+fn is_in_module(module: HMODULE, addr: usize) -> bool {
+    let mut info = MODULEINFO::default();
+
+    // SAFETY: Standard function, and info is the size we pass
+    let res = unsafe {
+        GetModuleInformation(
+            GetCurrentProcess(),
+            module,
+            &mut info,
+            size_of::<MODULEINFO>() as u32,
+        )
+    };
+
+    if let Err(e) = res {
+        // can't tell, but it came from this module's export table
+        warn!(%e, "failed to get module information");
+        return true;
+    }
+
+    let base = info.lpBaseOfDll as usize;
+    (base..base + info.SizeOfImage as usize).contains(&addr)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use tempfile::tempdir;
+    use windows::{core::w, Win32::System::LibraryLoader::GetModuleHandleW};
+
+    use super::*;
+
+    fn plugin(name: &str, imports: &[&str]) -> DiscoveredPlugin {
+        DiscoveredPlugin {
+            name: name.to_owned(),
+            name_formatted: name.to_owned(),
+            path: PathBuf::from(format!("{name}.dll")),
+            imports: imports.iter().map(|i| i.to_string()).collect(),
+            file: None,
+        }
+    }
+
+    fn batch_names(
+        plugins: &mut Peekable<impl Iterator<Item = DiscoveredPlugin>>,
+        batch_size: usize,
+    ) -> Vec<String> {
+        next_batch(plugins, batch_size)
+            .into_iter()
+            .map(|p| p.name)
+            .collect()
+    }
+
+    #[test]
+    fn identical_plugins_are_duplicates() {
+        let tmp = tempdir().unwrap();
+        let dir = tmp.path();
+
+        fs::write(dir.join("Plugin.dll"), b"plugin").unwrap();
+        fs::write(dir.join("Plugin - Copy.dll"), b"plugin").unwrap();
+        fs::write(dir.join("Other.dll"), b"other").unwrap();
+
+        let mut hashes = HashMap::new();
+        let mut check = |name: &str| {
+            let (_, file) = read_file(&dir.join(name)).unwrap();
+            duplicate_of(&mut hashes, &file, name)
+        };
+
+        assert_eq!(check("Plugin.dll"), None);
+        assert_eq!(check("Other.dll"), None);
+        assert_eq!(check("Plugin - Copy.dll").as_deref(), Some("Plugin.dll"));
+        // the first one found stays the original
+        assert_eq!(check("Plugin.dll").as_deref(), Some("Plugin.dll"));
+    }
+
+    #[test]
+    fn no_more_than_max_loads_in_flight() {
+        const MAX: usize = 3;
+
+        let mut config = Config::default();
+        config.core.parallel_inject = true;
+        config.core.max_concurrent_injections = MAX;
+
+        let batch_size = batch_size(&config);
+        assert!((1..=MAX).contains(&batch_size));
+
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let most = Arc::new(AtomicUsize::new(0));
+        let loaded = Arc::new(AtomicUsize::new(0));
+
+        let plugins = (0..20)
+            .map(|i| plugin(&format!("Plugin{i}"), &[]))
+            .collect();
+
+        let load = {
+            let (in_flight, most, loaded) = (in_flight.clone(), most.clone(), loaded.clone());
+            move |_: DiscoveredPlugin| {
+                let now = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                most.fetch_max(now, Ordering::SeqCst);
+                thread::sleep(Duration::from_millis(20));
+                in_flight.fetch_sub(1, Ordering::SeqCst);
+                loaded.fetch_add(1, Ordering::SeqCst);
+            }
+        };
+
+        load_in_batches(plugins, batch_size, load, || true);
+
+        assert_eq!(loaded.load(Ordering::SeqCst), 20);
+        assert!(most.load(Ordering::SeqCst) <= MAX);
+    }
+
+    #[test]
+    fn serial_loading_is_one_at_a_time() {
+        let mut config = Config::default();
+        config.core.parallel_inject = false;
+        config.core.max_concurrent_injections = 8;
+
+        assert_eq!(batch_size(&config), 1);
+    }
+
+    #[test]
+    fn batches_stop_at_barriers() {
+        let mut plugins = vec![
+            plugin("BG3ScriptExtender", &[]),
+            plugin("A", &[]),
+            plugin("B", &[]),
+            plugin("C", &["a.dll"]),
+            plugin("D", &[]),
+        ]
+        .into_iter()
+        .peekable();
+
+        // the Script Extender loads alone
+        assert_eq!(batch_names(&mut plugins, 4), ["BG3ScriptExtender"]);
+        // C imports from A, so waits for it
+        assert_eq!(batch_names(&mut plugins, 4), ["A", "B"]);
+        assert_eq!(batch_names(&mut plugins, 4), ["C", "D"]);
+        assert!(batch_names(&mut plugins, 4).is_empty());
+    }
+
+    fn module(name: PCWSTR) -> HMODULE {
+        unsafe { LoadLibraryW(name).unwrap() }
+    }
+
+    #[test]
+    fn module_range_contains_only_its_own_code() {
+        let exe = unsafe { GetModuleHandleW(None).unwrap() };
+        let ntdll = module(w!("ntdll.dll"));
+
+        let own = module_range_contains_only_its_own_code as usize;
+        assert!(is_in_module(exe, own));
+        assert!(!is_in_module(ntdll, own));
+
+        // the image starts at its base, and ends before base + size
+        let mut info = MODULEINFO::default();
+        unsafe {
+            GetModuleInformation(
+                GetCurrentProcess(),
+                exe,
+                &mut info,
+                size_of::<MODULEINFO>() as u32,
+            )
+            .unwrap();
+        }
+
+        let base = info.lpBaseOfDll as usize;
+        let end = base + info.SizeOfImage as usize;
+        assert!(is_in_module(exe, base));
+        assert!(is_in_module(exe, end - 1));
+        assert!(!is_in_module(exe, end));
+        assert!(!is_in_module(exe, base - 1));
+    }
+
+    #[test]
+    fn same_export_resolves_to_each_modules_own() {
+        // both export their own strlen
+        let ntdll = module(w!("ntdll.dll"));
+        let msvcrt = module(w!("msvcrt.dll"));
+
+        let a = resolve_export(ntdll, "strlen").unwrap() as usize;
+        let b = resolve_export(msvcrt, "strlen").unwrap() as usize;
+
+        assert_ne!(a, b);
+        assert!(is_in_module(ntdll, a));
+        assert!(is_in_module(msvcrt, b));
+    }
+
+    #[test]
+    fn forwarded_export_is_not_resolved() {
+        // forwarded to ntdll's RtlAllocateHeap
+        let kernel32 = module(w!("kernel32.dll"));
+        assert_eq!(
+            resolve_export(kernel32, "HeapAlloc").err(),
+            Some(ExportError::Forwarded)
+        );
+    }
+
+    #[test]
+    fn missing_export_is_not_resolved() {
+        let ntdll = module(w!("ntdll.dll"));
+        assert_eq!(
+            resolve_export(ntdll, "NoSuchExport").err(),
+            Some(ExportError::Missing)
+        );
+    }
 }
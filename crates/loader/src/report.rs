@@ -0,0 +1,101 @@
+use std::path::PathBuf;
+
+use eyre::Result;
+use shared::pipe::commands::LoadedMsg;
+use tracing::{info, warn};
+
+/// The per-plugin results of a plugin load
+#[derive(Debug, Default)]
+pub struct InjectReport {
+    pub results: Vec<PluginResult>,
+}
+
+#[derive(Debug)]
+pub struct PluginResult {
+    pub name: String,
+    pub path: PathBuf,
+    /// None if the file couldn't be read
+    pub file: Option<FileInfo>,
+    /// None if it succeeded
+    pub error: Option<String>,
+}
+
+/// What a plugin's file was when it was found, to tell apart setups which look the same
+#[derive(Debug, Clone)]
+pub struct FileInfo {
+    pub size: u64,
+    pub sha256: String,
+}
+
+impl InjectReport {
+    pub fn push(
+        &mut self,
+        name: String,
+        path: PathBuf,
+        file: Option<FileInfo>,
+        result: Result<()>,
+    ) {
+        self.results.push(PluginResult {
+            name,
+            path,
+            file,
+            error: result.err().map(|e| format!("{e:#}")),
+        });
+    }
+
+    /// `last_phase` is whether this is the last phase which runs when the game starts
+    pub fn summary(&self, last_phase: bool) -> LoadedMsg {
+        let failed = self.results.iter().filter(|r| r.error.is_some()).count();
+
+        let names = self
+            .results
+            .iter()
+            .filter(|r| r.error.is_none())
+            .map(|r| r.name.clone())
+            .collect();
+
+        LoadedMsg {
+            loaded: self.results.len() - failed,
+            failed,
+            names,
+            last_phase,
+        }
+    }
+
+    /// Log a summary of the results
+    pub fn log(&self) {
+        let failed = self
+            .results
+            .iter()
+            .filter(|r| r.error.is_some())
+            .collect::<Vec<_>>();
+
+        // one line per plugin, in load order
+        for PluginResult {
+            path, file, error, ..
+        } in &self.results
+        {
+            let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+            let size = file.as_ref().map(|f| f.size);
+            let sha256 = file.as_ref().map_or("unknown", |f| f.sha256.as_str());
+            let result = if error.is_some() { "failed" } else { "loaded" };
+
+            info!(file = %file_name, size, sha256, result, "plugin");
+        }
+
+        for PluginResult {
+            name, path, error, ..
+        } in &failed
+        {
+            let error = error.as_deref().unwrap_or_default();
+            warn!(%name, path = %path.display(), %error, "plugin failed to load");
+        }
+
+        info!(
+            total = self.results.len(),
+            loaded = self.results.len() - failed.len(),
+            failed = failed.len(),
+            "finished loading plugins"
+        );
+    }
+}
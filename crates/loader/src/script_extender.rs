@@ -0,0 +1,50 @@
+use std::{fs, path::Path};
+
+use pelite::pe::{Pe as _, PeFile};
+use tracing::trace;
+use unicase::UniCase;
+use windows::{core::w, Win32::System::LibraryLoader::GetModuleHandleW};
+
+/// The filename of the Script Extender's dll, without extension
+const NAME: &str = "BG3ScriptExtender";
+const DLL_NAME: &str = "BG3ScriptExtender.dll";
+
+/// Whether a plugin is the Script Extender, by its filename without extension
+pub fn is_script_extender(name: &str) -> bool {
+    UniCase::new(name) == UniCase::new(NAME)
+}
+
+/// Whether the Script Extender was already loaded into the game some other way,
+/// e.g. through its DWrite.dll proxy in the game's bin folder
+pub fn is_loaded() -> bool {
+    unsafe { GetModuleHandleW(w!("BG3ScriptExtender.dll")) }.is_ok()
+}
+
+/// Heuristic check for a plugin needing the Script Extender, by it importing from the Script Extender's dll.
+/// Plugins which resolve it at runtime instead can't be detected
+pub fn depends_on(path: &Path) -> bool {
+    let Ok(data) = fs::read(path) else {
+        return false;
+    };
+
+    let Ok(file) = PeFile::from_bytes(&data) else {
+        return false;
+    };
+
+    let Ok(imports) = file.imports() else {
+        return false;
+    };
+
+    let dll_name = UniCase::new(DLL_NAME);
+
+    let depends = imports.iter().any(|desc| {
+        desc.dll_name()
+            .ok()
+            .and_then(|name| name.to_str().ok())
+            .is_some_and(|name| UniCase::new(name) == dll_name)
+    });
+
+    trace!(path = %path.display(), depends, "checked plugin imports for the Script Extender");
+
+    depends
+}
@@ -0,0 +1,179 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process,
+    sync::Once,
+};
+
+use eyre::{OptionExt as _, Result};
+use shared::{paths::get_staging_dir, utils::OwnedHandle};
+use tracing::{trace, warn};
+use windows::Win32::{
+    Foundation::STILL_ACTIVE,
+    System::Threading::{GetExitCodeProcess, OpenProcess, PROCESS_QUERY_LIMITED_INFORMATION},
+};
+
+static CLEANED: Once = Once::new();
+
+/// Staging dir for this game process
+pub fn staging_dir() -> PathBuf {
+    get_staging_dir().join(process::id().to_string())
+}
+
+/// Copy a plugin into the staging dir, returning the path of the copy. `data` is the plugin's contents,
+/// as they were read when it was found, and `sha256` is their hash
+///
+/// Copies are stored as `<staging_dir>/<hash>/<filename>`, so identical plugins are only copied once,
+/// and the plugin still keeps its original filename. Copies are read-only
+pub fn stage_plugin(path: &Path, data: &[u8], sha256: &str) -> Result<PathBuf> {
+    // a game's copies can't be removed while it runs, and removing them while it exits would mean file io under
+    // the loader lock. so they're removed once a later game stages its plugins
+    CLEANED.call_once(|| remove_stale(&get_staging_dir()));
+
+    let staged = stage_in(&staging_dir(), path, data, sha256)?;
+
+    trace!(from = %path.display(), to = %staged.display(), "staged plugin");
+
+    Ok(staged)
+}
+
+fn stage_in(staging_dir: &Path, path: &Path, data: &[u8], sha256: &str) -> Result<PathBuf> {
+    let file_name = path.file_name().ok_or_eyre("plugin has no filename")?;
+
+    let dir = staging_dir.join(sha256);
+    let staged = dir.join(file_name);
+
+    // an existing copy is only reused if it still is exactly what was read, since the copy is what gets
+    // checked and loaded
+    if fs::read(&staged).is_ok_and(|existing| existing == data) {
+        return Ok(staged);
+    }
+
+    fs::create_dir_all(&dir)?;
+
+    // a tampered copy is read-only too
+    if let Ok(metadata) = fs::metadata(&staged) {
+        set_readonly(&staged, metadata.permissions(), false)?;
+    }
+
+    fs::write(&staged, data)?;
+    set_readonly(&staged, fs::metadata(&staged)?.permissions(), true)?;
+
+    Ok(staged)
+}
+
+// on windows, this only sets or clears the file's read-only attribute
+#[allow(clippy::permissions_set_readonly_false)]
+fn set_readonly(path: &Path, mut permissions: fs::Permissions, readonly: bool) -> Result<()> {
+    permissions.set_readonly(readonly);
+    fs::set_permissions(path, permissions)?;
+    Ok(())
+}
+
+/// Remove the staging dirs in `dir` of game processes which aren't running anymore
+fn remove_stale(dir: &Path) {
+    let Ok(read_dir) = fs::read_dir(dir) else {
+        return;
+    };
+
+    for entry in read_dir.flatten() {
+        let name = entry.file_name();
+        let Some(pid) = name.to_str().and_then(|name| name.parse::<u32>().ok()) else {
+            continue;
+        };
+
+        if pid == process::id() || is_running(pid) {
+            continue;
+        }
+
+        let path = entry.path();
+        match fs::remove_dir_all(&path) {
+            Ok(()) => trace!(dir = %path.display(), "removed staging dir of exited game"),
+            Err(e) => warn!(%e, dir = %path.display(), "failed to clean up staging dir"),
+        }
+    }
+}
+
+fn is_running(pid: u32) -> bool {
+    let Ok(process) = (unsafe { OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, false, pid) })
+    else {
+        return false;
+    };
+
+    let process = OwnedHandle::new(process);
+
+    let mut code = 0u32;
+    unsafe { GetExitCodeProcess(process.as_raw_handle(), &mut code) }
+        .is_ok_and(|_| code == STILL_ACTIVE.0 as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn staged_copy_is_what_was_read() {
+        let tmp = tempdir().unwrap();
+        let dir = tmp.path();
+        let original = dir.join("Plugin.dll");
+
+        // the original changes after it was read and checked
+        fs::write(&original, b"changed").unwrap();
+
+        let staged = stage_in(&dir.join("staging"), &original, b"read", "hash").unwrap();
+        assert_eq!(fs::read(&staged).unwrap(), b"read");
+        assert_eq!(staged.file_name(), original.file_name());
+    }
+
+    #[test]
+    fn tampered_copy_is_replaced() {
+        let tmp = tempdir().unwrap();
+        let dir = tmp.path();
+        let original = dir.join("Plugin.dll");
+        let staging = dir.join("staging");
+
+        let staged = stage_in(&staging, &original, b"read", "hash").unwrap();
+        set_readonly(&staged, fs::metadata(&staged).unwrap().permissions(), false).unwrap();
+        fs::write(&staged, b"tampered").unwrap();
+        set_readonly(&staged, fs::metadata(&staged).unwrap().permissions(), true).unwrap();
+
+        let staged = stage_in(&staging, &original, b"read", "hash").unwrap();
+        assert_eq!(fs::read(&staged).unwrap(), b"read");
+    }
+
+    #[test]
+    fn staged_copy_is_read_only() {
+        let tmp = tempdir().unwrap();
+        let dir = tmp.path();
+        let original = dir.join("Plugin.dll");
+
+        let staged = stage_in(&dir.join("staging"), &original, b"read", "hash").unwrap();
+        assert!(fs::metadata(&staged).unwrap().permissions().readonly());
+        assert!(fs::write(&staged, b"tampered").is_err());
+    }
+
+    #[test]
+    fn removes_only_exited_games_dirs() {
+        let tmp = tempdir().unwrap();
+        let dir = tmp.path();
+        let staging = dir.join("staging");
+
+        let own = staging.join(process::id().to_string());
+        // pids are multiples of 4, so this one never runs
+        let exited = staging.join("4294967295");
+        let other = staging.join("not-a-pid");
+
+        stage_in(&exited, &dir.join("Plugin.dll"), b"read", "hash").unwrap();
+        for dir in [&own, &other] {
+            fs::create_dir_all(dir).unwrap();
+        }
+
+        remove_stale(&staging);
+
+        assert!(own.exists());
+        assert!(other.exists());
+        assert!(!exited.exists());
+    }
+}
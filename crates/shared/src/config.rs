@@ -1,18 +1,170 @@
-use std::path::PathBuf;
-use std::{fs, sync::LazyLock};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::{
+    env,
+    ffi::OsString,
+    fmt::Display,
+    fs, io,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        LazyLock, PoisonError, RwLock,
+    },
+    time::Duration,
+};
 
-use eyre::{Report, Result};
+use eyre::{eyre, Report, Result};
 use serde::{Deserialize, Serialize};
-use tracing::error;
+use tracing::{error, info, warn};
 use unicase::UniCase;
+use windows::Win32::System::LibraryLoader::{
+    LOAD_LIBRARY_SEARCH_APPLICATION_DIR, LOAD_LIBRARY_SEARCH_DEFAULT_DIRS,
+    LOAD_LIBRARY_SEARCH_DLL_LOAD_DIR, LOAD_LIBRARY_SEARCH_SYSTEM32, LOAD_LIBRARY_SEARCH_USER_DIRS,
+    LOAD_WITH_ALTERED_SEARCH_PATH,
+};
 
-use crate::paths::get_bg3_plugins_dir;
+use crate::{
+    paths::{bg3_plugins_dir_path, get_bg3_plugins_dir},
+    utils::glob_match,
+    version_info::{FileVersion, VersionRange},
+};
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 #[serde(default)]
 pub struct Config {
     pub core: Core,
     pub log: Log,
+    /// Settings for individual plugins, keyed by the plugins filename without extension (case-insensitive)
+    /// e.g. [plugins.FooBar]
+    pub plugins: HashMap<String, PluginConfig>,
+    /// Keys in config.toml which don't match any option, e.g. `core.instal_root`
+    #[serde(skip)]
+    pub unknown_keys: Vec<String>,
+    /// Options which were overridden by environment variables, e.g. `YABG3NML_INSTALL_ROOT`
+    #[serde(skip)]
+    pub env_overrides: Vec<String>,
+}
+
+impl Config {
+    /// Check for option values and combinations which can't work together
+    ///
+    /// All problems are reported at once, so they can be fixed in one go
+    pub fn validate(&self) -> Result<()> {
+        let mut errors = Vec::new();
+
+        if self.core.extensions.is_empty() {
+            errors.push("`extensions` is empty, so no plugins can ever be found".to_owned());
+        }
+
+        for ext in &self.core.extensions {
+            let trimmed = ext.trim_start_matches('.');
+            if trimmed.is_empty() || trimmed.contains(['.', '/', '\\']) {
+                errors.push(format!(
+                    "`extensions` entry \"{ext}\" is not a valid file extension; it must not be empty or contain `.`, `/`, or `\\`"
+                ));
+            }
+        }
+
+        if self.core.max_plugins == Some(0) {
+            errors.push("`max_plugins` is 0, so loading would always be aborted. To stop loading plugins, set `enabled` to false instead".to_owned());
+        }
+
+        if self.core.max_write_size == 0 {
+            errors
+                .push("`max_write_size` is 0, so nothing can be written into the game".to_owned());
+        }
+
+        for range in &self.core.supported_game_versions {
+            if VersionRange::parse(range).is_none() {
+                errors.push(format!(
+                    "`supported_game_versions` has an invalid version range \"{range}\""
+                ));
+            }
+        }
+
+        for flag in &self.core.load_library_flags {
+            if load_library_flag(flag).is_none() {
+                errors.push(format!(
+                    "`load_library_flags` has an unknown flag \"{flag}\""
+                ));
+            }
+        }
+
+        let altered = self
+            .core
+            .load_library_flags
+            .iter()
+            .any(|f| f == "LOAD_WITH_ALTERED_SEARCH_PATH");
+        let search = self
+            .core
+            .load_library_flags
+            .iter()
+            .any(|f| f.starts_with("LOAD_LIBRARY_SEARCH_"));
+
+        if altered && search {
+            errors.push("`load_library_flags` can't combine LOAD_WITH_ALTERED_SEARCH_PATH with the LOAD_LIBRARY_SEARCH_ flags".to_owned());
+        }
+
+        if self.core.max_concurrent_injections == 0 {
+            errors.push("`max_concurrent_injections` must be at least 1".to_owned());
+        }
+
+        for (exe, dir) in &self.core.target_plugin_dirs {
+            if dir.as_os_str().is_empty() {
+                errors.push(format!("`target_plugin_dirs` entry \"{exe}\" is empty"));
+            }
+        }
+
+        for (exe, interval) in &self.core.poll_intervals {
+            if *interval == 0 {
+                errors.push(format!("`poll_intervals` entry \"{exe}\" is 0"));
+            }
+        }
+
+        for (name, plugin) in &self.plugins {
+            if let Some(export) = &plugin.call_after_load {
+                if export.trim().is_empty() || export.contains('\0') {
+                    errors.push(format!(
+                        "`plugins.{name}.call_after_load` \"{export}\" is not a valid export name"
+                    ));
+                }
+            }
+        }
+
+        if matches!(&self.core.wait_strategy, WaitStrategy::Module(name) if name.trim().is_empty())
+        {
+            errors.push("`wait_strategy` module name is empty".to_owned());
+        }
+
+        if errors.is_empty() {
+            return Ok(());
+        }
+
+        let errors = errors
+            .iter()
+            .map(|e| format!("- {e}"))
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        Err(eyre!("{errors}"))
+    }
+
+    /// Settings for a plugin, by the plugins filename without extension
+    pub fn plugin(&self, name: &str) -> Option<&PluginConfig> {
+        let name = UniCase::new(name);
+        self.plugins
+            .iter()
+            .find(|(n, _)| UniCase::new(n.as_str()) == name)
+            .map(|(_, p)| p)
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PluginConfig {
+    /// An export to call once the plugin was loaded (and its `Init` export, if any, was called).
+    /// For plugins which do their setup outside of DllMain, to avoid running under the loader lock
+    /// e.g. "Initialize"
+    pub call_after_load: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -31,6 +183,192 @@ pub struct Core {
     pub disabled_plugins: Vec<String>,
     /// Whether to show cli window
     pub cli: bool,
+    /// Whether unknown keys in config.toml (e.g. a typo like `instal_root`) are an error.
+    /// If false, they're only logged as warnings and ignored
+    pub strict_config: bool,
+    /// How long (in ms) to wait for the game exes to appear in `install_root`, e.g. while Steam is updating
+    /// the game. Only waits if the game's bin folder exists. 0 doesn't wait
+    pub game_binary_timeout: u64,
+    /// Which winapi is used to start the remote threads in the game process
+    /// "createremotethread" (default) or "ntcreatethreadex"
+    /// ntcreatethreadex sometimes works in cases where CreateRemoteThread is hooked/blocked
+    pub injection_trigger: InjectionTrigger,
+    /// Which winapi loads each plugin inside of the game
+    /// "loadlibraryw" (default) or "loadlibraryexw", which also passes `load_library_flags`
+    /// e.g. loadlibraryexw with ["LOAD_WITH_ALTERED_SEARCH_PATH"] lets plugins find dlls next to themselves
+    pub load_library: LoadLibrary,
+    /// The flags passed to LoadLibraryExW when `load_library` is "loadlibraryexw", by their winapi names,
+    /// e.g. ["LOAD_LIBRARY_SEARCH_DLL_LOAD_DIR", "LOAD_LIBRARY_SEARCH_DEFAULT_DIRS"].
+    /// LOAD_WITH_ALTERED_SEARCH_PATH can't be combined with the LOAD_LIBRARY_SEARCH_ flags
+    pub load_library_flags: Vec<String>,
+    /// Path to a loader dll to use instead of the loader.dll shipped next to the tools, for development or forks.
+    /// It skips the hash check loader.dll gets, so only set this to a dll you trust. In portable mode, it must be
+    /// next to the exe. `--loader` overrides it.
+    /// Unset uses the shipped loader.dll
+    pub loader_path: Option<PathBuf>,
+    /// Whether to restrict loader.dll's permissions so only the current user and administrators can change it,
+    /// so other users on a shared machine can't swap it out. Everyone can still read it. Failing to set them is
+    /// only logged. Skipped when running as the service. Turning this off doesn't undo it; see the readme.
+    /// Doesn't apply to `loader_path`
+    pub secure_loader_acl: bool,
+    /// Path to a test dll to load into the first game that's found, after the plugins, to check that loading
+    /// still works, e.g. after a game patch. The result is logged. The dll must export
+    /// `extern "system" fn SelfTest(*mut c_void) -> u32` returning 0x59414247; see the readme.
+    /// Unset means disabled. Only used by the watcher and injector tools
+    pub selftest_dll: Option<PathBuf>,
+    /// Whether to also detect the game by its command line mentioning the game's Steam or GOG AppID,
+    /// for when the game exe was renamed. Only processes inside of `install_root` are checked.
+    /// Only used by the watcher and injector tools
+    pub match_command_line: bool,
+    /// Whether to raise this tool's process and thread priority while it loads plugins into the game, and
+    /// restore it afterwards. Can make injection faster on a busy system
+    pub inject_priority_boost: bool,
+    /// Whether to load plugins into every running instance of the game. If false, new instances are
+    /// left alone while an injected one is still running. Only used by the watcher tool
+    pub inject_all_instances: bool,
+    /// Whether to recognize the Larian launcher, so it's never mistaken for the game (e.g. with `match_command_line`),
+    /// and to log when the game it starts is found. Only used by the watcher and injector tools
+    pub follow_launcher: bool,
+    /// How often (in ms) to check for each game exe, keyed by the exe filename (case-insensitive),
+    /// e.g. { "bg3_dx11.exe" = 500, "bg3.exe" = 5000 }. Exes which aren't listed use the default rate.
+    /// Only used by the watcher tool
+    pub poll_intervals: HashMap<String, u64>,
+    /// A separate plugins dir for each game exe, keyed by the exe filename (case-insensitive), for using
+    /// different plugins with each renderer, e.g. { "bg3.exe" = "vulkan", "bg3_dx11.exe" = "dx11" }.
+    /// Relative dirs are inside of the plugins dir. Exes which aren't listed use the plugins dir.
+    /// config.toml and manifest.toml are always read from the plugins dir
+    pub target_plugin_dirs: HashMap<String, PathBuf>,
+    /// Up to how many ms to randomly add to or subtract from each poll interval, so polling doesn't
+    /// happen in a fixed pattern. 0 disables it. Only used by the watcher and injector tools
+    pub poll_jitter: u64,
+    /// The max amount of plugins allowed to be loaded. If more plugins than this are
+    /// found, loading is aborted. Useful to catch the plugins folder having unrelated dlls in it.
+    /// Unset means there is no limit
+    pub max_plugins: Option<usize>,
+    /// The largest amount of bytes allowed to be written into the game process at once, as a sanity check
+    /// against something going wrong. Only raise this if the log says a write was refused
+    pub max_write_size: usize,
+    /// When to inject after the game process was found. Only used by the watcher and injector tools
+    /// "immediate" (default) injects right away
+    /// { module = "name.dll" } waits until the game has loaded that module
+    /// { delay = 5000 } waits a fixed amount of milliseconds
+    pub wait_strategy: WaitStrategy,
+    /// Whether to also wait for the game to reach its main menu before injecting, after `wait_strategy`.
+    /// This is a heuristic: the game is treated as being at the main menu once it has a visible window, and
+    /// it stopped loading modules for `main_menu_settle` ms. Only used by the watcher and injector tools
+    pub wait_for_main_menu: bool,
+    /// How long (in ms) the game must not load any modules for, before it's treated as being at the main menu.
+    /// Raise this if plugins still get loaded too early on slow machines
+    pub main_menu_settle: u64,
+    /// The longest (in ms) to wait for the main menu before injecting anyways
+    pub main_menu_timeout: u64,
+    /// Whether to ask if the remaining plugins should still be loaded, when a plugin fails to load.
+    /// If false, loading continues, and the failure is logged. Never asked with --quiet
+    pub prompt_on_failure: bool,
+    /// Whether to load multiple plugins at the same time. This is faster for large plugin lists,
+    /// but the order plugins get loaded in is no longer deterministic
+    pub parallel_inject: bool,
+    /// The most plugins loaded at the same time with `parallel_inject`, so large plugin lists don't start too many
+    /// threads in the game at once. Plugins still wait for the Script Extender and for plugins they import from,
+    /// so fewer may load at once. Must be at least 1
+    pub max_concurrent_injections: usize,
+    /// Which file extensions are treated as plugins (case-insensitive; no leading dot)
+    /// e.g. ["dll", "asi"]
+    pub extensions: Vec<String>,
+    /// Whether plugins in subfolders of the plugins folder are loaded too. If false, only plugins directly
+    /// inside of it are. Doesn't apply to plugins listed in `manifest.toml`, which may be in any subfolder
+    pub recursive: bool,
+    /// Whether to copy plugins to a temp staging folder and load them from there instead.
+    /// This stops the game from locking the original files, so they can be edited while the game runs.
+    /// The read-only copies are removed once the game exited, the next time plugins are staged
+    pub stage_plugins: bool,
+    /// Whether to skip plugins which are identical (by content) to another plugin that was already found.
+    /// Loading the same plugin twice under different filenames can crash the game
+    pub dedupe_by_hash: bool,
+    /// Whether to watch the plugins folder while the game runs, and load plugins which are added to
+    /// it right away. Meant for plugin development. Plugins which were already loaded are not reloaded
+    pub fs_watch: bool,
+    /// Whether to load plugins even when they declare they're incompatible with the current game version
+    pub ignore_version_checks: bool,
+    /// Whether to only load plugins which have a valid Authenticode signature
+    pub require_signed: bool,
+    /// Which signers are trusted when `require_signed` is enabled (case-insensitive),
+    /// e.g. ["Cherry"]. Empty means any valid signature is accepted
+    pub trusted_publishers: Vec<String>,
+    /// Whether to check the game exe's Authenticode signature before loading plugins into it, so a fake or
+    /// tampered game is never loaded into. Repacked installs may fail this. Only used by the watcher and injector tools
+    pub verify_game_signature: bool,
+    /// Which game versions plugins may be loaded into, as version ranges like in a plugin's `BG3GameVersions`,
+    /// e.g. ["4.1.1.5000-4.1.1.6999", "4.2-"]. Other versions aren't loaded into unless the tool is started with
+    /// --force, so a major game patch can't be crashed by plugins that weren't updated for it yet.
+    /// Empty allows every version. The tools check this before loading into the game, and the loader checks it
+    /// again inside of it, which covers the autostart tool
+    pub supported_game_versions: Vec<String>,
+    /// Whether to also load plugins into the Larian launcher, for overlay and UI mods which belong there.
+    /// Only the plugins in `launcher_plugins` are loaded into it. Only used by the watcher tool
+    pub inject_into_launcher: bool,
+    /// Which plugins are loaded into the launcher instead of the game, when `inject_into_launcher` is enabled.
+    /// Each entry is the plugins filename without extension, like `disabled_plugins`
+    pub launcher_plugins: Vec<String>,
+    /// Which plugins must be loaded before the game's main thread starts running.
+    /// Each entry is the plugins filename without extension, like `disabled_plugins`
+    /// This only has an effect with the autostart tool, since it's the only one that launches the game itself.
+    /// Other tools load these like any other plugin
+    pub suspended_plugins: Vec<String>,
+    /// Like `suspended_plugins`, but glob patterns matched against the plugins filename without extension,
+    /// e.g. ["*Core*", "ScriptExtender*"]. Matching is case-insensitive; `*` matches any amount of characters
+    /// and `?` matches exactly one
+    pub inject_suspended_patterns: Vec<String>,
+    /// Which plugins are loaded `late_plugin_delay` ms after the other plugins were loaded, for plugins
+    /// which need the game to have settled first. These are never loaded while the game is suspended.
+    /// Each entry is the plugins filename without extension, like `disabled_plugins`
+    pub late_plugins: Vec<String>,
+    /// How long (in ms) after the other plugins were loaded to load `late_plugins`
+    pub late_plugin_delay: u64,
+    /// Whether the game should be closed when the autostart tool exits, for launchers that treat
+    /// the autostart tool as the game's lifetime. Only has an effect with the autostart tool
+    pub kill_game_with_loader: bool,
+    /// Whether the game keeps running without plugins when the autostart tool fails to load them into it.
+    /// If false, the autostart tool shows an error and exits, so a broken setup is never missed.
+    /// With `kill_game_with_loader`, exiting also closes the game. Only has an effect with the autostart tool
+    pub autostart_allow_unmodded: bool,
+    /// The working directory the autostart tool starts the game in. Unset uses the folder of the game's exe,
+    /// which is what the game expects. Only has an effect with the autostart tool
+    pub game_working_dir: Option<PathBuf>,
+    /// Extra arguments passed to the game when the watcher tool launches it with `--launch`
+    /// e.g. ["--skip-launcher"]
+    pub extra_game_args: Vec<String>,
+    /// Whether to show a popup listing the loaded plugins, the first time plugins were loaded into the game.
+    /// Only used by the watcher tool, and never shown with --quiet
+    pub confirm_inject: bool,
+    /// Url to POST a small json body to once plugins were loaded into the game, for automation
+    /// e.g. {"pid":1234,"plugins":5,"failed":0,"timestamp":1700000000}
+    /// Posted once per game, and not if no plugins loaded. Unset means disabled
+    pub on_inject_url: Option<String>,
+    /// Whether to write a minidump of the game to the logs dir, when it crashes shortly after plugins were loaded
+    /// into it. After every injection, the game is kept attached to as a debugger for 60 seconds, so no other
+    /// debugger can attach to it during that time, and this does nothing if one already is. Dumps are written to
+    /// the same dir as the log files. Dumps can be large, so this is off by default. Only used by the watcher,
+    /// injector, and autostart tools
+    pub capture_minidump: bool,
+    /// Whether to keep watching the game after plugins were loaded into it, and warn when one of them gets
+    /// unloaded again, e.g. because another mod forced it out. Only used by the watcher and autostart tools,
+    /// since the injector tool exits right after loading
+    pub watch_unloads: bool,
+    /// Whether to also write injection failures to the Windows Application event log,
+    /// for monitoring managed machines
+    pub windows_event_log: bool,
+    /// Whether to show the tray icon. Without it, the watcher tool can be stopped with Ctrl+C in the terminal
+    /// it was started from, or with the `stop` command of the control pipe
+    pub show_tray: bool,
+    /// Whether to only show the tray icon once plugins were loaded into the game, as a sign that it worked.
+    /// Until then, the watcher tool runs without a tray, and can be stopped with Ctrl+C in the terminal it was
+    /// started from, or with the `stop` command of the control pipe. Only used by the watcher tool
+    pub tray_on_inject: bool,
+    /// Custom title to show in the tray tooltip and about dialog. Unset uses the default title
+    pub tray_title: Option<String>,
+    /// Path to a custom .ico file to use for the tray icon. Unset (or an invalid icon) uses the default icon
+    pub tray_icon_path: Option<PathBuf>,
 }
 
 impl Default for Core {
@@ -41,6 +379,60 @@ impl Default for Core {
             install_root: r"C:\Program Files (x86)\Steam\steamapps\common\Baldurs Gate 3".into(),
             disabled_plugins: Vec::new(),
             cli: false,
+            game_binary_timeout: 0,
+            injection_trigger: InjectionTrigger::default(),
+            load_library: LoadLibrary::default(),
+            load_library_flags: Vec::new(),
+            loader_path: None,
+            secure_loader_acl: false,
+            selftest_dll: None,
+            match_command_line: false,
+            inject_priority_boost: false,
+            inject_all_instances: true,
+            follow_launcher: true,
+            poll_intervals: HashMap::new(),
+            target_plugin_dirs: HashMap::new(),
+            poll_jitter: 0,
+            max_plugins: None,
+            // 4 MiB; far more than anything written today
+            max_write_size: 4 * 1024 * 1024,
+            wait_strategy: WaitStrategy::default(),
+            wait_for_main_menu: false,
+            main_menu_settle: 5000,
+            main_menu_timeout: 120_000,
+            prompt_on_failure: false,
+            parallel_inject: false,
+            max_concurrent_injections: 4,
+            extensions: vec!["dll".into()],
+            recursive: false,
+            stage_plugins: false,
+            dedupe_by_hash: true,
+            fs_watch: false,
+            ignore_version_checks: false,
+            require_signed: false,
+            trusted_publishers: Vec::new(),
+            verify_game_signature: false,
+            supported_game_versions: Vec::new(),
+            inject_into_launcher: false,
+            launcher_plugins: Vec::new(),
+            suspended_plugins: Vec::new(),
+            inject_suspended_patterns: Vec::new(),
+            late_plugins: Vec::new(),
+            late_plugin_delay: 30_000,
+            kill_game_with_loader: false,
+            autostart_allow_unmodded: false,
+            game_working_dir: None,
+            extra_game_args: Vec::new(),
+            confirm_inject: false,
+            on_inject_url: None,
+            capture_minidump: false,
+            watch_unloads: false,
+            windows_event_log: false,
+            strict_config: false,
+            show_tray: true,
+            tray_on_inject: false,
+            tray_title: None,
+            tray_icon_path: None,
         }
     }
 }
@@ -52,6 +444,167 @@ impl Core {
             .iter()
             .any(|p| UniCase::new(p) == name)
     }
+
+    /// The configured poll interval for a game exe, by its filename
+    pub fn poll_interval(&self, exe: &str) -> Option<Duration> {
+        let exe = UniCase::new(exe);
+        self.poll_intervals
+            .iter()
+            .find(|(e, _)| UniCase::new(e.as_str()) == exe)
+            .map(|(_, ms)| Duration::from_millis(*ms))
+    }
+
+    /// The range in `supported_game_versions` which `version` is in, if any
+    pub fn supported_game_range(&self, version: FileVersion) -> Option<VersionRange> {
+        // validated with the config, so every range parses
+        self.supported_game_versions
+            .iter()
+            .filter_map(|r| VersionRange::parse(r))
+            .find(|r| r.contains(version))
+    }
+
+    /// The dir to load plugins from for a game exe, by its filename. `plugins_dir` unless it's in `target_plugin_dirs`
+    pub fn target_plugin_dir(&self, plugins_dir: &Path, exe: &str) -> PathBuf {
+        let exe = UniCase::new(exe);
+        self.target_plugin_dirs
+            .iter()
+            .find(|(e, _)| UniCase::new(e.as_str()) == exe)
+            // an absolute dir replaces plugins_dir
+            .map_or_else(|| plugins_dir.to_owned(), |(_, dir)| plugins_dir.join(dir))
+    }
+
+    pub fn is_plugin_suspended(&self, name: &str) -> bool {
+        let uni_name = UniCase::new(name);
+        let listed = self
+            .suspended_plugins
+            .iter()
+            .any(|p| UniCase::new(p) == uni_name);
+
+        listed
+            || self
+                .inject_suspended_patterns
+                .iter()
+                .any(|p| glob_match(p, name))
+    }
+
+    /// `load_library_flags` combined. Unknown flags fail validation, so they're left out
+    pub fn load_library_flag_bits(&self) -> u32 {
+        self.load_library_flags
+            .iter()
+            .filter_map(|f| load_library_flag(f))
+            .fold(0, |bits, flag| bits | flag)
+    }
+
+    pub fn is_plugin_late(&self, name: &str) -> bool {
+        let name = UniCase::new(name);
+        self.late_plugins.iter().any(|p| UniCase::new(p) == name)
+    }
+
+    pub fn is_launcher_plugin(&self, name: &str) -> bool {
+        let name = UniCase::new(name);
+        self.launcher_plugins
+            .iter()
+            .any(|p| UniCase::new(p) == name)
+    }
+
+    /// Whether any plugins may need to be loaded while the game is suspended
+    pub fn has_suspended_plugins(&self) -> bool {
+        !self.suspended_plugins.is_empty() || !self.inject_suspended_patterns.is_empty()
+    }
+
+    pub fn is_publisher_trusted(&self, name: &str) -> bool {
+        if self.trusted_publishers.is_empty() {
+            return true;
+        }
+
+        let name = UniCase::new(name);
+        self.trusted_publishers
+            .iter()
+            .any(|p| UniCase::new(p.trim()) == name)
+    }
+
+    pub fn has_plugin_extension<P: AsRef<Path>>(&self, path: P) -> bool {
+        let Some(ext) = path.as_ref().extension() else {
+            return false;
+        };
+
+        let ext = UniCase::new(ext.to_string_lossy());
+        self.extensions
+            .iter()
+            .any(|e| UniCase::new(e.trim_start_matches('.')) == ext)
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum InjectionTrigger {
+    #[default]
+    CreateRemoteThread,
+    NtCreateThreadEx,
+}
+
+impl Display for InjectionTrigger {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let trigger = match self {
+            Self::CreateRemoteThread => "CreateRemoteThread",
+            Self::NtCreateThreadEx => "NtCreateThreadEx",
+        };
+
+        write!(f, "{trigger}")
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LoadLibrary {
+    #[default]
+    LoadLibraryW,
+    LoadLibraryExW,
+}
+
+impl Display for LoadLibrary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let function = match self {
+            Self::LoadLibraryW => "LoadLibraryW",
+            Self::LoadLibraryExW => "LoadLibraryExW",
+        };
+
+        write!(f, "{function}")
+    }
+}
+
+/// The value of a LoadLibraryExW flag, by its winapi name
+pub fn load_library_flag(name: &str) -> Option<u32> {
+    let flag = match name {
+        "LOAD_WITH_ALTERED_SEARCH_PATH" => LOAD_WITH_ALTERED_SEARCH_PATH,
+        "LOAD_LIBRARY_SEARCH_DLL_LOAD_DIR" => LOAD_LIBRARY_SEARCH_DLL_LOAD_DIR,
+        "LOAD_LIBRARY_SEARCH_APPLICATION_DIR" => LOAD_LIBRARY_SEARCH_APPLICATION_DIR,
+        "LOAD_LIBRARY_SEARCH_DEFAULT_DIRS" => LOAD_LIBRARY_SEARCH_DEFAULT_DIRS,
+        "LOAD_LIBRARY_SEARCH_SYSTEM32" => LOAD_LIBRARY_SEARCH_SYSTEM32,
+        "LOAD_LIBRARY_SEARCH_USER_DIRS" => LOAD_LIBRARY_SEARCH_USER_DIRS,
+        _ => return None,
+    };
+
+    Some(flag.0)
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WaitStrategy {
+    #[default]
+    Immediate,
+    Module(String),
+    Delay(u64),
+}
+
+impl Display for WaitStrategy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Immediate => write!(f, "immediate"),
+            Self::Module(name) => write!(f, "module ({name})"),
+            Self::Delay(ms) => write!(f, "delay ({ms}ms)"),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -61,6 +614,17 @@ pub struct Log {
     pub level: String,
     /// whether to display log targets
     pub target: bool,
+    /// the log filter used for the console (with `cli`), e.g. "info,yabg3nml=trace". Unset uses `level`.
+    /// The env var YABG3NML_LOG still takes precedence
+    pub console_log_filter: Option<String>,
+    /// whether the console (with `cli`) is switched to UTF-8, so non-ASCII paths aren't garbled.
+    /// Turn off if it causes issues with your console
+    pub console_utf8: bool,
+    /// whether each run gets its own log file, named with the time it started and its pid,
+    /// instead of sharing a daily log file
+    pub per_run_log: bool,
+    /// how many days to keep per-run log files for; 0 keeps them forever
+    pub max_log_days: u64,
 }
 
 impl Default for Log {
@@ -68,6 +632,10 @@ impl Default for Log {
         Self {
             level: "info".into(),
             target: Default::default(),
+            console_log_filter: None,
+            console_utf8: true,
+            per_run_log: false,
+            max_log_days: 7,
         }
     }
 }
@@ -85,47 +653,479 @@ impl ConfigState {
     }
 }
 
+/// Read config.toml from disk again. This does not change the config returned by [`get_config`]
+pub fn reload_config() -> Result<Config> {
+    let path = get_bg3_plugins_dir()?.join("config.toml");
+    let config = fs::read_to_string(path)?;
+
+    parse(&config)
+}
+
+/// Read config.toml without creating or writing anything, not even the plugins dir. The defaults if it doesn't exist
+pub fn peek_config() -> Result<Config> {
+    let path = bg3_plugins_dir_path()?.join("config.toml");
+
+    match fs::read_to_string(path) {
+        Ok(config) => parse(&config),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Config::default()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// The config from the last [`apply_config`]
+static APPLIED: RwLock<Option<&'static Config>> = RwLock::new(None);
+
+/// Make [`current_config`] return `config` from now on, e.g. after [`reload_config`]. The previous config is
+/// leaked, since it may still be borrowed; configs are only reloaded on request, so this stays small
+pub fn apply_config(config: Config) {
+    let config = Box::leak(Box::new(config));
+    *APPLIED.write().unwrap_or_else(PoisonError::into_inner) = Some(config);
+}
+
+/// The config from the last [`apply_config`], or the one from [`get_config`] if there wasn't one
+pub fn current_config() -> Result<&'static Config> {
+    let applied = *APPLIED.read().unwrap_or_else(PoisonError::into_inner);
+
+    match applied {
+        Some(config) => Ok(config),
+        None => Ok(get_config()?.get()),
+    }
+}
+
+/// Where the last known good config is kept
+pub fn config_backup_path() -> Result<PathBuf> {
+    Ok(get_bg3_plugins_dir()?.join("config.toml.bak"))
+}
+
+static WRITEBACK: AtomicBool = AtomicBool::new(true);
+
+/// Never write config.toml or its backup, e.g. when it's managed elsewhere. Must be called before [`get_config`]
+pub fn disable_config_writeback() {
+    WRITEBACK.store(false, Ordering::Relaxed);
+}
+
+/// Whether config.toml and its backup may be written; false after [`disable_config_writeback`]
+pub fn is_config_writeback_enabled() -> bool {
+    WRITEBACK.load(Ordering::Relaxed)
+}
+
+/// Save config.toml as the last known good config, replacing the previous backup
+///
+/// Only call this once the config loaded and validated successfully
+pub fn backup_config() -> Result<()> {
+    let path = get_bg3_plugins_dir()?.join("config.toml");
+    let backup = config_backup_path()?;
+
+    backup_config_to(&path, &backup, is_config_writeback_enabled())
+}
+
+fn backup_config_to(path: &Path, backup: &Path, writeback: bool) -> Result<()> {
+    if !writeback {
+        return Ok(());
+    }
+
+    // a read-only config is managed by something else, which is responsible for it
+    if fs::metadata(path).is_ok_and(|m| m.permissions().readonly()) {
+        info!("config.toml is read-only; not backing it up");
+        return Ok(());
+    }
+
+    let config = fs::read(path)?;
+
+    // skip needless writes on every start
+    if fs::read(backup).is_ok_and(|b| b == config) {
+        return Ok(());
+    }
+
+    fs::write(backup, config)?;
+
+    info!(backup = %backup.display(), "Backed up config");
+
+    Ok(())
+}
+
+/// Replace config.toml with the last known good config
+pub fn restore_config() -> Result<()> {
+    let path = get_bg3_plugins_dir()?.join("config.toml");
+    let backup = config_backup_path()?;
+
+    if !is_config_writeback_enabled() {
+        return Err(eyre!("config writeback is disabled"));
+    }
+
+    if !backup.is_file() {
+        return Err(eyre!(
+            "there is no config backup at {}. A backup is made every time the config loads successfully",
+            backup.display()
+        ));
+    }
+
+    fs::copy(&backup, &path)?;
+
+    info!(from = %backup.display(), to = %path.display(), "Restored config from backup");
+
+    Ok(())
+}
+
+/// Deserialize a config, keeping track of any keys which were ignored
+fn parse(config: &str) -> Result<Config> {
+    parse_with_env(config, |name| env::var_os(name))
+}
+
+/// [`parse`], with `var` looking up environment variables
+fn parse_with_env(config: &str, var: impl Fn(&str) -> Option<OsString>) -> Result<Config> {
+    let mut unknown_keys = Vec::new();
+
+    let deserializer = toml::Deserializer::new(config);
+    let mut config: Config = serde_ignored::deserialize(deserializer, |path| {
+        unknown_keys.push(path.to_string());
+    })?;
+
+    if config.core.strict_config && !unknown_keys.is_empty() {
+        let keys = unknown_keys
+            .iter()
+            .map(|k| format!("- `{k}`"))
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        return Err(eyre!("unknown keys (`strict_config` is enabled):\n{keys}"));
+    }
+
+    config.unknown_keys = unknown_keys;
+
+    apply_env(&mut config, var);
+
+    Ok(config)
+}
+
+/// Override options with environment variables, for automated setups where config.toml can't easily be edited.
+/// These take precedence over config.toml, which takes precedence over the defaults
+///
+/// - `YABG3NML_INSTALL_ROOT` sets `install_root`
+fn apply_env(config: &mut Config, var: impl Fn(&str) -> Option<OsString>) {
+    if let Some(root) = var("YABG3NML_INSTALL_ROOT").filter(|r| !r.is_empty()) {
+        config.core.install_root = root.into();
+        config
+            .env_overrides
+            .push("YABG3NML_INSTALL_ROOT (install_root)".to_owned());
+    }
+}
+
+/// Whether `YABG3NML_QUIET` is set to `1` or `true`, which acts like --quiet. For automated setups which can't
+/// pass arguments
+pub fn quiet_from_env() -> bool {
+    is_enabled(env::var_os("YABG3NML_QUIET"))
+}
+
+fn is_enabled(value: Option<OsString>) -> bool {
+    value.is_some_and(|v| v == "1" || v.eq_ignore_ascii_case("true"))
+}
+
 pub fn get_config() -> Result<&'static ConfigState> {
     static CONFIG: LazyLock<Result<ConfigState>> = LazyLock::new(|| {
         let path = get_bg3_plugins_dir()?.join("config.toml");
+        load_config(&path, is_config_writeback_enabled())
+    });
+
+    CONFIG.as_ref().map_err(|e| Report::new(&**e))
+}
+
+/// Read the config at `path`. If it's missing, the default config is written there, unless `writeback` is false
+fn load_config(path: &Path, writeback: bool) -> Result<ConfigState> {
+    let mut new = false;
+    if !path.exists() {
+        // nothing was created, so there's nothing for the user to set up either
+        if !writeback {
+            warn!("config.toml is missing, and config writeback is disabled; using the default config");
+            return Ok(ConfigState::Exists(Config::default()));
+        }
 
-        let mut new = false;
-        if !path.exists() {
-            let json = toml::to_string_pretty(&Config::default())?;
+        let json = toml::to_string_pretty(&Config::default())?;
 
-            if let Err(e) = fs::write(&path, json) {
+        match fs::write(path, json) {
+            Ok(_) => new = true,
+
+            // e.g. the plugins folder is synced from a read-only share
+            Err(e) if e.kind() == io::ErrorKind::PermissionDenied => {
+                warn!(%e, "config.toml is missing and can't be written; using the default config");
+                return Ok(ConfigState::Exists(Config::default()));
+            }
+
+            Err(e) => {
                 error!("failed to save config: {e}");
                 return Err(e.into());
             }
+        }
+    }
 
-            new = true;
+    let config = match fs::read_to_string(path) {
+        Ok(v) => v,
+        Err(e) => {
+            error!("failed to read config: {e}");
+            return Err(e.into());
         }
+    };
 
-        let config = match fs::read_to_string(path) {
-            Ok(v) => v,
-            Err(e) => {
-                error!("failed to read config: {e}");
-                return Err(e.into());
-            }
-        };
+    match parse(&config) {
+        Ok(v) => {
+            let state = if new {
+                ConfigState::New(v)
+            } else {
+                ConfigState::Exists(v)
+            };
 
-        match toml::from_str::<Config>(&config) {
-            Ok(v) => {
-                let state = if new {
-                    ConfigState::New(v)
-                } else {
-                    ConfigState::Exists(v)
-                };
+            Ok(state)
+        }
 
-                Ok(state)
-            }
+        Err(e) => {
+            error!("failed to deserialize config: {e}");
+            Err(e)
+        }
+    }
+}
 
-            Err(e) => {
-                error!("failed to deserialize config: {e}");
-                Err(e.into())
-            }
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn missing_config_is_not_written_without_writeback() {
+        let tmp = tempdir().unwrap();
+        let dir = tmp.path();
+        let path = dir.join("config.toml");
+
+        let state = load_config(&path, false).unwrap();
+
+        assert!(matches!(state, ConfigState::Exists(_)));
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn missing_config_is_written_with_writeback() {
+        let tmp = tempdir().unwrap();
+        let dir = tmp.path();
+        let path = dir.join("config.toml");
+
+        let state = load_config(&path, true).unwrap();
+
+        assert!(matches!(state, ConfigState::New(_)));
+        assert!(path.is_file());
+    }
+
+    #[test]
+    fn read_only_config_is_not_backed_up() {
+        let tmp = tempdir().unwrap();
+        let dir = tmp.path();
+        let path = dir.join("config.toml");
+        let backup = dir.join("config.toml.bak");
+
+        fs::write(&path, "[core]\n").unwrap();
+        let mut perms = fs::metadata(&path).unwrap().permissions();
+        perms.set_readonly(true);
+        fs::set_permissions(&path, perms).unwrap();
+
+        // still loads
+        assert!(load_config(&path, true).is_ok());
+
+        backup_config_to(&path, &backup, true).unwrap();
+        assert!(!backup.exists());
+
+        // so the dir can be removed again
+        let mut perms = fs::metadata(&path).unwrap().permissions();
+        #[allow(clippy::permissions_set_readonly_false)]
+        perms.set_readonly(false);
+        fs::set_permissions(&path, perms).unwrap();
+    }
+
+    #[test]
+    fn config_is_not_backed_up_without_writeback() {
+        let tmp = tempdir().unwrap();
+        let dir = tmp.path();
+        let path = dir.join("config.toml");
+        let backup = dir.join("config.toml.bak");
+
+        fs::write(&path, "[core]\n").unwrap();
+
+        backup_config_to(&path, &backup, false).unwrap();
+        assert!(!backup.exists());
+
+        backup_config_to(&path, &backup, true).unwrap();
+        assert!(backup.is_file());
+    }
+
+    fn vars(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<OsString> {
+        let vars = vars
+            .iter()
+            .map(|&(k, v)| (k.to_owned(), OsString::from(v)))
+            .collect::<HashMap<_, _>>();
+
+        move |name| vars.get(name).cloned()
+    }
+
+    #[test]
+    fn env_overrides_file() {
+        let vars = vars(&[("YABG3NML_INSTALL_ROOT", r"C:\Env")]);
+        let config = parse_with_env("[core]\ninstall_root = 'C:\\File'", vars).unwrap();
+
+        assert_eq!(config.core.install_root, Path::new(r"C:\Env"));
+        assert_eq!(config.env_overrides.len(), 1);
+    }
+
+    #[test]
+    fn env_overrides_default() {
+        let vars = vars(&[("YABG3NML_INSTALL_ROOT", r"C:\Env")]);
+        let config = parse_with_env("", vars).unwrap();
+
+        assert_eq!(config.core.install_root, Path::new(r"C:\Env"));
+    }
+
+    #[test]
+    fn file_overrides_default() {
+        let config = parse_with_env("[core]\ninstall_root = 'C:\\File'", vars(&[])).unwrap();
+
+        assert_eq!(config.core.install_root, Path::new(r"C:\File"));
+        assert!(config.env_overrides.is_empty());
+
+        let config = parse_with_env("", vars(&[])).unwrap();
+        assert_eq!(
+            config.core.install_root,
+            Config::default().core.install_root
+        );
+    }
+
+    #[test]
+    fn empty_env_var_is_ignored() {
+        let vars = vars(&[("YABG3NML_INSTALL_ROOT", "")]);
+        let config = parse_with_env("[core]\ninstall_root = 'C:\\File'", vars).unwrap();
+
+        assert_eq!(config.core.install_root, Path::new(r"C:\File"));
+        assert!(config.env_overrides.is_empty());
+    }
+
+    #[test]
+    fn quiet_env_values() {
+        for value in ["1", "true", "TRUE", "True"] {
+            assert!(is_enabled(Some(value.into())), "{value}");
         }
-    });
 
-    CONFIG.as_ref().map_err(|e| Report::new(&**e))
+        for value in ["0", "false", "", "yes"] {
+            assert!(!is_enabled(Some(value.into())), "{value}");
+        }
+
+        assert!(!is_enabled(None));
+    }
+
+    /// The validation error for `config`, which must be invalid
+    fn invalid(config: &Config) -> String {
+        config.validate().unwrap_err().to_string()
+    }
+
+    #[test]
+    fn default_config_is_valid() {
+        Config::default().validate().unwrap();
+    }
+
+    #[test]
+    fn zero_max_write_size_is_invalid() {
+        let mut config = Config::default();
+        config.core.max_write_size = 0;
+
+        assert!(invalid(&config).contains("`max_write_size`"));
+    }
+
+    #[test]
+    fn zero_poll_interval_is_invalid() {
+        let mut config = Config::default();
+        config.core.poll_intervals.insert("bg3.exe".to_owned(), 500);
+        config.validate().unwrap();
+
+        config
+            .core
+            .poll_intervals
+            .insert("bg3_dx11.exe".to_owned(), 0);
+        assert!(invalid(&config).contains("`poll_intervals` entry \"bg3_dx11.exe\""));
+    }
+
+    #[test]
+    fn bad_call_after_load_is_invalid() {
+        let mut config = Config::default();
+        let mut plugin = |export: &str| {
+            config.plugins.insert(
+                "Plugin".to_owned(),
+                PluginConfig {
+                    call_after_load: Some(export.to_owned()),
+                },
+            );
+
+            config.validate()
+        };
+
+        assert!(plugin("Initialize").is_ok());
+        for export in ["", "  ", "Init\0ialize"] {
+            let e = plugin(export).unwrap_err().to_string();
+            assert!(e.contains("`plugins.Plugin.call_after_load`"), "{export:?}");
+        }
+    }
+
+    #[test]
+    fn zero_max_concurrent_injections_is_invalid() {
+        let mut config = Config::default();
+        config.core.max_concurrent_injections = 0;
+
+        assert!(invalid(&config).contains("`max_concurrent_injections`"));
+    }
+
+    #[test]
+    fn bad_supported_game_version_is_invalid() {
+        let mut config = Config::default();
+        config.core.supported_game_versions =
+            vec!["4.1-".to_owned(), "4.1.1.5000-4.1.1.6999".to_owned()];
+        config.validate().unwrap();
+
+        config.core.supported_game_versions.push("4.x".to_owned());
+        assert!(invalid(&config)
+            .contains("`supported_game_versions` has an invalid version range \"4.x\""));
+    }
+
+    #[test]
+    fn unknown_load_library_flag_is_invalid() {
+        let mut config = Config::default();
+        config.core.load_library_flags = vec!["LOAD_LIBRARY_SEARCH_DLL_LOAD_DIR".to_owned()];
+        config.validate().unwrap();
+
+        config
+            .core
+            .load_library_flags
+            .push("LOAD_NOTHING".to_owned());
+        assert!(invalid(&config).contains("unknown flag \"LOAD_NOTHING\""));
+    }
+
+    #[test]
+    fn conflicting_load_library_flags_are_invalid() {
+        let mut config = Config::default();
+        config.core.load_library_flags = vec![
+            "LOAD_WITH_ALTERED_SEARCH_PATH".to_owned(),
+            "LOAD_LIBRARY_SEARCH_SYSTEM32".to_owned(),
+        ];
+
+        assert!(invalid(&config).contains("can't combine LOAD_WITH_ALTERED_SEARCH_PATH"));
+    }
+
+    #[test]
+    fn empty_target_plugin_dir_is_invalid() {
+        let mut config = Config::default();
+        config
+            .core
+            .target_plugin_dirs
+            .insert("bg3.exe".to_owned(), "vulkan".into());
+        config.validate().unwrap();
+
+        config
+            .core
+            .target_plugin_dirs
+            .insert("bg3_dx11.exe".to_owned(), PathBuf::new());
+        assert!(invalid(&config).contains("`target_plugin_dirs` entry \"bg3_dx11.exe\" is empty"));
+    }
 }
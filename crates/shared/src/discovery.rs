@@ -0,0 +1,246 @@
+//! Which files are plugins. The loader and every tool which reports on plugins use this,
+//! so they always agree on what gets loaded
+
+use std::{
+    fs,
+    os::windows::fs::MetadataExt as _,
+    path::{self, Path, PathBuf},
+};
+
+use eyre::{Context as _, Result};
+use tracing::{error, trace, warn};
+use unicase::UniCase;
+use windows::Win32::Storage::FileSystem::FILE_ATTRIBUTE_REPARSE_POINT;
+
+use crate::{config::Config, paths::get_bg3_plugins_dir};
+
+/// The plugins dir for a game exe, by its filename. The plugins dir, unless the exe is in `target_plugin_dirs`
+pub fn plugins_dir_for(config: &Config, exe: &str) -> Result<PathBuf> {
+    Ok(config.core.target_plugin_dir(&get_bg3_plugins_dir()?, exe))
+}
+
+/// The plugins dir, and every dir in `target_plugin_dirs`. These are all the dirs plugins may be loaded from
+pub fn all_plugin_dirs(config: &Config) -> Result<Vec<PathBuf>> {
+    let plugins_dir = get_bg3_plugins_dir()?;
+
+    let mut dirs = vec![plugins_dir.clone()];
+    for exe in config.core.target_plugin_dirs.keys() {
+        let dir = config.core.target_plugin_dir(&plugins_dir, exe);
+        if !is_skipped(&dir, &dirs) {
+            dirs.push(dir);
+        }
+    }
+
+    Ok(dirs)
+}
+
+/// Every plugin in `dir`, going by `extensions`. With `recursive`, the plugins in its subdirs are included too.
+/// Subdirs which can't be read are skipped
+///
+/// Subdirs which are junctions or symlinks are never searched, since they could lead back up the tree.
+/// Neither are the subdirs of the plugins dir which this tool keeps its own files in, nor the
+/// `target_plugin_dirs` of other exes, since those plugins are only for them
+pub fn list_plugin_files(config: &Config, dir: &Path) -> Result<Vec<PathBuf>> {
+    let skipped = match get_bg3_plugins_dir() {
+        Ok(plugins_dir) => skipped_dirs(config, &plugins_dir, dir),
+        Err(_) => Vec::new(),
+    };
+
+    walk(config, dir, &skipped)
+}
+
+/// The subdirs which are never searched for plugins when searching `dir`
+fn skipped_dirs(config: &Config, plugins_dir: &Path, dir: &Path) -> Vec<PathBuf> {
+    let mut skipped = vec![plugins_dir.join("logs"), plugins_dir.join("overrides")];
+
+    skipped.extend(
+        config
+            .core
+            .target_plugin_dirs
+            .keys()
+            .map(|exe| config.core.target_plugin_dir(plugins_dir, exe))
+            .filter(|target| !is_skipped(dir, std::slice::from_ref(target))),
+    );
+
+    skipped
+}
+
+fn walk(config: &Config, dir: &Path, skipped: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let entries = list_dir(dir).inspect_err(|e| error!(?e, "failed to read plugins dir"))?;
+    let mut dirs = vec![entries];
+
+    while let Some(entries) = dirs.pop() {
+        for path in entries {
+            if path.is_dir() {
+                if !config.core.recursive || is_skipped(&path, skipped) {
+                    continue;
+                }
+
+                if is_reparse_point(&path) {
+                    trace!(path = %path.display(), "not searching plugins subdir, since it's a junction or symlink");
+                    continue;
+                }
+
+                match list_dir(&path) {
+                    Ok(entries) => dirs.push(entries),
+                    Err(e) => warn!(%e, "skipping unreadable plugins subdir"),
+                }
+            } else if path.is_file() && config.core.has_plugin_extension(&path) {
+                files.push(path);
+            }
+        }
+    }
+
+    Ok(files)
+}
+
+/// Whether `path` is one of `skipped`. Paths are case-insensitive, and may use either slash
+fn is_skipped(path: &Path, skipped: &[PathBuf]) -> bool {
+    let normalize = |path: &Path| {
+        let path = path::absolute(path).unwrap_or_else(|_| path.to_owned());
+        UniCase::new(path.to_string_lossy().trim_end_matches('\\').to_owned())
+    };
+
+    let path = normalize(path);
+    skipped.iter().any(|s| normalize(s) == path)
+}
+
+/// Whether `path` itself is a junction or symlink
+fn is_reparse_point(path: &Path) -> bool {
+    fs::symlink_metadata(path)
+        .is_ok_and(|m| m.file_attributes() & FILE_ATTRIBUTE_REPARSE_POINT.0 != 0)
+}
+
+/// Every path in a dir
+fn list_dir(dir: &Path) -> Result<Vec<PathBuf>> {
+    let read_dir = fs::read_dir(dir)
+        .with_context(|| format!("failed to read plugins dir {}", dir.display()))?;
+
+    let paths = read_dir
+        .filter_map(|entry| match entry {
+            Ok(entry) => Some(entry.path()),
+            Err(e) => {
+                warn!(%e, "skipping unreadable dir entry");
+                None
+            }
+        })
+        .collect();
+
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::windows::fs::symlink_dir;
+
+    use tempfile::tempdir;
+
+    use super::*;
+
+    fn touch(path: &Path) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, b"").unwrap();
+    }
+
+    fn recursive() -> Config {
+        let mut config = Config::default();
+        config.core.recursive = true;
+        config
+    }
+
+    fn names(files: Vec<PathBuf>) -> Vec<String> {
+        let mut names = files
+            .iter()
+            .map(|f| f.file_name().unwrap().to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn finds_nested_plugins() {
+        let tmp = tempdir().unwrap();
+        let dir = tmp.path();
+        touch(&dir.join("Top.dll"));
+        touch(&dir.join("a").join("b").join("Nested.dll"));
+        touch(&dir.join("a").join("readme.txt"));
+
+        let files = walk(&recursive(), dir, &[]).unwrap();
+        assert_eq!(names(files), ["Nested.dll", "Top.dll"]);
+
+        let files = walk(&Config::default(), dir, &[]).unwrap();
+        assert_eq!(names(files), ["Top.dll"]);
+    }
+
+    #[test]
+    fn finds_plugins_by_extension() {
+        let tmp = tempdir().unwrap();
+        let dir = tmp.path();
+        touch(&dir.join("Plain.dll"));
+        touch(&dir.join("Upper.DLL"));
+        touch(&dir.join("Asi.asi"));
+        touch(&dir.join("Mixed.AsI"));
+        touch(&dir.join("readme.txt"));
+        touch(&dir.join("NoExtension"));
+
+        let files = walk(&Config::default(), dir, &[]).unwrap();
+        assert_eq!(names(files), ["Plain.dll", "Upper.DLL"]);
+
+        let mut config = Config::default();
+        config.core.extensions = vec!["dll".into(), ".ASI".into()];
+
+        let files = walk(&config, dir, &[]).unwrap();
+        assert_eq!(
+            names(files),
+            ["Asi.asi", "Mixed.AsI", "Plain.dll", "Upper.DLL"]
+        );
+    }
+
+    #[test]
+    fn skips_tool_dirs() {
+        let tmp = tempdir().unwrap();
+        let dir = tmp.path();
+        touch(&dir.join("Top.dll"));
+        touch(&dir.join("logs").join("Log.dll"));
+
+        let files = walk(&recursive(), dir, &[dir.join("LOGS")]).unwrap();
+        assert_eq!(names(files), ["Top.dll"]);
+    }
+
+    #[test]
+    fn skips_other_exes_dirs() {
+        let mut config = recursive();
+        config
+            .core
+            .target_plugin_dirs
+            .insert("bg3_dx11.exe".to_owned(), "dx11/plugins".into());
+
+        let plugins_dir = Path::new(r"C:\Plugins");
+
+        let skipped = skipped_dirs(&config, plugins_dir, plugins_dir);
+        assert!(is_skipped(Path::new(r"C:\Plugins\DX11\plugins"), &skipped));
+        assert!(is_skipped(Path::new(r"C:\Plugins\logs"), &skipped));
+
+        // an exe's own dir is searched
+        let own = plugins_dir.join("dx11").join("plugins");
+        let skipped = skipped_dirs(&config, plugins_dir, &own);
+        assert!(!is_skipped(&own, &skipped));
+    }
+
+    #[test]
+    fn does_not_follow_links() {
+        let tmp = tempdir().unwrap();
+        let dir = tmp.path();
+        touch(&dir.join("sub").join("Plugin.dll"));
+
+        // creating symlinks needs developer mode or admin
+        if symlink_dir(dir, dir.join("sub").join("loop")).is_err() {
+            return;
+        }
+
+        let files = walk(&recursive(), dir, &[]).unwrap();
+        assert_eq!(names(files), ["Plugin.dll"]);
+    }
+}
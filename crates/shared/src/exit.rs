@@ -0,0 +1,18 @@
+use std::{process, sync::OnceLock};
+
+static BEFORE_EXIT: OnceLock<fn()> = OnceLock::new();
+
+/// Run `f` right before the process exits through [`exit`], e.g. to flush buffered logs.
+/// Only the first hook set is kept
+pub fn set_before_exit(f: fn()) {
+    _ = BEFORE_EXIT.set(f);
+}
+
+/// Like [`process::exit`], but runs the before exit hook first, since exiting skips all destructors
+pub fn exit(code: i32) -> ! {
+    if let Some(f) = BEFORE_EXIT.get() {
+        f();
+    }
+
+    process::exit(code);
+}
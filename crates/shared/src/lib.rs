@@ -1,7 +1,13 @@
 pub mod backtrace;
 pub mod config;
+pub mod discovery;
+pub mod exit;
+pub mod manifest;
 pub mod paths;
+pub mod pe;
 pub mod pipe;
 pub mod popup;
+pub mod signature;
 pub mod thread_data;
 pub mod utils;
+pub mod version_info;
@@ -0,0 +1,153 @@
+use std::{
+    fs, io,
+    path::{Component, Path, PathBuf},
+    sync::LazyLock,
+};
+
+use eyre::{bail, Report, Result};
+use serde::{Deserialize, Serialize};
+use tracing::trace;
+use unicase::UniCase;
+
+use crate::{config::PluginConfig, paths::get_bg3_plugins_dir};
+
+/// An explicit list of plugins, in load order. When `manifest.toml` exists in the plugins dir, it's used
+/// instead of searching the plugins dir, and plugins which aren't listed in it are never loaded
+///
+/// ```toml
+/// [[plugin]]
+/// file = "FooBar.dll"
+///
+/// [[plugin]]
+/// file = "Baz.dll"
+/// enabled = false
+/// call_after_load = "Initialize"
+/// ```
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Manifest {
+    #[serde(default, rename = "plugin")]
+    pub plugins: Vec<ManifestPlugin>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManifestPlugin {
+    /// Path of the plugin, relative to the plugins dir. It must be inside of it
+    pub file: PathBuf,
+    /// Whether to load it
+    #[serde(default = "enabled")]
+    pub enabled: bool,
+    /// The same options as `[plugins.<name>]` in config.toml. These take precedence over those
+    #[serde(flatten)]
+    pub options: PluginConfig,
+}
+
+fn enabled() -> bool {
+    true
+}
+
+impl ManifestPlugin {
+    /// The plugins filename without extension, like it's used everywhere in config.toml
+    pub fn name(&self) -> String {
+        self.file
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned()
+    }
+}
+
+impl Manifest {
+    /// The manifest entry for a plugin, by the plugins filename without extension
+    pub fn plugin(&self, name: &str) -> Option<&ManifestPlugin> {
+        let name = UniCase::new(name);
+        self.plugins.iter().find(|p| UniCase::new(p.name()) == name)
+    }
+
+    /// Parse a manifest, and check that every `file` is inside of the plugins dir
+    pub fn parse(manifest: &str) -> Result<Self> {
+        let manifest = toml::from_str::<Manifest>(manifest)?;
+
+        for plugin in &manifest.plugins {
+            if !is_inside(&plugin.file) {
+                bail!(
+                    "`file = {:?}` must be a relative path inside of the plugins folder",
+                    plugin.file
+                );
+            }
+        }
+
+        Ok(manifest)
+    }
+}
+
+/// Whether `path` is a plain relative path, which can't lead out of the dir it's joined to. e.g. `..\x.dll`,
+/// `C:\x.dll`, and `\x.dll` all aren't
+fn is_inside(path: &Path) -> bool {
+    let mut components = path.components().peekable();
+    if components.peek().is_none() {
+        return false;
+    }
+
+    components.all(|c| match c {
+        // a colon would name an alternate data stream
+        Component::Normal(name) => !name.to_string_lossy().contains(':'),
+        Component::CurDir => true,
+        Component::Prefix(_) | Component::RootDir | Component::ParentDir => false,
+    })
+}
+
+/// The plugins dir's `manifest.toml`, or None if there isn't one
+pub fn get_manifest() -> Result<Option<&'static Manifest>> {
+    static MANIFEST: LazyLock<Result<Option<Manifest>>> = LazyLock::new(|| {
+        let path = get_bg3_plugins_dir()?.join("manifest.toml");
+
+        let manifest = match fs::read_to_string(&path) {
+            Ok(v) => v,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        let manifest = Manifest::parse(&manifest)?;
+
+        trace!(?manifest, "read manifest");
+
+        Ok(Some(manifest))
+    });
+
+    MANIFEST
+        .as_ref()
+        .map(Option::as_ref)
+        .map_err(|e| Report::new(&**e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manifest(file: &str) -> Result<Manifest> {
+        Manifest::parse(&format!("[[plugin]]\nfile = '{file}'"))
+    }
+
+    #[test]
+    fn accepts_paths_inside_plugins_dir() {
+        for file in ["Foo.dll", r"sub\Foo.dll", "sub/Foo.dll", r".\Foo.dll"] {
+            assert!(manifest(file).is_ok(), "{file}");
+        }
+    }
+
+    #[test]
+    fn rejects_paths_outside_plugins_dir() {
+        for file in [
+            r"..\..\x.dll",
+            r"sub\..\..\x.dll",
+            r"C:\x.dll",
+            r"\x.dll",
+            r"\\server\share\x.dll",
+            "C:x.dll",
+            "Foo.dll:stream",
+            "",
+        ] {
+            assert!(manifest(file).is_err(), "{file}");
+        }
+    }
+}
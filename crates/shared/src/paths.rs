@@ -1,8 +1,56 @@
-use std::{fs, path::PathBuf, sync::OnceLock};
+use std::{
+    env, fs,
+    os::windows::ffi::OsStrExt as _,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
 
 use directories::BaseDirs;
 use eyre::{bail, eyre, Result};
-use tracing::{info, trace};
+use tracing::{info, trace, warn};
+use windows::Win32::Foundation::MAX_PATH;
+
+/// The Larian launcher's exe filename, found in `<install_root>\Launcher`
+pub const LAUNCHER_EXE: &str = "LariLauncher.exe";
+
+/// When this file is next to the exe, the tools run in portable mode, like with `--portable`
+pub const PORTABLE_MARKER: &str = "portable.txt";
+
+static LOCAL_APPDATA_OVERRIDE: OnceLock<PathBuf> = OnceLock::new();
+static PORTABLE_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+/// Use this instead of the current user's local AppData folder. Must be called before any paths are looked up
+///
+/// Needed when running as a service, since the service account has its own AppData folder
+pub fn set_local_appdata_override(path: PathBuf) {
+    _ = LOCAL_APPDATA_OVERRIDE.set(path);
+}
+
+/// Keep everything in `dir` instead of the user's AppData and temp folders. Must be called before any paths are looked up
+///
+/// The layout is:
+/// - `<dir>\Plugins` for plugins, `config.toml`, and `manifest.toml`
+/// - `<dir>\Plugins\logs` for logs
+/// - `<dir>\staging` for staged plugins
+pub fn set_portable_dir(dir: PathBuf) {
+    _ = PORTABLE_DIR.set(dir);
+}
+
+/// The portable mode base dir, if portable mode is enabled
+pub fn get_portable_dir() -> Option<&'static Path> {
+    PORTABLE_DIR.get().map(PathBuf::as_path)
+}
+
+/// The local AppData folder, or its override if one was set
+pub fn get_local_appdata_dir() -> Result<PathBuf> {
+    if let Some(path) = LOCAL_APPDATA_OVERRIDE.get() {
+        return Ok(path.clone());
+    }
+
+    let local = BaseDirs::new().ok_or(eyre!("Failed to instantiate BaseDirs"))?;
+
+    Ok(local.data_local_dir().to_owned())
+}
 
 pub fn get_larian_local_dir() -> Result<PathBuf> {
     static CACHE: OnceLock<PathBuf> = OnceLock::new();
@@ -11,9 +59,7 @@ pub fn get_larian_local_dir() -> Result<PathBuf> {
         return Ok(cache.clone());
     }
 
-    let local = BaseDirs::new().ok_or(eyre!("Failed to instantiate BaseDirs"))?;
-
-    let mut local = local.data_local_dir().to_owned();
+    let mut local = get_local_appdata_dir()?;
 
     local.push("Larian Studios");
 
@@ -55,8 +101,7 @@ pub fn get_bg3_plugins_dir() -> Result<PathBuf> {
         return Ok(cache.clone());
     }
 
-    let mut plugins_dir = get_bg3_local_dir()?;
-    plugins_dir.push("Plugins");
+    let plugins_dir = bg3_plugins_dir_path()?;
 
     trace!(path = %plugins_dir.display(), "Looking for bg3 plugins dir");
 
@@ -70,9 +115,77 @@ pub fn get_bg3_plugins_dir() -> Result<PathBuf> {
     if !log_dir.exists() {
         info!("Log directory not found; creating it..");
 
-        fs::create_dir(plugins_dir.join("logs"))?;
+        // not fatal; logging falls back to another location if the logs dir is unusable
+        if let Err(e) = fs::create_dir(&log_dir) {
+            warn!(%e, "failed to create log directory");
+        }
     }
 
     _ = CACHE.set(plugins_dir.clone());
     Ok(plugins_dir)
 }
+
+/// Where the plugins dir is. Unlike [`get_bg3_plugins_dir`], it and the logs dir aren't created if they're missing
+pub fn bg3_plugins_dir_path() -> Result<PathBuf> {
+    let mut plugins_dir = match get_portable_dir() {
+        Some(dir) => dir.to_owned(),
+        None => get_bg3_local_dir()?,
+    };
+
+    plugins_dir.push("Plugins");
+
+    Ok(plugins_dir)
+}
+
+/// The plugins given with `--plugin` for a game process, one path per line. Read by the loader
+/// instead of searching the plugins dir
+///
+/// `created` is the process' creation time, so a list left behind for an earlier process with the same pid
+/// is never picked up
+pub fn get_plugin_override_path(pid: u32, created: u64) -> Result<PathBuf> {
+    Ok(get_plugin_overrides_dir()?.join(format!("{pid}-{created}.txt")))
+}
+
+/// Where the `--plugin` lists of every game process are kept
+pub fn get_plugin_overrides_dir() -> Result<PathBuf> {
+    Ok(get_bg3_plugins_dir()?.join("overrides"))
+}
+
+/// Where plugins get copied to when `stage_plugins` is enabled
+pub fn get_staging_dir() -> PathBuf {
+    match get_portable_dir() {
+        Some(dir) => dir.join("staging"),
+        None => env::temp_dir().join("yabg3nml-staging"),
+    }
+}
+
+/// Paths of MAX_PATH or more chars only work with winapi if they use the extended-length `\\?\` prefix.
+/// Long absolute paths are converted to that form; anything else is returned as-is
+pub fn to_extended_path<P: AsRef<Path>>(path: P) -> PathBuf {
+    let path = path.as_ref();
+
+    // +1 for the null terminator
+    let len = path.as_os_str().encode_wide().count() + 1;
+    if len <= MAX_PATH as usize || !path.is_absolute() {
+        return path.to_owned();
+    }
+
+    let path_str = path.to_string_lossy();
+    // already extended or a device path
+    if path_str.starts_with(r"\\?\") || path_str.starts_with(r"\\.\") {
+        return path.to_owned();
+    }
+
+    // the prefix disables all path normalization, so separators must already be correct
+    let path_str = path_str.replace('/', r"\");
+
+    let extended = match path_str.strip_prefix(r"\\") {
+        // \\server\share -> \\?\UNC\server\share
+        Some(unc) => format!(r"\\?\UNC\{unc}"),
+        None => format!(r"\\?\{path_str}"),
+    };
+
+    trace!(path = %path.display(), %extended, "using extended-length path");
+
+    extended.into()
+}
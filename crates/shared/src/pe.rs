@@ -0,0 +1,47 @@
+use std::{fs, path::Path};
+
+use pelite::{
+    image::IMAGE_FILE_DLL,
+    pe64::{Pe as _, PeFile},
+};
+
+/// The dll names a plugin imports from, by its PE import table
+pub fn read_imports(path: &Path) -> Vec<String> {
+    let Ok(data) = fs::read(path) else {
+        return Vec::new();
+    };
+
+    let Ok(file) = PeFile::from_bytes(&data) else {
+        return Vec::new();
+    };
+
+    let Ok(imports) = file.imports() else {
+        return Vec::new();
+    };
+
+    imports
+        .iter()
+        .filter_map(|desc| {
+            desc.dll_name()
+                .ok()
+                .and_then(|name| name.to_str().ok())
+                .map(str::to_owned)
+        })
+        .collect()
+}
+
+/// Why a plugin can't be loaded into the game by its PE headers, or None if it looks fine.
+/// It must be a valid 64-bit dll, like the game
+pub fn invalid_reason(data: &[u8]) -> Option<String> {
+    let file = match PeFile::from_bytes(data) {
+        Ok(file) => file,
+        Err(pelite::Error::PeMagic) => return Some("it is not a 64-bit dll".to_owned()),
+        Err(e) => return Some(format!("it is not a valid dll: {e}")),
+    };
+
+    if file.file_header().Characteristics & IMAGE_FILE_DLL == 0 {
+        return Some("it is an exe, not a dll".to_owned());
+    }
+
+    None
+}
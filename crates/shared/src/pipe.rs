@@ -1,9 +1,9 @@
 pub mod commands;
 
 use std::{
-    cell::RefCell,
     convert::Infallible,
     io::{self, ErrorKind},
+    mem,
     ops::ControlFlow,
     os::windows::prelude::AsRawHandle as _,
     rc::Rc,
@@ -18,8 +18,9 @@ use tokio::{
         ClientOptions, NamedPipeClient, NamedPipeServer, PipeMode, ServerOptions,
     },
     runtime::{Builder, Runtime},
+    task::{self, LocalSet},
 };
-use tracing::{error, trace, trace_span};
+use tracing::{error, trace, trace_span, Instrument as _};
 use windows::Win32::{
     Foundation::HANDLE,
     Security::{
@@ -38,7 +39,8 @@ static RUNTIME: LazyLock<Runtime> = LazyLock::new(|| {
         .expect("failed to start runtime")
 });
 
-pub const PIPE: &str = r"\\.\pipe\yabg3nml";
+/// The pipe loader.dll reports back to the tool over. `\\.\pipe\yabg3nml` is the tool's control pipe
+pub const PIPE: &str = r"\\.\pipe\yabg3nml-loader";
 
 pub type Pid = u32;
 pub type Auth = u64;
@@ -109,31 +111,23 @@ impl Client {
     }
 }
 
-pub struct Server {
-    buf: Vec<u8>,
-    tbuf: Box<[u8]>,
-    msg_len: Option<usize>,
-}
-
-impl Default for Server {
-    fn default() -> Self {
-        Self {
-            buf: Vec::with_capacity(4096),
-            tbuf: vec![0; 4096].into_boxed_slice(),
-            msg_len: None,
-        }
-    }
-}
+/// The pipe server loader.dll reports back to. Serves every loader which connects at the same time, so every
+/// game being injected can report back
+#[derive(Default)]
+pub struct Server(());
 
 impl Server {
     pub fn new() -> Self {
         Self::default()
     }
 
+    /// Serve every loader which connects. A loader must authenticate with its first message. `auth` gets the
+    /// loader's pid and the code it sent. Every message after that is passed to `cb`, along with the pid of the
+    /// game it came from
     pub fn recv_all(
-        &mut self,
-        cb: impl Fn(Receive),
-        mut auth: impl FnMut(Pid, Auth) -> bool,
+        &self,
+        cb: impl Fn(Pid, Receive) + 'static,
+        auth: impl Fn(Pid, Auth) -> bool + 'static,
     ) -> io::Result<Infallible> {
         let span = trace_span!("pipe");
         let _guard = span.enter();
@@ -164,103 +158,79 @@ impl Server {
             bInheritHandle: false.into(),
         };
 
-        let first = Rc::new(RefCell::new(true));
-        let mut process_cmd = {
-            let first = first.clone();
-            move |server: &NamedPipeServer, cmd| {
-                let mut first = first.borrow_mut();
-                if *first {
-                    let span = trace_span!("auth");
-                    let _guard = span.enter();
-
-                    #[rustfmt::skip]
-                    #[allow(irrefutable_let_patterns)]
-                    let Command::Request(Request::Auth(auth_code)) = cmd else {
-                        error!(?cmd, "auth not provided, disconnecting client");
-                        return ControlFlow::Break(());
-                    };
-
-                    trace!(auth_code, "received auth");
-
-                    let handle = HANDLE(server.as_raw_handle());
-                    let mut pid = 0;
-                    let res = unsafe { GetNamedPipeClientProcessId(handle, &mut pid) };
-                    if let Err(e) = res {
-                        error!(%e, "failed to get client pid");
-                        return ControlFlow::Break(());
-                    }
-
-                    if !auth(pid, auth_code) {
-                        error!("failed auth, disconnecting");
-                        return ControlFlow::Break(());
-                    }
-
-                    *first = false;
-                } else {
-                    let Command::Receive(cmd) = cmd else {
-                        error!(?cmd, "did not receive Command::Receive");
-                        return ControlFlow::Break(());
-                    };
-
-                    let span = trace_span!("cb");
-                    let _guard = span.enter();
-
-                    cb(cmd);
-                }
-
-                ControlFlow::Continue(())
-            }
-        };
+        let cb = Rc::new(cb);
+        let auth = Rc::new(auth);
 
         let fut = async {
+            let mut server = unsafe { create(&mut sa)? };
+
             loop {
-                // this lint is returning a false positive?
-                // https://github.com/rust-lang/rust-clippy/issues/13879
-                #[allow(clippy::multiple_unsafe_ops_per_block)]
-                unsafe {
-                    self.connect(&mut sa, &mut process_cmd).await?;
+                if let Err(e) = server.connect().await {
+                    error!(%e, "client failed to connect");
+                    // not an error in the sense that it's not fatal
+                    server = unsafe { create(&mut sa)? };
+                    continue;
                 }
 
-                // reset state in case it early exited
-                self.buf.clear();
-                self.msg_len = None;
-                *first.borrow_mut() = true;
+                // the next instance is created before this one is served, so another loader can always connect
+                let connected = mem::replace(&mut server, unsafe { create(&mut sa)? });
+
+                let cb = cb.clone();
+                let auth = auth.clone();
+                task::spawn_local(
+                    async move { Connection::default().serve(&connected, &*cb, &*auth).await }
+                        .instrument(trace_span!("client")),
+                );
             }
         };
 
-        RUNTIME.block_on(fut)
+        LocalSet::new().block_on(&RUNTIME, fut)
     }
+}
 
-    /// # Safety:
-    /// sa must be valid
-    async unsafe fn connect(
-        &mut self,
-        sa: *mut SECURITY_ATTRIBUTES,
-        process_cmd: &mut impl FnMut(&NamedPipeServer, Command) -> ControlFlow<()>,
-    ) -> Result<(), io::Error> {
-        let server = unsafe {
-            ServerOptions::new()
-                .access_inbound(true)
-                .access_outbound(false)
-                .reject_remote_clients(true)
-                .pipe_mode(PipeMode::Byte)
-                .create_with_security_attributes_raw(PIPE, sa.cast())
-        };
+/// # Safety:
+/// sa must be valid
+unsafe fn create(sa: *mut SECURITY_ATTRIBUTES) -> io::Result<NamedPipeServer> {
+    let server = unsafe {
+        ServerOptions::new()
+            .access_inbound(true)
+            .access_outbound(false)
+            .reject_remote_clients(true)
+            .pipe_mode(PipeMode::Byte)
+            .create_with_security_attributes_raw(PIPE, sa.cast())
+    };
+
+    server.inspect_err(|e| error!(%e, "failed to create server"))
+}
 
-        let server = match server {
-            Ok(s) => s,
-            Err(e) => {
-                error!(%e, "failed to create server");
-                return Err(e);
-            }
-        };
+/// One connected loader
+struct Connection {
+    buf: Vec<u8>,
+    tbuf: Box<[u8]>,
+    msg_len: Option<usize>,
+    /// the pid of the loader's game, once it authenticated
+    pid: Option<Pid>,
+}
 
-        if let Err(e) = server.connect().await {
-            error!(%e, "client failed to connect");
-            // not an error in the sense that it's not fatal
-            return Ok(());
+impl Default for Connection {
+    fn default() -> Self {
+        Self {
+            buf: Vec::with_capacity(4096),
+            tbuf: vec![0; 4096].into_boxed_slice(),
+            msg_len: None,
+            pid: None,
         }
+    }
+}
 
+impl Connection {
+    /// Read messages from the loader until it disconnects
+    async fn serve(
+        mut self,
+        server: &NamedPipeServer,
+        cb: &impl Fn(Pid, Receive),
+        auth: &impl Fn(Pid, Auth) -> bool,
+    ) {
         loop {
             if server.readable().await.is_err() {
                 break;
@@ -299,12 +269,12 @@ impl Server {
 
                             let Ok(cmd) = serde_json::from_slice::<Command>(data) else {
                                 trace!(?data, "received invalid cmd");
-                                return Ok(());
+                                return;
                             };
 
-                            if process_cmd(&server, cmd).is_break() {
+                            if self.process(server, cmd, cb, auth).is_break() {
                                 _ = server.disconnect();
-                                return Ok(());
+                                return;
                             }
 
                             self.buf.drain(..len + size_of::<usize>());
@@ -328,7 +298,56 @@ impl Server {
                 }
             }
         }
+    }
 
-        Ok(())
+    fn process(
+        &mut self,
+        server: &NamedPipeServer,
+        cmd: Command,
+        cb: &impl Fn(Pid, Receive),
+        auth: &impl Fn(Pid, Auth) -> bool,
+    ) -> ControlFlow<()> {
+        let Some(pid) = self.pid else {
+            let span = trace_span!("auth");
+            let _guard = span.enter();
+
+            #[rustfmt::skip]
+            #[allow(irrefutable_let_patterns)]
+            let Command::Request(Request::Auth(auth_code)) = cmd else {
+                error!(?cmd, "auth not provided, disconnecting client");
+                return ControlFlow::Break(());
+            };
+
+            trace!(auth_code, "received auth");
+
+            let handle = HANDLE(server.as_raw_handle());
+            let mut pid = 0;
+            let res = unsafe { GetNamedPipeClientProcessId(handle, &mut pid) };
+            if let Err(e) = res {
+                error!(%e, "failed to get client pid");
+                return ControlFlow::Break(());
+            }
+
+            if !auth(pid, auth_code) {
+                error!(pid, "failed auth, disconnecting");
+                return ControlFlow::Break(());
+            }
+
+            self.pid = Some(pid);
+
+            return ControlFlow::Continue(());
+        };
+
+        let Command::Receive(cmd) = cmd else {
+            error!(?cmd, "did not receive Command::Receive");
+            return ControlFlow::Break(());
+        };
+
+        let span = trace_span!("cb");
+        let _guard = span.enter();
+
+        cb(pid, cmd);
+
+        ControlFlow::Continue(())
     }
 }
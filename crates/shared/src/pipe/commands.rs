@@ -11,6 +11,7 @@ pub enum Request {
 #[derive(Debug, Serialize, Deserialize)]
 pub enum Receive {
     Log(LogMsg),
+    Loaded(LoadedMsg),
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -42,6 +43,18 @@ pub struct LogMsg {
     pub fields: HashMap<String, String>,
 }
 
+/// Sent once plugin loading finished
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LoadedMsg {
+    pub loaded: usize,
+    pub failed: usize,
+    /// names of the plugins which loaded
+    pub names: Vec<String>,
+    /// whether this is from the last phase which runs when the game starts, so the game is done being injected.
+    /// False for the suspended phase, which the resumed phase follows, and for the late phase and reinjecting
+    pub last_phase: bool,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Span {
     pub name: String,
@@ -1,7 +1,10 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
 use windows::{
     core::{HSTRING, PCWSTR},
     Win32::UI::WindowsAndMessaging::{
-        MessageBoxW, MB_ICONERROR, MB_ICONINFORMATION, MB_ICONWARNING, MESSAGEBOX_STYLE,
+        MessageBoxW, IDYES, MB_ICONERROR, MB_ICONINFORMATION, MB_ICONWARNING, MB_YESNO,
+        MESSAGEBOX_STYLE,
     },
 };
 
@@ -21,7 +24,18 @@ impl From<MessageBoxIcon> for MESSAGEBOX_STYLE {
     }
 }
 
+static POPUPS_DISABLED: AtomicBool = AtomicBool::new(false);
+
+/// Make every popup a no-op. Used where nobody could see or dismiss them, e.g. in a service
+pub fn disable_popups() {
+    POPUPS_DISABLED.store(true, Ordering::Relaxed);
+}
+
 pub fn display_popup<T: AsRef<str>, M: AsRef<str>>(title: T, message: M, icon: MessageBoxIcon) {
+    if POPUPS_DISABLED.load(Ordering::Relaxed) {
+        return;
+    }
+
     let title = title.as_ref();
     let message = message.as_ref();
 
@@ -43,10 +57,27 @@ pub fn display_popup<T: AsRef<str>, M: AsRef<str>>(title: T, message: M, icon: M
 /// An error popup, except that the program exits after
 pub fn fatal_popup<T: AsRef<str>, M: AsRef<str>>(title: T, message: M) -> ! {
     display_popup(title, message, MessageBoxIcon::Error);
-    std::process::exit(1);
+    crate::exit::exit(1);
 }
 
 /// A warning popup, program DOES NOT exit
 pub fn warn_popup<T: AsRef<str>, M: AsRef<str>>(title: T, message: M) {
     display_popup(title, message, MessageBoxIcon::Warn);
 }
+
+/// A yes/no warning popup. Returns whether yes was picked, or `default` if popups are disabled
+pub fn confirm_popup<T: AsRef<str>, M: AsRef<str>>(title: T, message: M, default: bool) -> bool {
+    if POPUPS_DISABLED.load(Ordering::Relaxed) {
+        return default;
+    }
+
+    let h_title = HSTRING::from(title.as_ref());
+    let h_message = HSTRING::from(message.as_ref());
+
+    let title = PCWSTR::from_raw(h_title.as_ptr());
+    let message = PCWSTR::from_raw(h_message.as_ptr());
+
+    let res = unsafe { MessageBoxW(None, message, title, MB_ICONWARNING | MB_YESNO) };
+
+    res == IDYES
+}
@@ -0,0 +1,136 @@
+use std::{iter, os::windows::ffi::OsStrExt as _, path::Path, ptr};
+
+use tracing::trace;
+use windows::{
+    core::{Error, HRESULT, PCWSTR},
+    Win32::{
+        Foundation::{HANDLE, HWND, INVALID_HANDLE_VALUE},
+        Security::{
+            Cryptography::{CertGetNameStringW, CERT_NAME_SIMPLE_DISPLAY_TYPE},
+            WinTrust::{
+                WTHelperGetProvSignerFromChain, WTHelperProvDataFromStateData, WinVerifyTrust,
+                WINTRUST_ACTION_GENERIC_VERIFY_V2, WINTRUST_DATA, WINTRUST_DATA_0,
+                WINTRUST_FILE_INFO, WTD_CHOICE_FILE, WTD_REVOKE_NONE, WTD_STATEACTION_CLOSE,
+                WTD_STATEACTION_VERIFY, WTD_UI_NONE,
+            },
+        },
+    },
+};
+
+use crate::config::Core;
+
+/// The result of checking a file's Authenticode signature
+#[derive(Debug)]
+pub enum Signature {
+    /// Signed with a valid signature. Contains the signer's name, if it could be read
+    Valid(Option<String>),
+    /// Not signed, or the signature isn't valid. Contains the trust result
+    Invalid(String),
+}
+
+/// Why a plugin isn't trusted with `require_signed`, or None if it is
+pub fn untrusted_reason(path: &Path, core: &Core) -> Option<String> {
+    match verify(path) {
+        Signature::Valid(Some(signer)) if core.is_publisher_trusted(&signer) => {
+            trace!(%signer, path = %path.display(), "signed by a trusted publisher");
+            None
+        }
+
+        Signature::Valid(Some(signer)) => Some(format!(
+            "signed by \"{signer}\", who is not a trusted publisher"
+        )),
+
+        Signature::Valid(None) if core.trusted_publishers.is_empty() => None,
+
+        Signature::Valid(None) => Some("the signer could not be read".to_owned()),
+
+        Signature::Invalid(reason) => Some(reason),
+    }
+}
+
+/// Check a file's Authenticode signature with WinVerifyTrust
+pub fn verify(path: &Path) -> Signature {
+    let path_w = path
+        .as_os_str()
+        .encode_wide()
+        .chain(iter::once(0))
+        .collect::<Vec<_>>();
+
+    let mut file = WINTRUST_FILE_INFO {
+        cbStruct: size_of::<WINTRUST_FILE_INFO>() as u32,
+        pcwszFilePath: PCWSTR::from_raw(path_w.as_ptr()),
+        ..Default::default()
+    };
+
+    let mut data = WINTRUST_DATA {
+        cbStruct: size_of::<WINTRUST_DATA>() as u32,
+        dwUIChoice: WTD_UI_NONE,
+        // revocation checks need the network, and can stall the game for a long time without it
+        fdwRevocationChecks: WTD_REVOKE_NONE,
+        dwUnionChoice: WTD_CHOICE_FILE,
+        Anonymous: WINTRUST_DATA_0 { pFile: &mut file },
+        // keep the state around, so the signer can be read out of it
+        dwStateAction: WTD_STATEACTION_VERIFY,
+        ..Default::default()
+    };
+
+    let mut action = WINTRUST_ACTION_GENERIC_VERIFY_V2;
+    // no interactive user; never show any ui
+    let hwnd = HWND(INVALID_HANDLE_VALUE.0);
+
+    let res = unsafe { WinVerifyTrust(hwnd, &mut action, ptr::from_mut(&mut data).cast()) };
+
+    let signature = if res == 0 {
+        Signature::Valid(signer(data.hWVTStateData))
+    } else {
+        Signature::Invalid(Error::from_hresult(HRESULT(res)).message())
+    };
+
+    trace!(path = %path.display(), ?signature, "verified signature");
+
+    // frees the state
+    data.dwStateAction = WTD_STATEACTION_CLOSE;
+    _ = unsafe { WinVerifyTrust(hwnd, &mut action, ptr::from_mut(&mut data).cast()) };
+
+    signature
+}
+
+/// Get the name of whoever signed the file from the verified trust state
+fn signer(state: HANDLE) -> Option<String> {
+    let provider = unsafe { WTHelperProvDataFromStateData(state) };
+    if provider.is_null() {
+        return None;
+    }
+
+    let signer = unsafe { WTHelperGetProvSignerFromChain(provider, 0, false, 0) };
+    if signer.is_null() {
+        return None;
+    }
+
+    // the first cert in the chain is the signer's own
+    let len = unsafe { (*signer).csCertChain };
+    let chain = unsafe { (*signer).pasCertChain };
+    if len == 0 || chain.is_null() {
+        return None;
+    }
+
+    let cert = unsafe { (*chain).pCert };
+    if cert.is_null() {
+        return None;
+    }
+
+    // the returned size includes the null terminator
+    let size = unsafe { CertGetNameStringW(cert, CERT_NAME_SIMPLE_DISPLAY_TYPE, 0, None, None) };
+    if size <= 1 {
+        return None;
+    }
+
+    let mut buf = vec![0u16; size as usize];
+    let size =
+        unsafe { CertGetNameStringW(cert, CERT_NAME_SIMPLE_DISPLAY_TYPE, 0, None, Some(&mut buf)) };
+
+    let name = buf.get(..(size as usize).saturating_sub(1))?;
+    let name = String::from_utf16_lossy(name).trim().to_owned();
+
+    (!name.is_empty()).then_some(name)
+}
@@ -7,6 +7,41 @@ pub struct ThreadData {
     pub auth: u64,
     // log data
     pub log: LogData,
+    /// which plugins to load
+    pub phase: LoadPhase,
+    /// whether the tool was started with --quiet, so the loader doesn't ask anything
+    pub quiet: bool,
+    /// whether the tool was started with --force, so plugins are loaded regardless of `supported_game_versions`
+    pub force: bool,
+    /// whether the tool runs in portable mode, so the loader uses the plugins dir next to itself
+    pub portable: bool,
+    /// whether the tool was started with --no-config-writeback, so the loader doesn't write config.toml either
+    pub no_config_writeback: bool,
+}
+
+/// Plugins can be loaded in 2 phases, so that `suspended_plugins` get loaded before the game starts running.
+/// `late_plugins` are always loaded in a phase of their own, some time after the rest
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum LoadPhase {
+    /// load every plugin
+    All,
+    /// load only `suspended_plugins`, while the game's main thread is still suspended
+    Suspended,
+    /// load everything except `suspended_plugins`, after the game was resumed
+    Resumed,
+    /// load only `late_plugins`, once `late_plugin_delay` passed after the other plugins were loaded
+    Late,
+    /// load the plugins which aren't loaded yet, for the control pipe's `reinject` command
+    Reinject,
+}
+
+impl LoadPhase {
+    /// Whether this is the first phase to run in a process, which is the one that reports problems
+    /// with the plugins, so they aren't reported again by every phase
+    pub fn is_first(self) -> bool {
+        matches!(self, Self::All | Self::Suspended)
+    }
 }
 
 #[repr(C)]
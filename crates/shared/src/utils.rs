@@ -3,7 +3,13 @@ use std::{
     sync::{Mutex, MutexGuard},
 };
 
-use windows::{core::Free, Win32::Foundation::HANDLE};
+use windows::{
+    core::Free,
+    Win32::{
+        Foundation::{FILETIME, HANDLE, MAX_PATH},
+        System::Threading::GetProcessTimes,
+    },
+};
 
 #[repr(transparent)]
 #[derive(Debug, Default)]
@@ -45,6 +51,21 @@ impl Drop for OwnedHandle {
     }
 }
 
+/// When a process was created. Pids get reused, but a pid together with its creation time
+/// always names the same process
+pub fn process_creation_time(process: HANDLE) -> windows::core::Result<u64> {
+    let mut created = FILETIME::default();
+    let mut exited = FILETIME::default();
+    let mut kernel = FILETIME::default();
+    let mut user = FILETIME::default();
+
+    unsafe {
+        GetProcessTimes(process, &mut created, &mut exited, &mut kernel, &mut user)?;
+    }
+
+    Ok((u64::from(created.dwHighDateTime) << 32) | u64::from(created.dwLowDateTime))
+}
+
 pub trait SuperLock<T> {
     fn super_lock(&self) -> MutexGuard<T>;
 }
@@ -61,6 +82,67 @@ impl<T> SuperLock<T> for Mutex<T> {
     }
 }
 
+/// Case-insensitive glob match of the whole `text`. `*` matches any amount of characters (including none),
+/// and `?` matches exactly one character. There is no escaping
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern = pattern.to_lowercase().chars().collect::<Vec<_>>();
+    let text = text.to_lowercase().chars().collect::<Vec<_>>();
+
+    let (mut p, mut t) = (0, 0);
+    // where to resume from if the current attempt fails: (pattern idx after the last `*`, text idx it matched up to)
+    let mut backtrack = None;
+
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                p += 1;
+                backtrack = Some((p, t));
+            }
+
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+
+            // let the last `*` swallow one more character, and try again
+            _ => match backtrack {
+                Some((bp, bt)) => {
+                    p = bp;
+                    t = bt + 1;
+                    backtrack = Some((bp, bt + 1));
+                }
+
+                None => return false,
+            },
+        }
+    }
+
+    // any leftover pattern can only match the empty remainder if it's all `*`
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Fill `buf` with a path from a winapi function, growing it until the whole path fits, so paths
+/// longer than MAX_PATH aren't cut off. An empty `buf` starts out at MAX_PATH
+///
+/// `fill` is given the whole buffer, and returns how many chars it wrote, or None if the buffer is too small.
+/// Filling all but the last char may also mean the path was truncated, since some of these functions
+/// truncate to make room for the null terminator, so that's retried with a bigger buffer as well
+pub fn fill_path_buf<E>(
+    buf: &mut Vec<u16>,
+    mut fill: impl FnMut(&mut [u16]) -> Result<Option<usize>, E>,
+) -> Result<usize, E> {
+    if buf.is_empty() {
+        buf.resize(MAX_PATH as usize, 0);
+    }
+
+    loop {
+        match fill(buf)? {
+            Some(len) if len + 1 < buf.len() => return Ok(len),
+            _ => buf.resize(buf.len() * 2, 0),
+        }
+    }
+}
+
 /// Poor mans try {} blocks
 #[macro_export]
 macro_rules! tri {
@@ -73,3 +155,122 @@ macro_rules! tri {
     };
 }
 pub use tri;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Like GetModuleFileNameW, which truncates to the buffer size
+    fn truncating(path: &[u16]) -> impl FnMut(&mut [u16]) -> Result<Option<usize>, ()> + '_ {
+        |buf| {
+            let len = path.len().min(buf.len());
+            buf[..len].copy_from_slice(&path[..len]);
+            Ok(Some(len))
+        }
+    }
+
+    #[test]
+    fn fills_long_paths() {
+        let path = format!(r"C:\{}\Plugin.dll", r"nested\".repeat(100))
+            .encode_utf16()
+            .collect::<Vec<_>>();
+        assert!(path.len() > MAX_PATH as usize);
+
+        let mut buf = Vec::new();
+        let len = fill_path_buf(&mut buf, truncating(&path)).unwrap();
+        assert_eq!(&buf[..len], path);
+    }
+
+    #[test]
+    fn grows_on_insufficient_buffer() {
+        let path = vec![b'a' as u16; 1000];
+
+        let mut buf = Vec::new();
+        let len = fill_path_buf(&mut buf, |buf| {
+            if buf.len() <= path.len() {
+                return Ok::<_, ()>(None);
+            }
+
+            buf[..path.len()].copy_from_slice(&path);
+            Ok(Some(path.len()))
+        })
+        .unwrap();
+
+        assert_eq!(&buf[..len], path);
+    }
+
+    #[test]
+    fn fills_short_paths_once() {
+        let path = r"C:\Plugin.dll".encode_utf16().collect::<Vec<_>>();
+
+        let mut calls = 0;
+        let mut buf = Vec::new();
+        let len = fill_path_buf(&mut buf, |buf| {
+            calls += 1;
+            truncating(&path)(buf)
+        })
+        .unwrap();
+
+        assert_eq!(&buf[..len], path);
+        assert_eq!(calls, 1);
+        assert_eq!(buf.len(), MAX_PATH as usize);
+    }
+
+    #[test]
+    fn passes_errors_through() {
+        let mut buf = Vec::new();
+        let res = fill_path_buf(&mut buf, |_| Err::<Option<usize>, _>("failed"));
+        assert_eq!(res, Err("failed"));
+    }
+
+    #[test]
+    fn glob_star() {
+        assert!(glob_match("*", "Plugin.dll"));
+        assert!(glob_match("*.dll", "Plugin.dll"));
+        assert!(glob_match("Plug*", "Plugin.dll"));
+        assert!(glob_match("P*n.dll", "Plugin.dll"));
+        assert!(glob_match("*lug*.*", "Plugin.dll"));
+        // none at all
+        assert!(glob_match("Plugin*.dll", "Plugin.dll"));
+        // backtracks past an earlier partial match
+        assert!(glob_match("*in.dll", "PluginMain.dll"));
+
+        assert!(!glob_match("*.dll", "Plugin.exe"));
+        assert!(!glob_match("Plug*", "MyPlugin.dll"));
+    }
+
+    #[test]
+    fn glob_question_mark() {
+        assert!(glob_match("Plugin?.dll", "Plugin1.dll"));
+        assert!(glob_match("???", "abc"));
+
+        // exactly one
+        assert!(!glob_match("Plugin?.dll", "Plugin.dll"));
+        assert!(!glob_match("Plugin?.dll", "Plugin12.dll"));
+        assert!(!glob_match("???", "ab"));
+    }
+
+    #[test]
+    fn glob_is_case_insensitive() {
+        assert!(glob_match("plugin.DLL", "PLUGIN.dll"));
+        assert!(glob_match("*.DLL", "plugin.dll"));
+        assert!(glob_match("ÄB?", "äbc"));
+    }
+
+    #[test]
+    fn glob_matches_whole_text() {
+        assert!(!glob_match("Plugin", "Plugin.dll"));
+        assert!(!glob_match("lugin.dll", "Plugin.dll"));
+    }
+
+    #[test]
+    fn glob_empty() {
+        assert!(glob_match("", ""));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("**", ""));
+
+        assert!(!glob_match("", "Plugin.dll"));
+        assert!(!glob_match("?", ""));
+        assert!(!glob_match("a", ""));
+    }
+}
@@ -0,0 +1,197 @@
+use std::{ffi::c_void, fmt::Display, os::windows::prelude::OsStrExt as _, path::Path, ptr, slice};
+
+use tracing::trace;
+use windows::{
+    core::{HSTRING, PCWSTR},
+    Win32::Storage::FileSystem::{
+        GetFileVersionInfoSizeW, GetFileVersionInfoW, VerQueryValueW, VS_FIXEDFILEINFO,
+    },
+};
+
+use crate::paths::to_extended_path;
+
+/// The numeric file version from VS_FIXEDFILEINFO (major.minor.build.revision)
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct FileVersion(pub u16, pub u16, pub u16, pub u16);
+
+impl Display for FileVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let Self(major, minor, build, revision) = self;
+        write!(f, "{major}.{minor}.{build}.{revision}")
+    }
+}
+
+impl FileVersion {
+    /// Parse `major[.minor[.build[.revision]]]`. Missing parts are filled in with `fill`
+    fn parse(s: &str, fill: u16) -> Option<Self> {
+        let mut parts = [fill; 4];
+
+        let mut count = 0;
+        for (i, part) in s.trim().split('.').enumerate() {
+            *parts.get_mut(i)? = part.trim().parse().ok()?;
+            count += 1;
+        }
+
+        if count == 0 {
+            return None;
+        }
+
+        let [major, minor, build, revision] = parts;
+        Some(Self(major, minor, build, revision))
+    }
+}
+
+/// An inclusive range of versions, written as `min-max`. Either side may be left out for an open range,
+/// and partial versions are allowed, e.g. `4.1-` or `4.1.1.5000-4.1.1.6999`. A single version on its own
+/// matches only that version (or every version starting with it, if partial)
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct VersionRange {
+    pub min: FileVersion,
+    pub max: FileVersion,
+}
+
+impl VersionRange {
+    pub fn parse(s: &str) -> Option<Self> {
+        let (min, max) = s.split_once('-').unwrap_or((s, s));
+
+        let min = match min.trim() {
+            "" => FileVersion(0, 0, 0, 0),
+            v => FileVersion::parse(v, 0)?,
+        };
+
+        let max = match max.trim() {
+            "" => FileVersion(u16::MAX, u16::MAX, u16::MAX, u16::MAX),
+            v => FileVersion::parse(v, u16::MAX)?,
+        };
+
+        Some(Self { min, max })
+    }
+
+    pub fn contains(&self, version: FileVersion) -> bool {
+        (self.min..=self.max).contains(&version)
+    }
+}
+
+impl Display for VersionRange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}-{}", self.min, self.max)
+    }
+}
+
+/// Data read out of a PE file's embedded VERSIONINFO resource
+#[derive(Debug, Clone, Default)]
+pub struct VersionInfo {
+    pub product_name: Option<String>,
+    pub file_version: Option<String>,
+    pub company_name: Option<String>,
+    pub fixed_version: Option<FileVersion>,
+    /// The game versions a plugin declares it's compatible with, from the custom `BG3GameVersions` string
+    pub game_versions: Option<String>,
+}
+
+impl VersionInfo {
+    /// Returns None if the file has no version resource (or it couldn't be read)
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Option<Self> {
+        let path = to_extended_path(path);
+        let path = path
+            .as_os_str()
+            .encode_wide()
+            .chain(std::iter::once(0))
+            .collect::<Vec<_>>();
+        let path = PCWSTR::from_raw(path.as_ptr());
+
+        let size = unsafe { GetFileVersionInfoSizeW(path, None) };
+        if size == 0 {
+            return None;
+        }
+
+        let mut data = vec![0u8; size as usize];
+        unsafe {
+            GetFileVersionInfoW(path, None, size, data.as_mut_ptr().cast()).ok()?;
+        }
+
+        let fixed_version = query(&data, r"\").and_then(|v| {
+            if v.len() < size_of::<VS_FIXEDFILEINFO>() {
+                return None;
+            }
+
+            let info = unsafe { ptr::read_unaligned(v.as_ptr().cast::<VS_FIXEDFILEINFO>()) };
+
+            Some(FileVersion(
+                (info.dwFileVersionMS >> 16) as u16,
+                info.dwFileVersionMS as u16,
+                (info.dwFileVersionLS >> 16) as u16,
+                info.dwFileVersionLS as u16,
+            ))
+        });
+
+        // [lang: u16, codepage: u16]; use the first available translation
+        let translation = query(&data, r"\VarFileInfo\Translation").and_then(|v| {
+            let lang = u16::from_le_bytes(v.get(0..2)?.try_into().ok()?);
+            let codepage = u16::from_le_bytes(v.get(2..4)?.try_into().ok()?);
+            Some(format!("{lang:04x}{codepage:04x}"))
+        });
+
+        let string = |name: &str| {
+            let translation = translation.as_ref()?;
+            let v = query(&data, &format!(r"\StringFileInfo\{translation}\{name}"))?;
+
+            let v = v
+                .chunks_exact(2)
+                .map(|c| u16::from_le_bytes([c[0], c[1]]))
+                .take_while(|&c| c != 0)
+                .collect::<Vec<_>>();
+
+            let v = String::from_utf16_lossy(&v).trim().to_owned();
+            (!v.is_empty()).then_some(v)
+        };
+
+        let info = Self {
+            product_name: string("ProductName"),
+            file_version: string("FileVersion"),
+            company_name: string("CompanyName"),
+            fixed_version,
+            game_versions: string("BG3GameVersions"),
+        };
+
+        trace!(?info, "read version info");
+
+        Some(info)
+    }
+}
+
+/// Query a value from a version info block. The returned slice borrows `data`
+fn query<'a>(data: &'a [u8], sub_block: &str) -> Option<&'a [u8]> {
+    // string values report their len in u16 chars, everything else in bytes
+    let is_string = sub_block.starts_with(r"\StringFileInfo");
+    let sub_block = HSTRING::from(sub_block);
+
+    let mut buf: *mut c_void = ptr::null_mut();
+    let mut len = 0u32;
+
+    let res = unsafe {
+        VerQueryValueW(
+            data.as_ptr().cast(),
+            PCWSTR::from_raw(sub_block.as_ptr()),
+            &mut buf,
+            &mut len,
+        )
+    };
+
+    if !res.as_bool() || buf.is_null() || len == 0 {
+        return None;
+    }
+
+    let byte_len = if is_string {
+        len as usize * size_of::<u16>()
+    } else {
+        len as usize
+    };
+
+    // the returned pointer always points inside of `data`, but keep the slice in bounds regardless
+    let offset = (buf as usize).checked_sub(data.as_ptr() as usize)?;
+    let byte_len = byte_len.min(data.len().checked_sub(offset)?);
+
+    let v = unsafe { slice::from_raw_parts(buf.cast::<u8>(), byte_len) };
+    Some(v)
+}
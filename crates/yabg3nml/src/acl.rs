@@ -0,0 +1,148 @@
+use std::{path::Path, ptr};
+
+use eyre::{bail, Result};
+use shared::utils::OwnedHandle;
+use windows::{
+    core::{HSTRING, PWSTR},
+    Win32::{
+        Foundation::{LocalFree, BOOL, ERROR_INSUFFICIENT_BUFFER, HLOCAL},
+        Security::{
+            Authorization::{
+                ConvertSidToStringSidW, ConvertStringSecurityDescriptorToSecurityDescriptorW,
+                SetNamedSecurityInfoW, SDDL_REVISION_1, SE_FILE_OBJECT,
+            },
+            GetSecurityDescriptorDacl, GetTokenInformation, TokenUser, ACL,
+            DACL_SECURITY_INFORMATION, PROTECTED_DACL_SECURITY_INFORMATION, PSECURITY_DESCRIPTOR,
+            TOKEN_QUERY, TOKEN_USER,
+        },
+        System::Threading::{GetCurrentProcess, OpenProcessToken},
+    },
+};
+
+use crate::utils::PSecurityDescriptor;
+
+/// The SID of the LocalSystem account, which services run as
+const LOCAL_SYSTEM_SID: &str = "S-1-5-18";
+
+/// Replace a file's permissions so only the current user, administrators, and SYSTEM can change it. Everyone
+/// else can still read and run it. Inherited permissions are removed, so they can't grant more
+///
+/// Returns false without changing anything when running as LocalSystem (e.g. as the service), since
+/// that would leave the file to SYSTEM and administrators only. `icacls <path> /reset` undoes this
+pub fn restrict_to_current_user(path: &Path) -> Result<bool> {
+    let sid = current_user_sid()?;
+
+    if sid == LOCAL_SYSTEM_SID {
+        return Ok(false);
+    }
+
+    // FA = full access, FRFX = read and execute, SY = SYSTEM, BA = administrators, WD = everyone
+    let sddl = HSTRING::from(format!(
+        "D:P(A;;FA;;;{sid})(A;;FA;;;SY)(A;;FA;;;BA)(A;;FRFX;;;WD)"
+    ));
+
+    let mut psec_desc: PSecurityDescriptor = PSECURITY_DESCRIPTOR::default().into();
+    unsafe {
+        ConvertStringSecurityDescriptorToSecurityDescriptorW(
+            &sddl,
+            SDDL_REVISION_1,
+            psec_desc.as_mut(),
+            None,
+        )?;
+    }
+
+    let mut present = BOOL::default();
+    let mut defaulted = BOOL::default();
+    let mut dacl: *mut ACL = ptr::null_mut();
+    unsafe {
+        GetSecurityDescriptorDacl(
+            PSECURITY_DESCRIPTOR(psec_desc.as_void()),
+            &mut present,
+            &mut dacl,
+            &mut defaulted,
+        )?;
+    }
+
+    let res = unsafe {
+        SetNamedSecurityInfoW(
+            &HSTRING::from(path),
+            SE_FILE_OBJECT,
+            DACL_SECURITY_INFORMATION | PROTECTED_DACL_SECURITY_INFORMATION,
+            None,
+            None,
+            Some(dacl),
+            None,
+        )
+    };
+
+    res.ok()?;
+
+    Ok(true)
+}
+
+/// A security descriptor which only gives the current user, administrators, and SYSTEM access, e.g. for a pipe
+pub fn current_user_only() -> Result<PSecurityDescriptor> {
+    let sid = current_user_sid()?;
+
+    // GA = full access, SY = SYSTEM, BA = administrators
+    let sddl = HSTRING::from(format!("D:P(A;;GA;;;{sid})(A;;GA;;;SY)(A;;GA;;;BA)"));
+
+    let mut psec_desc: PSecurityDescriptor = PSECURITY_DESCRIPTOR::default().into();
+    unsafe {
+        ConvertStringSecurityDescriptorToSecurityDescriptorW(
+            &sddl,
+            SDDL_REVISION_1,
+            psec_desc.as_mut(),
+            None,
+        )?;
+    }
+
+    Ok(psec_desc)
+}
+
+/// The current user's SID, like S-1-5-21-...
+fn current_user_sid() -> Result<String> {
+    // a pseudo handle, which must not be closed
+    let process = unsafe { GetCurrentProcess() };
+
+    let mut token = OwnedHandle::default();
+    unsafe {
+        OpenProcessToken(process, TOKEN_QUERY, token.as_mut())?;
+    }
+
+    // the sid is variable length, so the size has to be asked for first
+    let mut len = 0;
+    let res = unsafe { GetTokenInformation(token.as_raw_handle(), TokenUser, None, 0, &mut len) };
+    if let Err(e) = res {
+        if e.code() != ERROR_INSUFFICIENT_BUFFER.to_hresult() {
+            return Err(e.into());
+        }
+    }
+
+    // u64s, so it's aligned enough for TOKEN_USER
+    let mut buf = vec![0u64; (len as usize).div_ceil(size_of::<u64>())];
+    unsafe {
+        GetTokenInformation(
+            token.as_raw_handle(),
+            TokenUser,
+            Some(buf.as_mut_ptr().cast()),
+            len,
+            &mut len,
+        )?;
+    }
+
+    let user = unsafe { &*buf.as_ptr().cast::<TOKEN_USER>() };
+
+    let mut sid = PWSTR::null();
+    unsafe {
+        ConvertSidToStringSidW(user.User.Sid, &mut sid)?;
+    }
+
+    let string = unsafe { sid.to_string() };
+    _ = unsafe { LocalFree(HLOCAL(sid.0.cast()).into()) };
+
+    match string {
+        Ok(sid) => Ok(sid),
+        Err(e) => bail!("user sid is not valid utf16: {e}"),
+    }
+}
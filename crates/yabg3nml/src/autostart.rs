@@ -1,28 +1,37 @@
 use std::{
     collections::VecDeque,
     env,
-    os::windows::process::{CommandExt as _, ExitCodeExt as _},
+    os::windows::{io::AsRawHandle as _, process::ExitCodeExt as _},
     path::Path,
-    process::{Command, ExitCode},
+    process::{Child, ExitCode},
+    sync::atomic::Ordering,
     thread,
 };
 
 use eyre::{eyre, Result};
-use shared::popup::fatal_popup;
-use tracing::{error, trace};
-
-use windows::Win32::System::{
-    Diagnostics::Debug::DebugActiveProcessStop,
-    Threading::{DEBUG_ONLY_THIS_PROCESS, DEBUG_PROCESS},
+use shared::{
+    config::{quiet_from_env, Config},
+    popup::{fatal_popup, warn_popup},
+    thread_data::LoadPhase,
 };
+use tracing::{error, info, trace, warn};
+use unicase::UniCase;
+use windows::Win32::Foundation::HANDLE;
+use winreg::{enums::HKEY_LOCAL_MACHINE, RegKey};
 
 use crate::{
+    cli::Args,
     event::Event,
-    loader::run_loader,
+    event_log,
+    job::KillOnCloseJob,
+    launch::{game_command, game_creation_flags, stop_debugging},
+    loader::{self, run_loader},
     paths::{get_game_binary_for, Bg3Exe},
-    setup::init,
+    process_watcher::Pid,
+    setup::{init, log_banner, set_portable},
     single_instance::SingleInstance,
-    wapi::event_loop::EventLoop,
+    tmp_loader::Loader,
+    wapi::{event_loop::EventLoop, resume_process::ResumeProcessRs},
 };
 
 pub fn autostart() -> Result<ExitCode> {
@@ -30,11 +39,26 @@ pub fn autostart() -> Result<ExitCode> {
     let _singleton = SingleInstance::new();
     let _event = Event::new()?;
 
-    let mut init = init()?;
+    // args are passed through to the game, so only the portable marker can enable portable mode
+    set_portable(false);
+
+    // args are passed through to the game, so none of them are ours
+    let mut init = init(&Args::default())?;
     let _loader_lock = init.loader.file.take();
     let _worker_guard = init.worker.take();
 
+    log_banner(init.config, "autostart");
+
+    // args are the game's, so this is the only way to be quiet
+    let quiet = quiet_from_env();
+    if quiet {
+        info!("--quiet was enabled by the environment variable YABG3NML_QUIET");
+    }
+
+    loader::QUIET.store(quiet, Ordering::Relaxed);
+
     // [this_exe_path, bg3_exe_path, ..args]
+    info!(argv = ?env::args().collect::<Vec<_>>(), "Autostart invoked");
     let mut args = env::args().skip(1).collect::<VecDeque<_>>();
 
     let bg3_exe = {
@@ -48,8 +72,15 @@ pub fn autostart() -> Result<ExitCode> {
         bg3_exe
     };
 
+    check_invocation(&bg3_exe);
+
     let exe: Bg3Exe = Path::new(&bg3_exe).into();
     let Some(bg3_path) = get_game_binary_for(exe, init.config) else {
+        error!(
+            target = bg3_exe,
+            "target is not named like a game exe; name match failed"
+        );
+
         // it's not a bg3 executable; or at least, it's not named correctly
         fatal_popup(
             "No direct launch",
@@ -57,15 +88,42 @@ pub fn autostart() -> Result<ExitCode> {
         )
     };
 
+    info!(target = bg3_exe, mode = %exe, path = %bg3_path.display(), "Name match succeeded; resolved game exe");
     trace!(mode = %exe, ?args, "launching bg3");
     trace!(env = ?env::vars());
 
-    let cmd = Command::new(bg3_path)
-        .args(args)
-        // bypass IFEO on this launch
-        .creation_flags(DEBUG_PROCESS.0 | DEBUG_ONLY_THIS_PROCESS.0)
-        .envs(env::vars())
-        .spawn();
+    // some plugins must be loaded before the game's main thread runs
+    let two_phase = init.config.core.has_suspended_plugins();
+
+    let kill_with_loader = init.config.core.kill_game_with_loader;
+    // the game must not run before it's in the job, otherwise anything it spawns in the meantime escapes it
+    let suspended = two_phase || kill_with_loader;
+
+    // the game can't find its data when started from the wrong folder
+    let working_dir = init
+        .config
+        .core
+        .game_working_dir
+        .clone()
+        .or_else(|| bg3_path.parent().map(Path::to_path_buf));
+
+    if let Some(dir) = &working_dir {
+        info!(dir = %dir.display(), "Starting game in working dir");
+    }
+
+    info!(
+        flags = %format!("0x{:X}", game_creation_flags(suspended)),
+        suspended,
+        "Creating game process as its debugger"
+    );
+
+    // bypass IFEO on this launch
+    let mut cmd = game_command(&bg3_path, suspended);
+    if let Some(dir) = working_dir {
+        cmd.current_dir(dir);
+    }
+
+    let cmd = cmd.args(args).envs(env::vars()).spawn();
 
     let mut child = match cmd {
         Ok(v) => v,
@@ -78,21 +136,56 @@ pub fn autostart() -> Result<ExitCode> {
     };
 
     let pid = child.id();
+    info!(pid, "Spawned game");
+
     // stop debugging
-    if let Err(e) = unsafe { DebugActiveProcessStop(pid) } {
+    if let Err(e) = stop_debugging(pid) {
         fatal_popup(
             "DebugActiveProcessStop failed",
             format!("DebugActiveProcessStop failed: {e}"),
         );
     }
 
-    let res = run_loader(init.config, pid, &init.loader, false, true);
-    if let Err(e) = res {
-        error!(err = %e, "run_loader failed");
-        fatal_popup(
-            "run loader failed",
-            format!("run_loader unexpectedly failed. You should report this.\n\nError: {e}"),
-        );
+    // assigned only after debugging stopped, so the debug attachment has nothing to do with the job.
+    // kept alive until we exit; closing it kills the game
+    let _job = if kill_with_loader {
+        let job = KillOnCloseJob::new().and_then(|job| {
+            job.assign(HANDLE(child.as_raw_handle()))?;
+            Ok(job)
+        });
+
+        match job {
+            Ok(job) => {
+                info!(
+                    pid,
+                    "Assigned game to job; it will be closed when this tool exits"
+                );
+                Some(job)
+            }
+
+            Err(e) => {
+                warn!(%e, pid, "failed to assign game to job; it will keep running if this tool exits");
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    if two_phase {
+        info!("Loading suspended plugins before the game starts");
+        load(init.config, pid, &init.loader, LoadPhase::Suspended);
+
+        resume(&child);
+
+        info!("Game resumed; loading the rest of the plugins");
+        load(init.config, pid, &init.loader, LoadPhase::Resumed);
+    } else {
+        if suspended {
+            resume(&child);
+        }
+
+        load(init.config, pid, &init.loader, LoadPhase::All);
     }
 
     // just put something here to stop the needless busy cursor
@@ -116,3 +209,77 @@ pub fn autostart() -> Result<ExitCode> {
         }
     }
 }
+
+/// Windows runs the `debugger` value of this key instead of the exe, with the exe's full path as the first arg
+const IFEO_KEY: &str = r"SOFTWARE\Microsoft\Windows NT\CurrentVersion\Image File Execution Options";
+
+/// Warn when this looks like it wasn't started through IFEO, which is the only way autostart is meant to be run.
+/// Only ever logs; the name match decides whether to continue
+fn check_invocation(target: &str) {
+    let target = Path::new(target);
+
+    // IFEO always passes the full path of the exe it intercepted
+    if !target.is_absolute() {
+        warn!(target = %target.display(), "target is not a full path; autostart was likely started directly instead of through IFEO");
+    }
+
+    let Some(file_name) = target.file_name() else {
+        return;
+    };
+
+    let key = format!(r"{IFEO_KEY}\{}", file_name.to_string_lossy());
+    let debugger = RegKey::predef(HKEY_LOCAL_MACHINE)
+        .open_subkey(&key)
+        .and_then(|k| k.get_value::<String, _>("debugger"));
+
+    let debugger = match debugger {
+        Ok(debugger) => debugger,
+        Err(e) => {
+            warn!(%e, key = %format!(r"HKLM\{key}"), "no IFEO debugger is registered for the target; was autostart started directly, or the installer not run?");
+            return;
+        }
+    };
+
+    let is_us = env::current_exe().is_ok_and(|exe| {
+        UniCase::new(exe.to_string_lossy().as_ref()) == UniCase::new(debugger.trim_matches('"'))
+    });
+
+    if is_us {
+        info!(debugger, "Invoked through IFEO");
+    } else {
+        warn!(debugger, "the IFEO debugger registered for the target is not this exe; autostart was likely started directly, or the installer was run from another folder");
+    }
+}
+
+fn resume(child: &Child) {
+    if let Err(e) = ResumeProcessRs(child) {
+        error!(%e, "failed to resume game");
+        fatal_popup(
+            "Resume failure",
+            format!("Failed to resume the suspended game process.\n\nError: {e}"),
+        );
+    }
+}
+
+fn load(config: &Config, pid: Pid, loader: &Loader, phase: LoadPhase) {
+    let res = run_loader(config, pid, loader, None, false, true, phase);
+    if let Err(e) = res {
+        error!(err = %e, "run_loader failed");
+
+        let message =
+            format!("run_loader unexpectedly failed. You should report this.\n\nError: {e}");
+        event_log::report("run loader failed", &message);
+
+        // the game is already running; leave it be instead of exiting
+        if config.core.autostart_allow_unmodded {
+            warn!("continuing without plugins, since autostart_allow_unmodded is enabled");
+            warn_popup(
+                "run loader failed",
+                format!("{message}\n\nThe game will keep running without plugins."),
+            );
+            return;
+        }
+
+        fatal_popup("run loader failed", message);
+    }
+}
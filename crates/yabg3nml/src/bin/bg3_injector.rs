@@ -4,7 +4,7 @@ use shared::popup::fatal_popup;
 use yabg3nml::RunType;
 
 fn main() {
-    if let Err(e) = yabg3nml::run(RunType::Injector) {
+    if let Err(e) = yabg3nml::run(RunType::Injector, argh::from_env()) {
         fatal_popup("injector failure", e.to_string());
     }
 }
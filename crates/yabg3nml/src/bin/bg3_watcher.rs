@@ -4,7 +4,7 @@ use shared::popup::fatal_popup;
 use yabg3nml::RunType;
 
 fn main() {
-    if let Err(e) = yabg3nml::run(RunType::Watcher) {
+    if let Err(e) = yabg3nml::run(RunType::Watcher, argh::from_env()) {
         fatal_popup("watcher failure", e.to_string());
     }
 }
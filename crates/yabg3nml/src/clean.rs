@@ -0,0 +1,95 @@
+use std::{
+    env,
+    fmt::{self, Display},
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use shared::paths::{get_bg3_plugins_dir, get_plugin_overrides_dir, get_staging_dir};
+
+#[derive(Debug, Default)]
+pub struct CleanSummary {
+    pub removed: Vec<PathBuf>,
+    pub failed: Vec<(PathBuf, io::Error)>,
+}
+
+impl CleanSummary {
+    fn remove(&mut self, path: &Path) {
+        if !path.exists() {
+            return;
+        }
+
+        let res = if path.is_dir() {
+            fs::remove_dir_all(path)
+        } else {
+            fs::remove_file(path)
+        };
+
+        match res {
+            Ok(_) => self.removed.push(path.to_owned()),
+            Err(e) => self.failed.push((path.to_owned(), e)),
+        }
+    }
+
+    /// Remove everything inside of `dir`, but not `dir` itself
+    fn remove_contents(&mut self, dir: &Path) {
+        let Ok(read_dir) = fs::read_dir(dir) else {
+            return;
+        };
+
+        for entry in read_dir.flatten() {
+            self.remove(&entry.path());
+        }
+    }
+}
+
+impl Display for CleanSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.removed.is_empty() && self.failed.is_empty() {
+            return writeln!(f, "Nothing to clean");
+        }
+
+        if !self.removed.is_empty() {
+            writeln!(f, "Removed:")?;
+            for path in &self.removed {
+                writeln!(f, "  {}", path.display())?;
+            }
+        }
+
+        if !self.failed.is_empty() {
+            writeln!(
+                f,
+                "Failed to remove (they may be in use by a running game):"
+            )?;
+            for (path, e) in &self.failed {
+                writeln!(f, "  {}: {e}", path.display())?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Remove temporary state: staged plugins, leftover `--plugin` lists, and if `logs` is set, all log files
+pub fn clean(logs: bool) -> CleanSummary {
+    let mut summary = CleanSummary::default();
+
+    // staged plugin copies of every game process
+    summary.remove(&get_staging_dir());
+
+    // --plugin lists of game processes which never read them
+    if let Ok(dir) = get_plugin_overrides_dir() {
+        summary.remove(&dir);
+    }
+
+    if logs {
+        if let Ok(plugins_dir) = get_bg3_plugins_dir() {
+            summary.remove_contents(&plugins_dir.join("logs"));
+        }
+
+        // fallback location used when the logs dir isn't writable
+        summary.remove(&env::temp_dir().join("yabg3nml-logs"));
+    }
+
+    summary
+}
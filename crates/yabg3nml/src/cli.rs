@@ -1,8 +1,101 @@
+use std::path::PathBuf;
+
 use argh::FromArgs;
 
+use crate::completions::Shell;
+
 /// A simple, non-invasive BG3 native mod loader
-#[derive(Default, FromArgs)]
+#[derive(Debug, Default, FromArgs)]
 pub struct Args {
+    /// don't show informational popups
+    #[argh(switch)]
+    pub quiet: bool,
+
+    /// on first run, create the plugins folder and config without showing the setup popup
+    #[argh(switch)]
+    pub no_first_run_prompt: bool,
+
+    /// with the watcher, launch the game if it isn't already running
+    #[argh(switch)]
+    pub launch: bool,
+
+    /// never write config.toml or its backup, e.g. when it's managed elsewhere. a missing config uses the defaults
+    #[argh(switch)]
+    pub no_config_writeback: bool,
+
+    /// keep the plugins folder, config, and logs next to this exe instead of in AppData.
+    /// also enabled by a `portable.txt` file next to the exe
+    #[argh(switch)]
+    pub portable: bool,
+
+    /// show a live status line in the console instead of the tray icon, with the watched games and how the
+    /// last injection went
+    #[argh(switch)]
+    pub foreground: bool,
+
+    /// don't show the tray icon. stop it with Ctrl+C, or the control pipe's stop command
+    #[argh(switch)]
+    pub no_tray: bool,
+
+    /// load plugins into the game even if its version is not in `supported_game_versions`
+    #[argh(switch)]
+    pub force: bool,
+
+    /// a plugin dll to load; can be repeated. when given, only these are loaded, and the plugins
+    /// folder is not searched. `disabled_plugins` doesn't apply to them
+    #[argh(option)]
+    pub plugin: Vec<PathBuf>,
+
+    /// use this loader dll instead of the loader.dll next to this exe. it is not hash checked,
+    /// so only use a dll you trust. overrides `loader_path`
+    #[argh(option)]
+    pub loader: Option<PathBuf>,
+
+    /// remove staged plugin copies and leftover --plugin lists, then exit
+    #[argh(switch)]
+    pub clean: bool,
+
+    /// with --clean, remove all log files too
+    #[argh(switch)]
+    pub clean_logs: bool,
+
+    /// print the resolved install_root, game exes, plugins and logs dirs, and loader path, then exit
+    #[argh(switch)]
+    pub show_paths: bool,
+
+    /// check every plugin for problems which would stop it from loading, print the results, then exit
+    #[argh(switch)]
+    pub validate_plugins: bool,
+
+    /// bundle the config, newest logs, plugin checks and hashes, and version info into a zip for
+    /// bug reports, then exit. see the readme for exactly what's included
+    #[argh(option)]
+    pub collect_diagnostics: Option<PathBuf>,
+
+    /// replace config.toml with the last config that loaded successfully, then exit
+    #[argh(switch)]
+    pub restore_config: bool,
+
+    /// register the watcher as a windows service which starts at boot, then exit. requires admin
+    #[argh(switch)]
+    pub install_service: bool,
+
+    /// stop and remove the windows service, then exit. requires admin
+    #[argh(switch)]
+    pub uninstall_service: bool,
+
+    /// run as a windows service. only used by the service control manager. implies --no-tray and --quiet
+    #[argh(switch)]
+    pub run_as_service: bool,
+
+    /// use this local AppData folder instead of the current user's. set by --install-service
+    #[argh(option, hidden_help)]
+    pub local_appdata: Option<PathBuf>,
+
+    /// print a shell completion script (bash, zsh, powershell) to stdout and exit
+    #[argh(option, hidden_help)]
+    pub generate_completions: Option<Shell>,
+
     /// binary to test inject
     #[cfg(feature = "test-injection")]
     #[argh(option)]
@@ -0,0 +1,111 @@
+use std::{env, fmt::Write as _, str::FromStr};
+
+use argh::FromArgs as _;
+use eyre::{OptionExt as _, Result};
+
+use crate::cli::Args;
+
+#[derive(Debug, Copy, Clone)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    PowerShell,
+}
+
+impl FromStr for Shell {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match &*s.to_ascii_lowercase() {
+            "bash" => Ok(Self::Bash),
+            "zsh" => Ok(Self::Zsh),
+            "powershell" | "pwsh" => Ok(Self::PowerShell),
+            _ => Err(format!(
+                "unknown shell `{s}`; expected one of: bash, zsh, powershell"
+            )),
+        }
+    }
+}
+
+/// Generate a completion script for the current binary
+pub fn generate(shell: Shell) -> Result<String> {
+    let exe = env::current_exe()?;
+    let name = exe
+        .file_stem()
+        .ok_or_eyre("filename not found")?
+        .to_string_lossy()
+        .into_owned();
+
+    let flags = flags(&name);
+
+    let mut script = String::new();
+
+    match shell {
+        Shell::Bash => {
+            let flags = flags.join(" ");
+
+            writeln!(script, "_{name}() {{")?;
+            writeln!(
+                script,
+                r#"    COMPREPLY=($(compgen -W "{flags}" -- "${{COMP_WORDS[COMP_CWORD]}}"))"#
+            )?;
+            writeln!(script, "}}")?;
+            writeln!(script, "complete -F _{name} {name} {name}.exe")?;
+        }
+
+        Shell::Zsh => {
+            let flags = flags.join(" ");
+
+            writeln!(script, "#compdef {name} {name}.exe")?;
+            writeln!(script, "compadd -- {flags}")?;
+        }
+
+        Shell::PowerShell => {
+            let flags = flags
+                .iter()
+                .map(|f| format!("'{f}'"))
+                .collect::<Vec<_>>()
+                .join(", ");
+
+            writeln!(
+                script,
+                "Register-ArgumentCompleter -Native -CommandName '{name}', '{name}.exe' -ScriptBlock {{"
+            )?;
+            writeln!(
+                script,
+                "    param($wordToComplete, $commandAst, $cursorPosition)"
+            )?;
+            writeln!(
+                script,
+                r#"    @({flags}) | Where-Object {{ $_ -like "$wordToComplete*" }} | ForEach-Object {{"#
+            )?;
+            writeln!(
+                script,
+                "        [System.Management.Automation.CompletionResult]::new($_, $_, 'ParameterName', $_)"
+            )?;
+            writeln!(script, "    }}")?;
+            writeln!(script, "}}")?;
+        }
+    }
+
+    Ok(script)
+}
+
+/// Get all the visible flags from the help output, that way this is always in sync with `Args`
+fn flags(name: &str) -> Vec<String> {
+    let help = match Args::from_args(&[name], &["--help"]) {
+        Ok(_) => return Vec::new(),
+        Err(e) => e.output,
+    };
+
+    // Options:
+    //   --quiet           don't show informational popups
+    //   --help, help      display usage information
+    help.lines()
+        .skip_while(|l| !l.starts_with("Options:"))
+        .filter_map(|l| l.split_whitespace().next())
+        .map(|f| f.trim_end_matches(','))
+        .filter(|f| f.starts_with("--"))
+        .map(ToOwned::to_owned)
+        .collect()
+}
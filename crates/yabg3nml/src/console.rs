@@ -1,18 +1,49 @@
+use std::{
+    sync::{Condvar, Mutex, OnceLock},
+    time::Duration,
+};
+
 use eyre::Result;
+use shared::utils::SuperLock as _;
+use tracing::{info, warn};
 use windows::{
     core::PCWSTR,
-    Win32::System::Console::{
-        AllocConsole, GetStdHandle, SetConsoleMode, SetConsoleTitleW, ENABLE_PROCESSED_OUTPUT,
-        ENABLE_VIRTUAL_TERMINAL_PROCESSING, ENABLE_WRAP_AT_EOL_OUTPUT, STD_OUTPUT_HANDLE,
+    Win32::{
+        Foundation::BOOL,
+        System::Console::{
+            AllocConsole, AttachConsole, GetStdHandle, SetConsoleCtrlHandler, SetConsoleMode,
+            SetConsoleOutputCP, SetConsoleTitleW, ATTACH_PARENT_PROCESS, ENABLE_PROCESSED_OUTPUT,
+            ENABLE_VIRTUAL_TERMINAL_PROCESSING, ENABLE_WRAP_AT_EOL_OUTPUT, STD_OUTPUT_HANDLE,
+        },
     },
 };
 
+/// how long the ctrl handler waits for shutdown to finish. windows kills the process once
+/// the handler returns for close events, and after a few seconds regardless of the handler
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(3);
+
+type OnStop = Box<dyn Fn() + Send + Sync>;
+
+/// the UTF-8 code page
+const CP_UTF8: u32 = 65001;
+
+static ON_STOP: OnceLock<OnStop> = OnceLock::new();
+static SHUTDOWN: Mutex<bool> = Mutex::new(false);
+static SHUTDOWN_CVAR: Condvar = Condvar::new();
+
 #[allow(dead_code)]
-pub fn debug_console<A: AsRef<str>>(title: A) -> Result<()> {
+pub fn debug_console<A: AsRef<str>>(title: A, utf8: bool) -> Result<()> {
     unsafe {
         AllocConsole()?;
     }
 
+    // the default code page garbles non-ASCII paths, e.g. from localized mod folders
+    if utf8 {
+        if let Err(e) = unsafe { SetConsoleOutputCP(CP_UTF8) } {
+            warn!(%e, "failed to set console code page to UTF-8");
+        }
+    }
+
     let handle = unsafe { GetStdHandle(STD_OUTPUT_HANDLE)? };
 
     unsafe {
@@ -36,3 +67,52 @@ pub fn debug_console<A: AsRef<str>>(title: A) -> Result<()> {
 
     Ok(())
 }
+
+/// Attach to the parent process' console (if it has one), so stdout output shows up in it
+pub fn attach_parent_console() {
+    // it's fine if this fails; it just means there's no console to attach to
+    _ = unsafe { AttachConsole(ATTACH_PARENT_PROCESS) };
+}
+
+/// Cleanly shut down on Ctrl+C or console close, instead of the process being killed outright.
+/// `on_stop` should make run return
+pub fn set_ctrl_handler(on_stop: impl Fn() + Send + Sync + 'static) {
+    if ON_STOP.set(Box::new(on_stop)).is_err() {
+        return;
+    }
+
+    // if this fails, Ctrl+C just kills the process instead
+    if let Err(e) = unsafe { SetConsoleCtrlHandler(Some(ctrl_handler), true) } {
+        warn!(%e, "failed to set console ctrl handler");
+    }
+}
+
+unsafe extern "system" fn ctrl_handler(ctrl_type: u32) -> BOOL {
+    let Some(on_stop) = ON_STOP.get() else {
+        return false.into();
+    };
+
+    info!(ctrl_type, "received console control event; shutting down");
+    on_stop();
+
+    // wait for everything to exit and the logs to get flushed, since we may be killed once this returns
+    let done = SHUTDOWN.super_lock();
+    let res = SHUTDOWN_CVAR.wait_timeout_while(done, SHUTDOWN_TIMEOUT, |done| !*done);
+    if res.is_ok_and(|(_, timeout)| timeout.timed_out()) {
+        warn!("timed out waiting for shutdown");
+    }
+
+    true.into()
+}
+
+/// Lets the ctrl handler know shutdown is done once dropped
+///
+/// Must be declared before the log `WorkerGuard`, so that it's dropped after the logs are flushed
+pub struct ShutdownGuard;
+
+impl Drop for ShutdownGuard {
+    fn drop(&mut self) {
+        *SHUTDOWN.super_lock() = true;
+        SHUTDOWN_CVAR.notify_all();
+    }
+}
@@ -0,0 +1,361 @@
+//! A control interface for external GUIs and scripts, at `\\.\pipe\yabg3nml`
+//!
+//! Only processes running as the same user (or admins, or SYSTEM) can connect. Each request is one line of json,
+//! and gets answered with one line of json
+//!
+//! Requests: `{"command":"<name>"}`, where name is one of
+//! - `status`: whether the watcher is running, the pid of the injected game if there is one, and how the last
+//!   injection went, e.g. `"pid 1234: 3 loaded, 1 failed"`, if there was one
+//! - `reinject`: load the plugins which aren't loaded into the injected game yet, e.g. ones added since it started.
+//!   Plugins which are already loaded are never loaded twice
+//! - `reload-config`: re-read config.toml, and use it for the games injected from now on if it's valid. Settings
+//!   which the watcher only reads at startup, like which exes are watched or the tray, still require a restart
+//! - `stop`: stop the watcher and exit
+//! - `list-plugins`: every plugin in the plugins folder and `target_plugin_dirs`, and whether it's enabled, disabled, or suspended
+//!
+//! Responses: `{"ok":true,"data":{..}}` on success, or `{"ok":false,"error":"<reason>"}`
+
+use std::{
+    fs::File,
+    io::{self, BufRead as _, BufReader, Read as _, Write as _},
+    os::windows::io::FromRawHandle as _,
+    thread,
+};
+
+use serde::{Deserialize, Serialize};
+use shared::{
+    config::{apply_config, current_config, reload_config, Config},
+    discovery::{all_plugin_dirs, list_plugin_files},
+};
+use tracing::{error, info, trace, trace_span, warn};
+use windows::{
+    core::{w, Error},
+    Win32::{
+        Foundation::ERROR_PIPE_CONNECTED,
+        Security::SECURITY_ATTRIBUTES,
+        Storage::FileSystem::PIPE_ACCESS_DUPLEX,
+        System::Pipes::{
+            ConnectNamedPipe, CreateNamedPipeW, PIPE_READMODE_BYTE, PIPE_REJECT_REMOTE_CLIENTS,
+            PIPE_TYPE_BYTE, PIPE_WAIT,
+        },
+    },
+};
+
+use crate::{
+    acl::current_user_only,
+    process_watcher::Pid,
+    status::{last_result, WatcherStatus},
+    utils::PSecurityDescriptor,
+};
+
+/// requests longer than this are refused, so a misbehaving client can't make us buffer forever
+const MAX_REQUEST_LEN: u64 = 64 * 1024;
+const BUF_SIZE: u32 = 4096;
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "command", rename_all = "kebab-case")]
+enum Request {
+    Status,
+    Reinject,
+    ReloadConfig,
+    Stop,
+    ListPlugins,
+}
+
+#[derive(Debug, Serialize)]
+struct Response {
+    ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data: Option<Data>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl Response {
+    fn ok(data: Data) -> Self {
+        Self {
+            ok: true,
+            data: Some(data),
+            error: None,
+        }
+    }
+
+    fn err(error: impl Into<String>) -> Self {
+        Self {
+            ok: false,
+            data: None,
+            error: Some(error.into()),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+enum Data {
+    Status {
+        running: bool,
+        injected_pid: Option<Pid>,
+        last_result: Option<String>,
+    },
+    Message {
+        message: String,
+    },
+    Plugins {
+        plugins: Vec<Plugin>,
+    },
+}
+
+#[derive(Debug, Serialize)]
+struct Plugin {
+    name: String,
+    file: String,
+    state: PluginState,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum PluginState {
+    Enabled,
+    Disabled,
+    Suspended,
+}
+
+/// Serve the control pipe on a new thread. `reinject` is called for the `reinject` command with the injected
+/// game's pid. `on_stop` is called for the `stop` command, and should make run return
+pub fn spawn(
+    status: WatcherStatus,
+    reinject: impl Fn(Pid) -> eyre::Result<()> + Send + 'static,
+    on_stop: impl Fn() + Send + 'static,
+) {
+    thread::spawn(move || {
+        let span = trace_span!("control");
+        let _guard = span.enter();
+
+        // without it, the pipe would get the default dacl, which may let other users in
+        let security = match current_user_only() {
+            Ok(security) => security,
+            Err(e) => {
+                error!(%e, "failed to create control pipe security descriptor; not serving the control pipe");
+                return;
+            }
+        };
+
+        loop {
+            let pipe = match connect(&security) {
+                Ok(pipe) => pipe,
+                Err(e) => {
+                    // nothing to retry; the control interface just isn't available
+                    error!(%e, "failed to create control pipe");
+                    return;
+                }
+            };
+
+            if let Err(e) = serve(pipe, &status, &reinject, &on_stop) {
+                trace!(%e, "control client disconnected");
+            }
+        }
+    });
+}
+
+/// Create the pipe and wait for a client to connect to it
+fn connect(security: &PSecurityDescriptor) -> Result<File, Error> {
+    let attributes = SECURITY_ATTRIBUTES {
+        nLength: size_of::<SECURITY_ATTRIBUTES>() as u32,
+        lpSecurityDescriptor: security.as_void(),
+        bInheritHandle: false.into(),
+    };
+
+    let handle = unsafe {
+        CreateNamedPipeW(
+            w!(r"\\.\pipe\yabg3nml"),
+            PIPE_ACCESS_DUPLEX,
+            PIPE_TYPE_BYTE | PIPE_READMODE_BYTE | PIPE_WAIT | PIPE_REJECT_REMOTE_CLIENTS,
+            1,
+            BUF_SIZE,
+            BUF_SIZE,
+            0,
+            Some(&attributes),
+        )
+    };
+
+    if handle.is_invalid() {
+        return Err(Error::from_win32());
+    }
+
+    // SAFETY: the handle is valid, and nothing else owns it. it's closed when the file drops
+    let pipe = unsafe { File::from_raw_handle(handle.0) };
+
+    // a client which connected between creating and connecting is not an error
+    if let Err(e) = unsafe { ConnectNamedPipe(handle, None) } {
+        if e.code() != ERROR_PIPE_CONNECTED.to_hresult() {
+            return Err(e);
+        }
+    }
+
+    Ok(pipe)
+}
+
+fn serve(
+    pipe: File,
+    status: &WatcherStatus,
+    reinject: &impl Fn(Pid) -> eyre::Result<()>,
+    on_stop: &impl Fn(),
+) -> io::Result<()> {
+    let mut writer = pipe.try_clone()?;
+    let mut reader = BufReader::new(pipe);
+
+    let mut line = String::new();
+
+    loop {
+        line.clear();
+
+        let len = reader.by_ref().take(MAX_REQUEST_LEN).read_line(&mut line)?;
+
+        // client disconnected
+        if len == 0 {
+            return Ok(());
+        }
+
+        if !line.ends_with('\n') && len as u64 >= MAX_REQUEST_LEN {
+            warn!(len, "control request too long; disconnecting client");
+            respond(&mut writer, &Response::err("request is too long"))?;
+            return Ok(());
+        }
+
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let (response, stop) = match serde_json::from_str::<Request>(line) {
+            Ok(request) => {
+                info!(?request, "received control request");
+                handle(request, status, reinject)
+            }
+
+            Err(e) => {
+                trace!(%e, line, "invalid control request");
+                (Response::err(format!("invalid request: {e}")), false)
+            }
+        };
+
+        respond(&mut writer, &response)?;
+
+        if stop {
+            on_stop();
+            return Ok(());
+        }
+    }
+}
+
+fn respond(writer: &mut File, response: &Response) -> io::Result<()> {
+    let mut data = serde_json::to_vec(response)?;
+    data.push(b'\n');
+
+    writer.write_all(&data)?;
+    writer.flush()
+}
+
+/// Returns the response, and whether we should stop
+fn handle(
+    request: Request,
+    status: &WatcherStatus,
+    reinject: &impl Fn(Pid) -> eyre::Result<()>,
+) -> (Response, bool) {
+    let response = match request {
+        Request::Status => Response::ok(Data::Status {
+            running: status.is_running(),
+            injected_pid: status.injected_pid(),
+            last_result: last_result(),
+        }),
+
+        Request::Reinject => match status.injected_pid() {
+            Some(pid) => match reinject(pid) {
+                Ok(()) => Response::ok(Data::Message {
+                    message: format!("loaded the plugins which weren't loaded into {pid} yet; see the log for which ones"),
+                }),
+
+                Err(e) => Response::err(format!("failed to reinject: {e}")),
+            },
+
+            None => Response::err("no game is injected"),
+        },
+
+        Request::ReloadConfig => match reload_config() {
+            Ok(config) => match config.validate() {
+                Ok(_) => {
+                    apply_config(config);
+                    info!("Reloaded config.toml");
+
+                    Response::ok(Data::Message {
+                        message: "reloaded config.toml. It's used for the games injected from now on; settings which are only read at startup, like which exes are watched or the tray, require a restart of this tool".to_owned(),
+                    })
+                }
+
+                Err(e) => Response::err(format!("config.toml has invalid options, so it wasn't reloaded:\n{e}")),
+            },
+
+            Err(e) => Response::err(format!("failed to read config.toml: {e}")),
+        },
+
+        Request::Stop => {
+            return (
+                Response::ok(Data::Message {
+                    message: "stopping".to_owned(),
+                }),
+                true,
+            )
+        }
+
+        Request::ListPlugins => match list_plugins() {
+            Ok(plugins) => Response::ok(Data::Plugins { plugins }),
+            Err(e) => Response::err(format!("failed to read plugins dir: {e}")),
+        },
+    };
+
+    (response, false)
+}
+
+fn list_plugins() -> eyre::Result<Vec<Plugin>> {
+    // the loader reads the config fresh every time, so this is what it'd do on the next load
+    let fresh = reload_config();
+    let config: &Config = match &fresh {
+        Ok(config) => config,
+        Err(_) => current_config()?,
+    };
+
+    let mut plugins = Vec::new();
+
+    let mut paths = Vec::new();
+    for dir in all_plugin_dirs(config)? {
+        paths.extend(list_plugin_files(config, &dir)?);
+    }
+
+    for path in paths {
+        let name = path
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned();
+
+        let state = if config.core.is_plugin_disabled(&name) {
+            PluginState::Disabled
+        } else if config.core.is_plugin_suspended(&name) {
+            PluginState::Suspended
+        } else {
+            PluginState::Enabled
+        };
+
+        plugins.push(Plugin {
+            file: path
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned(),
+            name,
+            state,
+        });
+    }
+
+    Ok(plugins)
+}
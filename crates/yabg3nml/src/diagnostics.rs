@@ -0,0 +1,175 @@
+use std::{
+    env,
+    fmt::Write as _,
+    fs::{self, File},
+    io::Write as _,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use eyre::Result;
+use shared::{
+    config::Config,
+    paths::{get_bg3_plugins_dir, get_portable_dir},
+    version_info::VersionInfo,
+};
+use zip::{write::SimpleFileOptions, CompressionMethod, ZipWriter};
+
+use crate::{logging::LOG_NAME, validate::validate_plugins};
+
+/// how many of the newest log files are included
+const MAX_LOGS: usize = 5;
+
+/// Bundle everything useful for a bug report into a zip at `out`. Returns the names of the files in it
+///
+/// The user's profile path and name are replaced in everything that's included, and `on_inject_url`
+/// is left out of the config, since it may contain a token
+pub fn collect_diagnostics(config: &Config, out: &Path) -> Result<Vec<String>> {
+    let mut zip = ZipWriter::new(File::create(out)?);
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+
+    let mut entries = Vec::new();
+    let mut add = |name: String, contents: &str| -> Result<()> {
+        zip.start_file(name.as_str(), options)?;
+        zip.write_all(scrub(contents).as_bytes())?;
+        entries.push(name);
+        Ok(())
+    };
+
+    add("system.txt".to_owned(), &system_info(config))?;
+
+    let mut config = config.clone();
+    if config.core.on_inject_url.is_some() {
+        config.core.on_inject_url = Some("<redacted>".to_owned());
+    }
+
+    add("config.toml".to_owned(), &toml::to_string_pretty(&config)?)?;
+
+    // finds the plugins like the loader does, and checks them for most setup problems
+    let (validate, plugins) = match validate_plugins(&config) {
+        Ok(summary) => {
+            let plugins = summary
+                .plugins
+                .iter()
+                .map(|p| plugin_line(&p.path))
+                .collect::<String>();
+
+            (summary.to_string(), plugins)
+        }
+
+        Err(e) => {
+            let error = format!("failed to check plugins: {e}\n");
+            (error.clone(), error)
+        }
+    };
+
+    add("validate.txt".to_owned(), &validate)?;
+    add("plugins.txt".to_owned(), &plugins)?;
+
+    for log in newest_logs()? {
+        let Ok(contents) = fs::read(&log) else {
+            continue;
+        };
+
+        let name = log.file_name().unwrap_or_default().to_string_lossy();
+        add(format!("logs/{name}"), &String::from_utf8_lossy(&contents))?;
+    }
+
+    zip.finish()?;
+
+    Ok(entries)
+}
+
+/// Versions of everything that commonly matters for a bug report
+fn system_info(config: &Config) -> String {
+    let version = |path: &Path| {
+        VersionInfo::from_file(path)
+            .and_then(|v| v.fixed_version)
+            .map(|v| v.to_string())
+            .unwrap_or_else(|| "unknown".to_owned())
+    };
+
+    let system32 = env::var_os("SystemRoot")
+        .map(|root| PathBuf::from(root).join("System32"))
+        .unwrap_or_default();
+
+    let bin = config.core.install_root.join("bin");
+
+    let mut info = String::new();
+    _ = writeln!(info, "yabg3nml: {}", env!("CARGO_PKG_VERSION"));
+    // kernel32 is versioned with the OS build
+    _ = writeln!(info, "windows: {}", version(&system32.join("kernel32.dll")));
+    _ = writeln!(info, "bg3.exe: {}", version(&bin.join("bg3.exe")));
+    _ = writeln!(info, "bg3_dx11.exe: {}", version(&bin.join("bg3_dx11.exe")));
+    _ = writeln!(info, "portable: {}", get_portable_dir().is_some());
+
+    info
+}
+
+/// A plugin's filename, size, and sha256
+fn plugin_line(path: &Path) -> String {
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+
+    match fs::read(path) {
+        Ok(data) => format!(
+            "{file_name}: {} bytes, sha256 {}\n",
+            data.len(),
+            sha256::digest(&data)
+        ),
+
+        Err(e) => format!("{file_name}: failed to read: {e}\n"),
+    }
+}
+
+/// The newest log files in the logs dir, newest first
+fn newest_logs() -> Result<Vec<PathBuf>> {
+    let logs_dir = get_bg3_plugins_dir()?.join("logs");
+
+    let Ok(read_dir) = fs::read_dir(&logs_dir) else {
+        return Ok(Vec::new());
+    };
+
+    let mut logs = read_dir
+        .flatten()
+        // the logs dir also holds crash dumps
+        .filter(|e| e.file_name().to_string_lossy().starts_with(LOG_NAME))
+        .map(|e| {
+            let modified = e
+                .metadata()
+                .and_then(|m| m.modified())
+                .unwrap_or(SystemTime::UNIX_EPOCH);
+
+            (modified, e.path())
+        })
+        .collect::<Vec<_>>();
+
+    logs.sort_by(|a, b| b.0.cmp(&a.0));
+
+    Ok(logs
+        .into_iter()
+        .take(MAX_LOGS)
+        .map(|(_, path)| path)
+        .collect())
+}
+
+/// Replace the user's profile path and name, which show up in most paths
+fn scrub(text: &str) -> String {
+    let mut text = text.to_owned();
+
+    for (var, replacement) in [("USERPROFILE", "%USERPROFILE%"), ("USERNAME", "%USERNAME%")] {
+        let Ok(value) = env::var(var) else {
+            continue;
+        };
+
+        if value.is_empty() {
+            continue;
+        }
+
+        // the loader logs lowercased paths
+        text = text
+            .replace(&value, replacement)
+            .replace(&value.to_lowercase(), replacement);
+    }
+
+    text
+}
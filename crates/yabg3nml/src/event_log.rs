@@ -0,0 +1,76 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    OnceLock,
+};
+
+use tracing::{trace, warn};
+use windows::{
+    core::{w, HSTRING, PCWSTR},
+    Win32::{
+        Foundation::HANDLE,
+        System::EventLog::{RegisterEventSourceW, ReportEventW, EVENTLOG_ERROR_TYPE},
+    },
+};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug)]
+struct EventSource(HANDLE);
+
+// SAFETY: event log handles may be used from any thread
+unsafe impl Send for EventSource {}
+unsafe impl Sync for EventSource {}
+
+/// Whether [`report`] writes to the event log
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// Write an error to the Windows Application event log, if enabled. Failing to do so is only logged
+pub fn report(title: &str, message: &str) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+
+    // the source is only registered once something needs to be reported
+    static SOURCE: OnceLock<Option<EventSource>> = OnceLock::new();
+
+    let source = SOURCE.get_or_init(|| {
+        let res = unsafe {
+            RegisterEventSourceW(PCWSTR::null(), w!("Yet Another BG3 Native Mod Loader"))
+        };
+
+        match res {
+            Ok(handle) => Some(EventSource(handle)),
+            Err(e) => {
+                warn!(%e, "failed to register event log source");
+                None
+            }
+        }
+    });
+
+    let Some(source) = source else {
+        return;
+    };
+
+    let text = HSTRING::from(format!("{title}\n\n{message}"));
+    let strings = [PCWSTR::from_raw(text.as_ptr())];
+
+    let res = unsafe {
+        ReportEventW(
+            source.0,
+            EVENTLOG_ERROR_TYPE,
+            0,
+            0,
+            None,
+            0,
+            Some(&strings),
+            None,
+        )
+    };
+
+    match res {
+        Ok(_) => trace!(title, "wrote to event log"),
+        Err(e) => warn!(%e, "failed to write to event log"),
+    }
+}
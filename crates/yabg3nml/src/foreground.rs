@@ -0,0 +1,61 @@
+use std::{
+    io::{self, Write as _},
+    path::Path,
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use windows::Win32::System::Console::{AllocConsole, AttachConsole, ATTACH_PARENT_PROCESS};
+
+use crate::status::{last_result, WatcherStatus};
+
+const REDRAW_RATE: Duration = Duration::from_millis(250);
+
+/// Use the console this was started from, or a new one
+pub fn init() {
+    if unsafe { AttachConsole(ATTACH_PARENT_PROCESS) }.is_err() {
+        _ = unsafe { AllocConsole() };
+    }
+}
+
+/// Redraw a single status line until the watcher is done
+pub fn status_line<S: AsRef<str>>(
+    status: &WatcherStatus,
+    processes: &[S],
+    watcher_handle: &JoinHandle<()>,
+) {
+    let watched = processes
+        .iter()
+        .map(|p| {
+            let p = Path::new(p.as_ref());
+            p.file_name().unwrap_or(p.as_os_str()).to_string_lossy()
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    let mut stdout = io::stdout();
+    let mut last_len = 0;
+
+    while !watcher_handle.is_finished() {
+        let state = match (status.is_running(), status.injected_pid()) {
+            (_, Some(pid)) => format!("Injected (pid {pid})"),
+            (true, None) => "Waiting for game".to_owned(),
+            (false, None) => "Starting".to_owned(),
+        };
+
+        let line = match last_result() {
+            Some(last) => format!("Watching {watched} | {state} | Last: {last} | Ctrl+C to quit"),
+            None => format!("Watching {watched} | {state} | Ctrl+C to quit"),
+        };
+
+        // pad out any leftovers from a longer previous line
+        let pad = last_len.saturating_sub(line.len());
+        _ = write!(stdout, "\r{line}{:pad$}", "");
+        _ = stdout.flush();
+        last_len = line.len();
+
+        thread::sleep(REDRAW_RATE);
+    }
+
+    _ = writeln!(stdout);
+}
@@ -0,0 +1,30 @@
+use std::{
+    ffi::{c_char, CStr},
+    mem,
+};
+
+use windows::{
+    core::{s, w},
+    Win32::System::LibraryLoader::{GetModuleHandleW, GetProcAddress},
+};
+
+/// Best-effort detection of Wine/Proton. Wine's ntdll exports `wine_get_version`, native Windows does not
+///
+/// Returns the wine version if running under wine
+pub fn is_wine() -> Option<String> {
+    type FarProc = unsafe extern "system" fn() -> isize;
+    type WineGetVersion = unsafe extern "C" fn() -> *const c_char;
+
+    let ntdll = unsafe { GetModuleHandleW(w!("ntdll")).ok()? };
+    let addr = unsafe { GetProcAddress(ntdll, s!("wine_get_version"))? };
+
+    let wine_get_version = unsafe { mem::transmute::<FarProc, WineGetVersion>(addr) };
+
+    let version = unsafe { wine_get_version() };
+    if version.is_null() {
+        return Some("unknown".to_owned());
+    }
+
+    let version = unsafe { CStr::from_ptr(version) };
+    Some(version.to_string_lossy().into_owned())
+}
@@ -0,0 +1,17 @@
+use windows::Win32::System::{
+    SystemInformation::{IMAGE_FILE_MACHINE, IMAGE_FILE_MACHINE_UNKNOWN},
+    Threading::{GetCurrentProcess, IsWow64Process2},
+};
+
+/// Whether this process runs under WOW64, i.e. it's a 32-bit build on 64-bit Windows.
+/// The tools are always built for x64, so this means a mismatched build is in use
+///
+/// Returns the machine this process was built for, if it runs under WOW64
+pub fn is_wow64() -> Option<IMAGE_FILE_MACHINE> {
+    let mut process_machine = IMAGE_FILE_MACHINE::default();
+
+    unsafe { IsWow64Process2(GetCurrentProcess(), &mut process_machine, None).ok()? };
+
+    // unknown means it's not a WOW64 process
+    (process_machine != IMAGE_FILE_MACHINE_UNKNOWN).then_some(process_machine)
+}
@@ -0,0 +1,44 @@
+use eyre::Result;
+use shared::utils::OwnedHandle;
+use windows::Win32::{
+    Foundation::HANDLE,
+    System::JobObjects::{
+        AssignProcessToJobObject, CreateJobObjectW, JobObjectExtendedLimitInformation,
+        SetInformationJobObject, JOBOBJECT_EXTENDED_LIMIT_INFORMATION,
+        JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE,
+    },
+};
+
+/// A job which kills all of its processes once the job handle is closed (including when this process exits)
+pub struct KillOnCloseJob {
+    /// closing it kills the processes
+    _handle: OwnedHandle,
+}
+
+impl KillOnCloseJob {
+    pub fn new() -> Result<Self> {
+        let job: OwnedHandle = unsafe { CreateJobObjectW(None, None)? }.into();
+
+        let mut info = JOBOBJECT_EXTENDED_LIMIT_INFORMATION::default();
+        info.BasicLimitInformation.LimitFlags = JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE;
+
+        unsafe {
+            SetInformationJobObject(
+                job.as_raw_handle(),
+                JobObjectExtendedLimitInformation,
+                (&raw const info).cast(),
+                size_of::<JOBOBJECT_EXTENDED_LIMIT_INFORMATION>() as u32,
+            )?;
+        }
+
+        Ok(Self { _handle: job })
+    }
+
+    pub fn assign(&self, process: HANDLE) -> Result<()> {
+        unsafe {
+            AssignProcessToJobObject(self._handle.as_raw_handle(), process)?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,128 @@
+use std::{
+    ffi::OsStr,
+    os::windows::process::CommandExt as _,
+    process::Command,
+    thread,
+    time::{Duration, Instant},
+};
+
+use shared::{config::Config, popup::warn_popup, utils::OwnedHandle};
+use tracing::{error, info};
+use unicase::UniCase;
+use windows::{
+    core::Error,
+    Win32::System::{
+        Diagnostics::Debug::DebugActiveProcessStop,
+        Threading::{
+            OpenProcess, CREATE_SUSPENDED, DEBUG_ONLY_THIS_PROCESS, DEBUG_PROCESS,
+            PROCESS_QUERY_LIMITED_INFORMATION,
+        },
+    },
+};
+
+use crate::{
+    process_watcher::Pid,
+    wapi::{
+        enum_processes::EnumProcessesRs, query_full_process_image_name::QueryFullProcessImageNameRs,
+    },
+};
+
+/// how long the game has to show up on its own before it's launched
+const LAUNCH_WINDOW: Duration = Duration::from_secs(5);
+const POLL_RATE: Duration = Duration::from_millis(500);
+
+/// A command which starts the game as its debugger. This bypasses IFEO, which would otherwise
+/// run the autostart tool instead of the game. Once spawned, [`stop_debugging`] must be called
+/// from the same thread, or the game is closed when this process exits
+pub fn game_command<P: AsRef<OsStr>>(path: P, suspended: bool) -> Command {
+    let mut cmd = Command::new(path);
+    cmd.creation_flags(game_creation_flags(suspended));
+    cmd
+}
+
+/// The process creation flags [`game_command`] uses
+pub fn game_creation_flags(suspended: bool) -> u32 {
+    let mut flags = DEBUG_PROCESS.0 | DEBUG_ONLY_THIS_PROCESS.0;
+    if suspended {
+        flags |= CREATE_SUSPENDED.0;
+    }
+
+    flags
+}
+
+/// Detach from a game spawned with [`game_command`]
+pub fn stop_debugging(pid: Pid) -> Result<(), Error> {
+    unsafe { DebugActiveProcessStop(pid) }
+}
+
+/// Give the game a short amount of time to be found, and launch `game` if it wasn't.
+/// The watcher then injects it like any other game process
+pub fn launch_if_not_running<S: AsRef<str>>(config: &Config, processes: &[S], game: &str) {
+    let start = Instant::now();
+
+    while start.elapsed() < LAUNCH_WINDOW {
+        if let Some(pid) = find_running(processes) {
+            info!(pid, "Found the game already running; not launching it");
+            return;
+        }
+
+        thread::sleep(POLL_RATE);
+    }
+
+    let args = &config.core.extra_game_args;
+    info!(game, ?args, "Game was not found running; launching it");
+
+    let child = game_command(game, false).args(args).spawn();
+
+    let child = match child {
+        Ok(v) => v,
+        Err(e) => {
+            error!(%e, game, "failed to launch game");
+            warn_popup(
+                "Launch failure",
+                format!("Failed to launch the game at\n{game}\n\nThe watcher is still running, so you can start the game yourself.\n\nError: {e}"),
+            );
+
+            return;
+        }
+    };
+
+    let pid = child.id();
+
+    if let Err(e) = stop_debugging(pid) {
+        error!(%e, pid, "DebugActiveProcessStop failed");
+        warn_popup(
+            "DebugActiveProcessStop failed",
+            format!("The game was launched, but it could not be detached from. It will be closed when this tool exits.\n\nError: {e}"),
+        );
+    }
+
+    info!(pid, "Launched the game");
+}
+
+/// Find a running process matching one of the full exe paths
+fn find_running<S: AsRef<str>>(processes: &[S]) -> Option<Pid> {
+    let mut pid_buf = vec![0u32; 1024];
+    let mut path_buf = Vec::new();
+
+    let processes = processes
+        .iter()
+        .map(|p| UniCase::new(p.as_ref()))
+        .collect::<Vec<_>>();
+
+    EnumProcessesRs(&mut pid_buf).iter().copied().find(|&pid| {
+        let Ok(process) = (unsafe { OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, false, pid) })
+        else {
+            return false;
+        };
+
+        let process = OwnedHandle::new(process);
+
+        let Ok(path) = QueryFullProcessImageNameRs(&process, &mut path_buf) else {
+            return false;
+        };
+
+        let path = path.to_string_lossy();
+        processes.contains(&UniCase::new(&*path))
+    })
+}
@@ -1,26 +1,49 @@
 #![feature(windows_process_exit_code_from)]
+#![feature(windows_process_extensions_main_thread_handle)]
 
+mod acl;
 mod autostart;
+mod clean;
 mod cli;
+mod completions;
 mod console;
+mod control;
+mod diagnostics;
 mod event;
+mod event_log;
+mod foreground;
 mod is_admin;
+mod is_wine;
+mod is_wow64;
+mod job;
+mod launch;
 mod loader;
 mod logging;
+mod minidump;
 mod panic;
 mod paths;
+mod priority;
 mod privileges;
 mod process_watcher;
 mod remote_thread;
 mod run;
 mod server;
+mod service;
 mod setup;
 mod single_instance;
+mod status;
 mod stop_token;
+mod supervisor;
 mod tmp_loader;
 mod tray;
+mod unload_watch;
 mod utils;
+mod validate;
+mod wait;
 mod wapi;
+mod webhook;
 
 pub use autostart::autostart;
-pub use run::{run, RunType};
+pub use cli::Args;
+pub use run::{run, run_with, RunOptions, RunType};
+pub use status::WatcherStatus;
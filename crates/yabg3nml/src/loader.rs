@@ -1,22 +1,29 @@
 mod dirty;
+mod pid_lock;
+mod selftest;
 mod write;
 
-use std::{ffi::c_void, sync::OnceLock};
-use std::{iter, sync::atomic::Ordering};
+use std::{
+    ffi::c_void,
+    iter,
+    sync::atomic::{AtomicBool, Ordering},
+};
 use std::{mem, os::windows::prelude::OsStrExt as _};
+use std::{sync::OnceLock, time::Instant};
 
 use eyre::{Context, Result};
 use native_plugin_lib::Version;
 use shared::{
-    config::Config,
+    config::{is_config_writeback_enabled, Config},
+    paths::{get_portable_dir, to_extended_path},
     popup::warn_popup,
-    thread_data::{LogData, ThreadData},
+    thread_data::{LoadPhase, LogData, ThreadData},
     utils::OwnedHandle,
 };
 use tracing::{error, info, level_filters::LevelFilter, trace, trace_span, warn};
-use windows::Win32::System::Threading::WaitForInputIdle;
+use windows::Win32::System::Threading::{GetExitCodeProcess, WaitForInputIdle};
 use windows::Win32::{
-    Foundation::WAIT_FAILED,
+    Foundation::{STILL_ACTIVE, WAIT_FAILED, WIN32_ERROR},
     System::Threading::{INFINITE, LPTHREAD_START_ROUTINE},
 };
 use windows::{
@@ -26,92 +33,182 @@ use windows::{
         System::{
             LibraryLoader::{GetModuleHandleW, GetProcAddress},
             Threading::{
-                OpenProcess, PROCESS_QUERY_INFORMATION, PROCESS_VM_OPERATION, PROCESS_VM_READ,
-                PROCESS_VM_WRITE,
+                OpenProcess, PROCESS_DUP_HANDLE, PROCESS_QUERY_INFORMATION, PROCESS_VM_OPERATION,
+                PROCESS_VM_READ, PROCESS_VM_WRITE,
             },
         },
     },
 };
 
-use crate::remote_thread::RemoteThread;
+use crate::remote_thread::{RemoteThread, SPAWN_ATTEMPTS};
 use crate::{
-    process_watcher::Pid,
-    server::{AUTH, PID},
-    tmp_loader::Loader,
-    wapi::get_module_base_ex::GetModuleBaseEx,
+    event_log, minidump, priority::PriorityBoost, process_watcher::Pid, server::expect_loader,
+    tmp_loader::Loader, wapi::get_module_base_ex::GetModuleBaseEx,
 };
 use dirty::is_dirty;
-use write::write_in;
+use pid_lock::claim_pid;
+use write::{free_in, write_in};
+
+pub use selftest::selftest;
+
+/// Whether --quiet was passed; the loader must not ask anything then
+pub static QUIET: AtomicBool = AtomicBool::new(false);
+/// Whether --force was passed; the loader skips its `supported_game_versions` check then
+pub static FORCE: AtomicBool = AtomicBool::new(false);
+
+/// A popup for when loading into the game was aborted. Also written to the event log, if enabled
+fn failure_popup<T: AsRef<str>, M: AsRef<str>>(title: T, message: M) {
+    let title = title.as_ref();
+    let message = message.as_ref();
+
+    event_log::report(title, message);
+    warn_popup(title, message);
+}
+
+/// The game process, opened ahead of time so injection can start right away once it's ready
+pub struct Prepared {
+    process: OwnedHandle,
+    opened: Instant,
+}
+
+/// Open the game process before waiting on it, so that doesn't have to happen after the wait
+pub fn prepare(pid: Pid) -> Result<Prepared> {
+    let process = open_process(pid)?;
+
+    trace!(pid, "opened game process ahead of injection");
+
+    Ok(Prepared {
+        process,
+        opened: Instant::now(),
+    })
+}
+
+fn open_process(pid: Pid) -> Result<OwnedHandle, WinError> {
+    let process = unsafe {
+        OpenProcess(
+            PROCESS_QUERY_INFORMATION
+                | PROCESS_DUP_HANDLE
+                | PROCESS_VM_OPERATION
+                | PROCESS_VM_READ
+                | PROCESS_VM_WRITE,
+            false,
+            pid,
+        )
+    };
+
+    process.map(Into::into)
+}
+
+/// The address of LoadLibraryW, as a remote thread start routine. kernel32 is mapped at the same address
+/// in every process, so it's the same in the game
+fn load_library_w() -> Result<LPTHREAD_START_ROUTINE> {
+    type FarProc = unsafe extern "system" fn() -> isize;
+
+    static CACHE: OnceLock<LPTHREAD_START_ROUTINE> = OnceLock::new();
+
+    if let Some(f) = CACHE.get() {
+        return Ok(*f);
+    }
+
+    let handle = {
+        let handle = unsafe { GetModuleHandleW(w!("kernel32")) };
+        handle.context("Failed to get kernel32 module handle")?
+    };
+
+    let addr = unsafe { GetProcAddress(handle, s!("LoadLibraryW")) };
+
+    let addr = addr
+        .ok_or(WinError::from_win32())
+        .context("failed to get LoadLibraryW proc address")?;
 
+    let f = unsafe { mem::transmute::<FarProc, LPTHREAD_START_ROUTINE>(addr) };
+    _ = CACHE.set(f);
+    Ok(f)
+}
+
+/// `prepared` is the game process, if it was already opened with [`prepare`]. Returns whether loader.dll was
+/// started in the game, which is false if injection was skipped or aborted along the way
 pub fn run_loader(
     config: &Config,
     pid: Pid,
     loader: &Loader,
+    prepared: Option<Prepared>,
     dirty_check: bool,
     wait_for_init: bool,
-) -> Result<()> {
+    phase: LoadPhase,
+) -> Result<bool> {
     if !config.core.enabled {
         info!("Plugins are globally disabled. If you want to re-enable them, set [core]enabled in config.toml to true");
-        return Ok(());
+        return Ok(false);
     }
 
     let span = trace_span!("loader");
     let _guard = span.enter();
 
-    PID.store(pid, Ordering::Relaxed);
+    // restored when this returns, on every path
+    let _boost = config.core.inject_priority_boost.then(PriorityBoost::new);
 
-    // get loadlibraryw address as fn pointer
-    #[allow(non_snake_case)]
-    let LoadLibraryW = 'b: {
-        type FarProc = unsafe extern "system" fn() -> isize;
-        type ThreadStartRoutine = Option<unsafe extern "system" fn(*mut c_void) -> u32>;
+    // how long it takes from here until loader.dll starts loading; the process was maybe opened ahead of time
+    let start = Instant::now();
 
-        static CACHE: OnceLock<ThreadStartRoutine> = OnceLock::new();
-
-        if let Some(f) = CACHE.get() {
-            break 'b *f;
-        }
+    let trigger = config.core.injection_trigger;
+    info!("Using {trigger} to start remote threads");
 
-        let handle = {
-            let handle = unsafe { GetModuleHandleW(w!("kernel32")) };
-            handle.context("Failed to get kernel32 module handle")?
-        };
-
-        let addr = unsafe { GetProcAddress(handle, s!("LoadLibraryW")) };
-
-        let addr = addr
-            .ok_or(WinError::from_win32())
-            .context("failed to get LoadLibraryW proc address")?;
-
-        let f = unsafe { mem::transmute::<FarProc, ThreadStartRoutine>(addr) };
-        _ = CACHE.set(f);
-        f
-    };
+    #[allow(non_snake_case)]
+    let LoadLibraryW = load_library_w()?;
+
+    let process: OwnedHandle = match prepared {
+        Some(Prepared { process, opened }) => {
+            // the game may have exited while we were waiting on it
+            let mut code = 0;
+            let res = unsafe { GetExitCodeProcess(process.as_raw_handle(), &mut code) };
+            let exited = res.is_err() || code != STILL_ACTIVE.0 as u32;
+
+            if exited {
+                info!(pid, "game exited before plugins could be loaded into it");
+                return Ok(false);
+            }
 
-    let process: OwnedHandle = {
-        let process = unsafe {
-            OpenProcess(
-                PROCESS_QUERY_INFORMATION
-                    | PROCESS_VM_OPERATION
-                    | PROCESS_VM_READ
-                    | PROCESS_VM_WRITE,
-                false,
-                pid,
-            )
-        };
+            trace!(pid, held_for = ?opened.elapsed(), "using game process opened ahead of time");
+            process
+        }
 
-        match process {
-            Ok(v) => v.into(),
+        None => match open_process(pid) {
+            Ok(v) => v,
             Err(e) => {
                 error!(?e, "failed to open process");
-                warn_popup("Can't open process", format!("Failed to open the game process.\n\nThis could be due to a few reasons:\n1. when the program attempted to open the process, it was already gone\n2. you need admin permissions to open it (try running this as admin)\n\nPress OK to continue; this tool will continue to operate normally.\n\nError: {e}"));
-                return Ok(());
+                failure_popup("Can't open process", format!("Failed to open the game process.\n\nThis could be due to a few reasons:\n1. when the program attempted to open the process, it was already gone\n2. you need admin permissions to open it (try running this as admin)\n\nPress OK to continue; this tool will continue to operate normally.\n\nError: {e}"));
+                return Ok(false);
             }
-        }
+        },
+    };
+
+    // another loader may be racing us for this same process
+    // (for the resumed phase, we already claimed it in the suspended phase, and reinjecting is for a
+    // process we already loaded into)
+    let claim = match phase {
+        LoadPhase::Resumed | LoadPhase::Reinject => Ok(true),
+        _ => claim_pid(&process, pid),
     };
 
+    match claim {
+        Ok(true) => (),
+        Ok(false) => {
+            info!("another loader is handling PID {pid}; skipping");
+            return Ok(false);
+        }
+
+        // don't let a failed claim stop injection
+        Err(e) => warn!(%e, pid, "failed to claim pid; continuing anyways"),
+    }
+
     // to help new processes settle into a stable state before trying things
-    let res = unsafe { WaitForInputIdle(process.as_raw_handle(), INFINITE) };
+    // a suspended process never becomes idle, so it can't be waited on
+    let res = match phase {
+        LoadPhase::Suspended => 0,
+        _ => unsafe { WaitForInputIdle(process.as_raw_handle(), INFINITE) },
+    };
+
     if res == WAIT_FAILED.0 {
         let e = {
             let e = unsafe { GetLastError() };
@@ -119,25 +216,29 @@ pub fn run_loader(
         };
 
         error!(error = %e, "WaitForInputIdle");
-        warn_popup("Can't wait", format!("Failed to WaitForInputIdle.\n\nThis could be due to a few reasons:\n1. when the program attempted to wait for the process, it was already gone\n2. you need admin permissions to open it (try running this as admin)\n\nPress OK to continue; this tool will continue to operate normally.\n\nError: {e}"));
-        return Ok(());
+        failure_popup("Can't wait", format!("Failed to WaitForInputIdle.\n\nThis could be due to a few reasons:\n1. when the program attempted to wait for the process, it was already gone\n2. you need admin permissions to open it (try running this as admin)\n\nPress OK to continue; this tool will continue to operate normally.\n\nError: {e}"));
+        return Ok(false);
     }
 
+    // the game must be given the extended form if the path is too long, and it's also what the module will be
+    // reported as once it's loaded
+    let loader_path = to_extended_path(&loader.path);
+
     if dirty_check {
         // checks if process has already had injection done on it
-        let is_dirty = match is_dirty(&process, &loader.path) {
+        let is_dirty = match is_dirty(config, &process, &loader_path) {
             Ok(v) => v,
             Err(e) => {
                 error!(?e, "failed dirty check");
 
-                warn_popup(
+                failure_popup(
             "Failed process patch check",
             format!(
                 "The process patch detection failed due to winapi failure. This can happen if the process unexpectedly disappeared on us (such as a game crash). Aborting process injection. Please try patching the game again. Press OK to continue; this tool will continue to operate normally.\n\n{e}",
             ),
         );
 
-                return Ok(());
+                return Ok(false);
             }
         };
 
@@ -145,7 +246,7 @@ pub fn run_loader(
             // return ok as if nothing happened, however we will log this
             warn!("Aborting patching since the game process is already patched. If you'd like to patch it again, please restart the game and patch a fresh instance.");
             warn_popup("Already patched", "Aborting patching since the game process is already patched. If you'd like to patch it again, please restart the game and patch a fresh instance. Press OK to continue; this tool will continue to operate normally.");
-            return Ok(());
+            return Ok(false);
         }
     }
 
@@ -177,8 +278,7 @@ pub fn run_loader(
 
     info!("Running {loader_formatted}");
 
-    let loader_v = loader
-        .path
+    let loader_v = loader_path
         .as_os_str()
         .encode_wide()
         .chain(iter::once(0))
@@ -186,44 +286,55 @@ pub fn run_loader(
     // 1 byte = u8, u16 = 2 bytes, len = number of elems in vector, so len * 2
     let loader_path_len = loader_v.len() * size_of::<u16>();
 
-    let Ok(ptr) = write_in(&process, loader_v.as_ptr(), loader_path_len) else {
+    let Ok(ptr) = write_in(
+        &process,
+        loader_v.as_ptr(),
+        loader_path_len,
+        config.core.max_write_size,
+    ) else {
         error!("failed to write loader path into process");
-        return Ok(());
+        return Ok(false);
     };
 
     // start thread with dll
     // Note that the returned HANDLE is intentionally not closed!
-    let thread = match RemoteThread::spawn(&process, LoadLibraryW, Some(ptr)) {
+    let thread = match RemoteThread::spawn_with_retry(&process, trigger, LoadLibraryW, Some(ptr)) {
         Ok(h) => h,
         Err(e) => {
-            error!(?e, "Failed to create remote thread");
-            warn_popup(
-                "Process injection failure",
-                format!("Failed to create process remote thread. Patching has been aborted on this process.\n\nThis could be due to multiple reasons, but in any case, winapi returned an error. This can happen if the process unexpectedly disappeared on us (such as a game crash). Please restart the game and try again. Press OK to continue; this tool will continue to operate normally.\n\nError: {e}"),
+            error!(?e, %trigger, attempts = SPAWN_ATTEMPTS, "Failed to create remote thread");
+            // the thread never ran, so nothing is using it
+            _ = free_in(&process, ptr);
+
+            failure_popup(
+                "Remote thread creation failure",
+                format!("Failed to create process remote thread after {SPAWN_ATTEMPTS} attempts. Patching has been aborted on this process.\n\nWriting into the process succeeded; it was starting the thread which failed. Antivirus interference is a common cause of this, as is the process unexpectedly disappearing on us (such as a game crash). Please restart the game and try again. Press OK to continue; this tool will continue to operate normally.\n\nError: {e}"),
             );
 
-            return Ok(());
+            return Ok(false);
         }
     };
 
-    // wait for it to be done starting
-    if let Err(err) = thread.wait() {
-        warn_popup(
+    trace!(took = ?start.elapsed(), "started loading loader.dll");
+
+    // wait for it to be done starting. once LoadLibraryW returned, the path is no longer needed; the dll keeps
+    // its own copy
+    if let Err(err) = wait_then_free(&process, &thread, ptr) {
+        failure_popup(
             "Process injection failure",
             format!("Failed to wait for remote thread. Patching has been aborted on this process.\n\nThis is a rare occurence. This can happen if the process unexpectedly disappeared on us (such as a game crash). Press OK to continue; this tool will continue to operate normally. If this specific error keeps happening, please report it. If not, this warning is safe to ignore.\n\nError: {err:?}"),
         );
 
-        return Ok(());
+        return Ok(false);
     }
 
     // now call Init
-    let Some(module) = GetModuleBaseEx(&process, &loader.path) else {
-        warn_popup(
+    let Some(module) = GetModuleBaseEx(&process, &loader_path) else {
+        failure_popup(
             "Where is the module?",
             "Failed to find loader.dll module handle. Patching has been aborted on this process.\n\nThis could be due to multiple reasons, but in any case, winapi returned an error. This can happen if the process unexpectedly disappeared on us (such as a game crash). Press OK to continue; this tool will continue to operate normally. If this specific error keeps happening, please report it. If not, this warning is safe to ignore.",
         );
 
-        return Ok(());
+        return Ok(false);
     };
 
     let base = module.0 as usize;
@@ -237,7 +348,7 @@ pub fn run_loader(
     );
 
     let auth_code = rand::random::<u64>();
-    AUTH.store(auth_code, Ordering::Relaxed);
+    expect_loader(pid, auth_code);
 
     trace!(auth_code, "generated auth");
 
@@ -247,40 +358,142 @@ pub fn run_loader(
             level: LevelFilter::current().into(),
             target: config.log.target,
         },
+        phase,
+        quiet: QUIET.load(Ordering::Relaxed),
+        force: FORCE.load(Ordering::Relaxed),
+        portable: get_portable_dir().is_some(),
+        no_config_writeback: !is_config_writeback_enabled(),
     };
 
-    let Ok(ptr) = write_in(&process, &thread_data, size_of::<ThreadData>()) else {
+    let Ok(ptr) = write_in(
+        &process,
+        &thread_data,
+        size_of::<ThreadData>(),
+        config.core.max_write_size,
+    ) else {
         error!("failed to write ThreadData into process");
-        return Ok(());
+        return Ok(false);
     };
 
     let init_fn = unsafe { mem::transmute::<usize, LPTHREAD_START_ROUTINE>(init_addr) };
 
-    let thread = match RemoteThread::spawn(&process, init_fn, Some(ptr)) {
+    let thread = match RemoteThread::spawn_with_retry(&process, trigger, init_fn, Some(ptr)) {
         Ok(h) => h,
         Err(e) => {
             error!(
                 ?e,
+                %trigger,
+                attempts = SPAWN_ATTEMPTS,
                 base = %format!("0x{base:x}"),
                 rva = %format!("0x{:x}", loader.rva),
                 addr = %format!("0x{init_addr:x}"),
                 "Failed to create remote thread for init fn"
             );
 
-            warn_popup(
-                "Process injection failure for init fn",
-                format!("Failed to create process remote thread. Patching has been aborted on this process.\n\nThis could be due to multiple reasons, but in any case, winapi returned an error. This can happen if the process unexpectedly disappeared on us (such as a game crash). Please restart the game and try again. Press OK to continue; this tool will continue to operate normally.\n\nError: {e}"),
+            _ = free_in(&process, ptr);
+
+            failure_popup(
+                "Remote thread creation failure for init fn",
+                format!("Failed to create process remote thread after {SPAWN_ATTEMPTS} attempts. Patching has been aborted on this process.\n\nWriting into the process succeeded; it was starting the thread which failed. Antivirus interference is a common cause of this, as is the process unexpectedly disappearing on us (such as a game crash). Please restart the game and try again. Press OK to continue; this tool will continue to operate normally.\n\nError: {e}"),
             );
 
-            return Ok(());
+            return Ok(false);
         }
     };
 
     if wait_for_init {
         // ignore errors like timeout, etc, they don't matter, just wait
         // this MAY block for a LONG time
-        _ = thread.wait();
+        if thread.wait().is_ok() {
+            // Init is done reading it. if we didn't wait, there's no telling when that is, so it stays allocated
+            _ = free_in(&process, ptr);
+        }
+    }
+
+    // the resumed phase watches it, once the game is actually running. it's already watched when reinjecting
+    let watch = !matches!(phase, LoadPhase::Suspended | LoadPhase::Reinject);
+    if config.core.capture_minidump && watch {
+        minidump::watch(pid);
     }
 
+    Ok(true)
+}
+
+/// Wait for `thread` to exit, then free `ptr`, which it was given. If the wait fails, the thread may still be
+/// using it, so it stays allocated
+fn wait_then_free(
+    process: &OwnedHandle,
+    thread: &RemoteThread,
+    ptr: *const c_void,
+) -> Result<(), WIN32_ERROR> {
+    thread.wait()?;
+
+    // not fatal; at worst it stays allocated
+    _ = free_in(process, ptr);
+
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{process, thread, time::Duration};
+
+    use shared::{config::InjectionTrigger, popup::disable_popups};
+    use windows::Win32::System::Memory::{VirtualQueryEx, MEMORY_BASIC_INFORMATION, MEM_FREE};
+
+    use super::*;
+
+    /// Reads the value it's given after a moment, like LoadLibraryW reads the path
+    unsafe extern "system" fn read_arg(arg: *mut c_void) -> u32 {
+        thread::sleep(Duration::from_millis(50));
+        unsafe { *arg.cast::<u32>() }
+    }
+
+    #[test]
+    fn frees_once_thread_exited() {
+        let process = open_process(process::id()).unwrap();
+
+        let value = 0x5941_4247u32;
+        let ptr = write_in(&process, &value, size_of::<u32>(), 4096).unwrap();
+
+        let thread = RemoteThread::spawn(
+            &process,
+            InjectionTrigger::CreateRemoteThread,
+            Some(read_arg),
+            Some(ptr),
+        )
+        .unwrap();
+
+        wait_then_free(&process, &thread, ptr).unwrap();
+
+        // it was only freed after the thread was done reading it
+        assert_eq!(thread.exit_code().unwrap(), value);
+
+        let mut info = MEMORY_BASIC_INFORMATION::default();
+        let len = unsafe {
+            VirtualQueryEx(
+                process.as_raw_handle(),
+                Some(ptr),
+                &mut info,
+                size_of::<MEMORY_BASIC_INFORMATION>(),
+            )
+        };
+
+        assert_ne!(len, 0);
+        assert_eq!(info.State, MEM_FREE);
+    }
+
+    #[test]
+    fn oversized_write_is_rejected() {
+        disable_popups();
+        let process = open_process(process::id()).unwrap();
+
+        let data = [0u8; 64];
+        let e = write_in(&process, data.as_ptr(), data.len(), data.len() - 1).unwrap_err();
+        assert!(e.to_string().contains("exceeds max_write_size"), "{e}");
+
+        // up to the max is fine
+        let ptr = write_in(&process, data.as_ptr(), data.len(), data.len()).unwrap();
+        free_in(&process, ptr).unwrap();
+    }
+}
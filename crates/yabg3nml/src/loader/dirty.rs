@@ -2,16 +2,13 @@ use std::os::windows::{io::IntoRawHandle, prelude::OsStrExt};
 use std::path::Path;
 use std::{collections::HashMap, fs::OpenOptions, os::windows::fs::OpenOptionsExt as _};
 
-use eyre::{OptionExt as _, Result};
-use shared::{paths::get_bg3_plugins_dir, utils::OwnedHandle};
+use eyre::{eyre, Result};
+use shared::{config::Config, discovery::all_plugin_dirs, utils::OwnedHandle};
 use tracing::{trace, trace_span};
 use widestring::U16Str;
-use windows::Win32::{
-    Foundation::MAX_PATH,
-    Storage::FileSystem::{
-        FileIdInfo, GetFileInformationByHandleEx, FILE_FLAG_BACKUP_SEMANTICS, FILE_ID_INFO,
-        FILE_SHARE_READ,
-    },
+use windows::Win32::Storage::FileSystem::{
+    FileIdInfo, GetFileInformationByHandleEx, FILE_FLAG_BACKUP_SEMANTICS, FILE_ID_INFO,
+    FILE_SHARE_READ,
 };
 
 use crate::wapi::{
@@ -61,30 +58,40 @@ fn dir_id(path: &Path) -> Option<Id> {
 }
 
 // Determine whether the process has been tainted by previous dll injections
-pub fn is_dirty(process: &OwnedHandle, loader: &Path) -> Result<bool> {
+pub fn is_dirty(config: &Config, process: &OwnedHandle, loader: &Path) -> Result<bool> {
     let span = trace_span!("is_dirty");
     let _guard = span.enter();
 
     let loader = loader.as_os_str().encode_wide().collect::<Vec<_>>();
     let loader = U16Str::from_slice(&loader);
 
-    let mut plugins_dir = get_bg3_plugins_dir()?;
-    plugins_dir.as_mut_os_str().make_ascii_lowercase();
-
     let mut cache_id_map = HashMap::new();
+    // the plugins dir comes first, and every exe's target_plugin_dirs after it
+    let mut plugins_dir_ids = Vec::new();
+
+    for (i, mut dir) in all_plugin_dirs(config)?.into_iter().enumerate() {
+        dir.as_mut_os_str().make_ascii_lowercase();
 
-    trace!(plugins_dir = %plugins_dir.display(), "checking dll path against dirs");
+        trace!(plugins_dir = %dir.display(), "checking dll path against dirs");
 
-    let plugins_dir_id = dir_id(&plugins_dir).ok_or_eyre("failed to get id for plugins_dir")?;
-    cache_id_map.insert(plugins_dir, plugins_dir_id);
+        let id = match dir_id(&dir) {
+            Some(id) => id,
+            None if i == 0 => return Err(eyre!("failed to get id for plugins_dir")),
+            // a target_plugin_dirs dir which doesn't exist has nothing loaded from it
+            None => continue,
+        };
+
+        cache_id_map.insert(dir, id);
+        plugins_dir_ids.push(id);
+    }
 
     let mut is_plugin = move |path: &U16Str| -> Result<bool> {
         let mut path = path.to_string()?;
         path.make_ascii_lowercase();
         let path = Path::new(&path);
 
-        // not a dll file
-        if !path.is_file() || path.extension().unwrap_or_default() != "dll" {
+        // not a plugin file
+        if !path.is_file() || !config.core.has_plugin_extension(path) {
             return Ok(false);
         }
 
@@ -107,11 +114,11 @@ pub fn is_dirty(process: &OwnedHandle, loader: &Path) -> Result<bool> {
         };
 
         // if plugins dir is the same id as this one, then this is a plugin inside our plugins dir~
-        Ok(plugins_dir_id == id)
+        Ok(plugins_dir_ids.contains(&id))
     };
 
     let mut detected = false;
-    let mut buf = vec![0u16; MAX_PATH as usize];
+    let mut buf = Vec::new();
     EnumProcessModulesExRs(process, |module| {
         let path = GetModuleFileNameExRs(process, Some(module), &mut buf)?;
         let os_path = path.to_os_string();
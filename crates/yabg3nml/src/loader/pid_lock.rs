@@ -0,0 +1,60 @@
+use eyre::Result;
+use shared::utils::OwnedHandle;
+use tracing::{trace, trace_span, warn};
+use windows::{
+    core::HSTRING,
+    Win32::{
+        Foundation::{
+            DuplicateHandle, GetLastError, DUPLICATE_CLOSE_SOURCE, DUPLICATE_SAME_ACCESS,
+            ERROR_ALREADY_EXISTS, HANDLE,
+        },
+        System::Threading::{CreateMutexW, GetCurrentProcess},
+    },
+};
+
+use crate::process_watcher::Pid;
+
+/// Claim the right to inject into `pid`, so that multiple loaders (e.g. the watcher and autostart)
+/// never both inject the same process. Returns false if another loader already claimed it
+///
+/// The claim is a named mutex, which is handed off to the game process itself. This way the claim
+/// lives for exactly as long as the game does, regardless of what happens to this loader
+pub fn claim_pid(process: &OwnedHandle, pid: Pid) -> Result<bool> {
+    let span = trace_span!("claim_pid");
+    let _guard = span.enter();
+
+    let name = HSTRING::from(format!("yet-another-bg3-native-mod-loader-pid-{pid}"));
+
+    let mutex = unsafe { CreateMutexW(None, false, &name)? };
+
+    let err = unsafe { GetLastError() };
+    if err == ERROR_ALREADY_EXISTS {
+        // close our handle to it
+        drop(OwnedHandle::new(mutex));
+        return Ok(false);
+    }
+
+    let current = unsafe { GetCurrentProcess() };
+
+    let mut target = HANDLE::default();
+    // the source handle is always closed by this call, even when it fails
+    let res = unsafe {
+        DuplicateHandle(
+            current,
+            mutex,
+            process.as_raw_handle(),
+            &mut target,
+            0,
+            false,
+            DUPLICATE_SAME_ACCESS | DUPLICATE_CLOSE_SOURCE,
+        )
+    };
+
+    match res {
+        Ok(_) => trace!(pid, "claimed pid"),
+        // not fatal. worst case, another loader might also inject; the dirty check still catches most of those
+        Err(e) => warn!(%e, pid, "failed to hand off pid claim to the game process"),
+    }
+
+    Ok(true)
+}
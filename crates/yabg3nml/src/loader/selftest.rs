@@ -0,0 +1,132 @@
+use std::{
+    fs, iter, mem,
+    os::windows::prelude::OsStrExt as _,
+    path::Path,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+use eyre::{bail, eyre, OptionExt as _, Result};
+use pelite::{pe::PeFile, pe64::exports::GetProcAddress as _};
+use shared::{config::Config, paths::to_extended_path, pe::invalid_reason};
+use tracing::{error, info, trace, trace_span};
+use windows::Win32::System::Threading::LPTHREAD_START_ROUTINE;
+
+use super::{free_in, load_library_w, open_process, write_in};
+use crate::{
+    process_watcher::Pid, remote_thread::RemoteThread, wapi::get_module_base_ex::GetModuleBaseEx,
+};
+
+/// What the test dll's `SelfTest` export must return, to show it was loaded and ran. "YABG" in ascii
+pub const SELFTEST_MAGIC: u32 = 0x5941_4247;
+
+/// how long each of the test's remote threads may take before the test fails
+const SELFTEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// the self test only runs in the first game that's found
+static DONE: AtomicBool = AtomicBool::new(false);
+
+/// Load `selftest_dll` into the game the same way loader.dll is, call its `SelfTest` export, and log whether
+/// it returned [`SELFTEST_MAGIC`]. Only runs for the first game it's called with. Meant to check that
+/// injection still works after a game patch
+///
+/// The test dll contract: a 64-bit dll exporting `extern "system" fn SelfTest(_: *mut c_void) -> u32`,
+/// which returns [`SELFTEST_MAGIC`]. It's called on a new thread, outside of DllMain. Loading it and calling
+/// `SelfTest` must each finish within 30 seconds, or the test fails
+pub fn selftest(config: &Config, pid: Pid) {
+    let Some(dll) = config.core.selftest_dll.as_deref() else {
+        return;
+    };
+
+    if DONE.swap(true, Ordering::Relaxed) {
+        return;
+    }
+
+    let span = trace_span!("selftest");
+    let _guard = span.enter();
+
+    info!(pid, dll = %dll.display(), "Running self test");
+
+    match run(config, pid, dll) {
+        Ok(()) => info!(
+            pid,
+            "Self test PASSED; plugins can be loaded into this game"
+        ),
+        Err(e) => error!(%e, pid, "Self test FAILED"),
+    }
+}
+
+fn run(config: &Config, pid: Pid, dll: &Path) -> Result<()> {
+    let data = fs::read(dll).map_err(|e| eyre!("failed to read {}: {e}", dll.display()))?;
+
+    if let Some(reason) = invalid_reason(&data) {
+        bail!("{} can't be used because {reason}", dll.display());
+    }
+
+    let rva = PeFile::from_bytes(&data)?
+        .get_export("SelfTest")?
+        .symbol()
+        .ok_or_eyre("SelfTest export is forwarded")?;
+
+    let process = open_process(pid)?;
+    let trigger = config.core.injection_trigger;
+
+    // the same form loader.dll is loaded with, which is also what the module is reported as
+    let path = to_extended_path(std::path::absolute(dll)?);
+    let path_w = path
+        .as_os_str()
+        .encode_wide()
+        .chain(iter::once(0))
+        .collect::<Vec<_>>();
+
+    let ptr = write_in(
+        &process,
+        path_w.as_ptr(),
+        path_w.len() * size_of::<u16>(),
+        config.core.max_write_size,
+    )?;
+
+    let res = RemoteThread::spawn_with_retry(&process, trigger, load_library_w()?, Some(ptr))
+        .map_err(|e| eyre!("failed to start LoadLibraryW thread: {e}"))
+        .and_then(|thread| {
+            thread
+                .wait_timeout(SELFTEST_TIMEOUT)
+                .map_err(|e| eyre!("failed to wait for LoadLibraryW thread: {e:?}"))
+        });
+
+    match res {
+        Ok(true) => _ = free_in(&process, ptr),
+        // the thread may still read the path, so it's leaked rather than freed
+        Ok(false) => bail!("LoadLibraryW timed out after {SELFTEST_TIMEOUT:?}"),
+        Err(e) => {
+            _ = free_in(&process, ptr);
+            return Err(e);
+        }
+    }
+
+    let module = GetModuleBaseEx(&process, &path)
+        .ok_or_eyre("the test dll is not loaded in the game; LoadLibraryW failed inside of it")?;
+
+    let addr = module.0 as usize + rva as usize;
+    trace!(addr = %format!("0x{addr:x}"), "calling SelfTest");
+
+    let self_test = unsafe { mem::transmute::<usize, LPTHREAD_START_ROUTINE>(addr) };
+
+    let thread = RemoteThread::spawn_with_retry(&process, trigger, self_test, None)
+        .map_err(|e| eyre!("failed to start SelfTest thread: {e}"))?;
+
+    let exited = thread
+        .wait_timeout(SELFTEST_TIMEOUT)
+        .map_err(|e| eyre!("failed to wait for SelfTest thread: {e:?}"))?;
+
+    if !exited {
+        bail!("SelfTest timed out after {SELFTEST_TIMEOUT:?}");
+    }
+
+    let code = thread.exit_code()?;
+    if code != SELFTEST_MAGIC {
+        bail!("SelfTest returned 0x{code:x} instead of 0x{SELFTEST_MAGIC:x}");
+    }
+
+    Ok(())
+}
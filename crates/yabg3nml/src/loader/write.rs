@@ -1,20 +1,48 @@
 use std::ffi::c_void;
 
 use eyre::{bail, Result};
-use shared::{popup::warn_popup, utils::OwnedHandle};
-use tracing::{error, trace_span};
+use shared::utils::OwnedHandle;
+use tracing::{error, trace, trace_span};
+
+use super::failure_popup;
 use windows::Win32::{
     Foundation::GetLastError,
     System::{
         Diagnostics::Debug::WriteProcessMemory,
-        Memory::{VirtualAllocEx, MEM_COMMIT, MEM_RESERVE, PAGE_READWRITE},
+        Memory::{
+            VirtualAllocEx, VirtualFreeEx, MEM_COMMIT, MEM_RELEASE, MEM_RESERVE, PAGE_READWRITE,
+        },
     },
 };
 
-pub fn write_in<T>(process: &OwnedHandle, data: *const T, size: usize) -> Result<*const c_void> {
+/// Allocate `size` bytes in the process and copy `data` there. Refuses anything bigger than `max_size`
+///
+/// The memory is never executable. Only data is written into the game, and executable allocations
+/// are blocked on systems with mitigations like ACG
+pub fn write_in<T>(
+    process: &OwnedHandle,
+    data: *const T,
+    size: usize,
+    max_size: usize,
+) -> Result<*const c_void> {
     let span = trace_span!("write_in");
     let _guard = span.enter();
 
+    // a bad size would otherwise reserve a huge amount of the game's memory
+    if size > max_size {
+        error!(
+            size,
+            max_size, "refusing to write more than max_write_size into process"
+        );
+
+        failure_popup(
+            "Write too large",
+            format!("Refused to write {size} bytes into the game process, since that is more than `max_write_size` ({max_size} bytes) in config.toml. Patching has been aborted on this process.\n\nThis is most likely a bug, please report it. Press OK to continue; this tool will continue to operate normally."),
+        );
+
+        bail!("write of {size} bytes exceeds max_write_size of {max_size} bytes");
+    }
+
     let alloc_addr = {
         let addr = unsafe {
             VirtualAllocEx(
@@ -34,7 +62,7 @@ pub fn write_in<T>(process: &OwnedHandle, data: *const T, size: usize) -> Result
 
             error!(%error, "VirtualAllocEx failed to allocate memory");
 
-            warn_popup(
+            failure_popup(
                 "Allocation failure",
                 format!("Failed to allocate in target process. Patching has been aborted on this process.\n\nThis could be due to multiple reasons, but in any case, winapi returned an error. Recommend restarting game and trying again. Press OK to continue; this tool will continue to operate normally.\n\nError: {error}"),
             );
@@ -58,7 +86,7 @@ pub fn write_in<T>(process: &OwnedHandle, data: *const T, size: usize) -> Result
     if let Err(e) = res {
         error!(?e, "Failed to write to process");
 
-        warn_popup(
+        failure_popup(
             "Write failure",
             format!("Failed to write to process memory. Patching has been aborted on this process.\n\nThis could be due to multiple reasons, but in any case, winapi returned an error. Recommend restarting game and trying again. Press OK to continue; this tool will continue to operate normally.\n\nError: {e}"),
         );
@@ -68,3 +96,20 @@ pub fn write_in<T>(process: &OwnedHandle, data: *const T, size: usize) -> Result
 
     Ok(alloc_addr)
 }
+
+/// Free an allocation made by [`write_in`]. The remote process must be done using it
+pub fn free_in(process: &OwnedHandle, addr: *const c_void) -> Result<()> {
+    let span = trace_span!("free_in");
+    let _guard = span.enter();
+
+    let res = unsafe { VirtualFreeEx(process.as_raw_handle(), addr.cast_mut(), 0, MEM_RELEASE) };
+
+    if let Err(e) = res {
+        error!(?e, addr = ?addr, "Failed to free process memory");
+        bail!("{e}");
+    }
+
+    trace!(addr = ?addr, "freed process memory");
+
+    Ok(())
+}
@@ -1,26 +1,142 @@
-use std::{env, path::Path};
+use std::{
+    env, fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process,
+    sync::{Mutex, OnceLock},
+    time::{Duration, SystemTime},
+};
 
-use eyre::Result;
-use shared::config::Config;
-use tracing::level_filters::LevelFilter;
-use tracing_appender::non_blocking::WorkerGuard;
+use eyre::{OptionExt as _, Result};
+use shared::{
+    config::Config, exit::set_before_exit, paths::get_bg3_plugins_dir, popup::warn_popup,
+    utils::SuperLock as _,
+};
+use tracing::{dispatcher::DefaultGuard, level_filters::LevelFilter, warn};
+use tracing_appender::{
+    non_blocking::{NonBlocking, WorkerGuard},
+    rolling::RollingFileAppender,
+};
 use tracing_subscriber::EnvFilter;
+use windows::{
+    core::HSTRING,
+    Win32::System::{Diagnostics::Debug::OutputDebugStringW, SystemInformation::GetLocalTime},
+};
 
-pub fn setup_logs<P: AsRef<Path>>(config: &Config, plugins_dir: P) -> Result<Option<WorkerGuard>> {
-    let mut worker_guard: Option<WorkerGuard> = None;
+pub const LOG_NAME: &str = "ya-bg3-native-mod-loader";
+const PER_RUN_PREFIX: &str = "ya-bg3-native-mod-loader.run-";
+
+/// the file log writer's guard; its buffered lines are only written out once it's dropped
+static WORKER_GUARD: Mutex<Option<WorkerGuard>> = Mutex::new(None);
+
+/// the dir the log files are written to, once logging was set up with one
+static LOGS_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+/// Flushes the file logs when dropped. [`shared::exit::exit`] flushes them too
+pub struct LogGuard(());
+
+impl Drop for LogGuard {
+    fn drop(&mut self) {
+        flush();
+    }
+}
+
+/// Write out all buffered log lines. Nothing is logged to the file afterwards
+fn flush() {
+    drop(WORKER_GUARD.super_lock().take());
+}
+
+/// A writer to `writer`, which buffers lines and writes them out on a worker thread. What's buffered is
+/// written out by [`flush`], which also runs before [`shared::exit::exit`]
+fn buffered_writer<W: Write + Send + 'static>(writer: W) -> NonBlocking {
+    let (non_blocking, guard) = tracing_appender::non_blocking(writer);
+
+    *WORKER_GUARD.super_lock() = Some(guard);
+    // exiting skips destructors, which would lose whatever is still buffered
+    set_before_exit(flush);
+
+    non_blocking
+}
+
+/// Log to the debugger (OutputDebugString, e.g. visible in DebugView) until [`setup_logs`] runs, so failures
+/// from before then still leave a trace. Only applies to the current thread, and stops once the guard is dropped
+pub fn bootstrap_logs() -> DefaultGuard {
+    let subscriber = tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::new(LevelFilter::INFO.to_string()))
+        .with_writer(|| DebugStringWriter(Vec::new()))
+        .without_time()
+        .with_ansi(false)
+        .finish();
+
+    tracing::subscriber::set_default(subscriber)
+}
+
+/// Buffers a single log line, and sends it to the debugger once dropped
+struct DebugStringWriter(Vec<u8>);
+
+impl Write for DebugStringWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for DebugStringWriter {
+    fn drop(&mut self) {
+        if self.0.is_empty() {
+            return;
+        }
+
+        let line = HSTRING::from(&*String::from_utf8_lossy(&self.0));
+        // a no-op if no debugger is listening
+        unsafe { OutputDebugStringW(&line) };
+    }
+}
+
+/// Log to the console or the logs dir from now on, for every thread. `bootstrap` is dropped as soon as this
+/// logger is installed, so nothing after that goes to the debugger instead
+pub fn setup_logs<P: AsRef<Path>>(
+    config: &Config,
+    plugins_dir: P,
+    bootstrap: DefaultGuard,
+) -> Result<Option<LogGuard>> {
+    let mut worker_guard: Option<LogGuard> = None;
+
+    let console = cfg!(debug_assertions) || config.core.cli;
 
     // env var takes precedence over config value
     let env = env::var("YABG3NML_LOG");
-    let env = env.as_deref().unwrap_or(&config.log.level);
-    let filter = EnvFilter::builder()
+    let directives = match env.as_deref() {
+        Ok(env) => env,
+        Err(_) if console => config
+            .log
+            .console_log_filter
+            .as_deref()
+            .unwrap_or(&config.log.level),
+        Err(_) => &config.log.level,
+    };
+
+    // reported once the logger is up
+    let (filter, filter_err) = match EnvFilter::builder()
         .with_default_directive(LevelFilter::INFO.into())
-        .parse_lossy(env);
+        .parse(directives)
+    {
+        Ok(filter) => (filter, None),
+        Err(e) => (EnvFilter::new(LevelFilter::INFO.to_string()), Some(e)),
+    };
 
-    if cfg!(debug_assertions) || config.core.cli {
+    if console {
         #[cfg(not(debug_assertions))]
         {
             use crate::console::debug_console;
-            debug_console("Yet Another BG3 Native Mod Loader Debug Console")?;
+            debug_console(
+                "Yet Another BG3 Native Mod Loader Debug Console",
+                config.log.console_utf8,
+            )?;
         }
 
         tracing_subscriber::fmt()
@@ -28,22 +144,240 @@ pub fn setup_logs<P: AsRef<Path>>(config: &Config, plugins_dir: P) -> Result<Opt
             .with_target(config.log.target)
             .without_time()
             .init();
+
+        drop(bootstrap);
     } else {
-        let plugins_dir = plugins_dir.as_ref();
-        let logs_dir = plugins_dir.join("logs");
+        let logs_dir = plugins_dir.as_ref().join("logs");
 
-        let file_appender = tracing_appender::rolling::daily(logs_dir, "ya-bg3-native-mod-loader");
-        let (non_blocking, _guard) = tracing_appender::non_blocking(file_appender);
+        // the logs dir may not be writable (permissions, read-only media, etc). logging should never
+        // stop injection from happening, so fall back to the temp dir, and if even that fails, stdout
+        let (dir, fell_back) = log_dir(&logs_dir, &fallback_log_dir());
 
-        worker_guard = Some(_guard);
-        tracing_subscriber::fmt()
-            .with_env_filter(filter)
-            .with_writer(non_blocking)
-            .with_target(config.log.target)
-            .without_time()
-            .with_ansi(false)
-            .init();
+        match dir.as_ref() {
+            Some(dir) => {
+                _ = LOGS_DIR.set(dir.clone());
+
+                let file_appender = if config.log.per_run_log {
+                    prune_run_logs(dir, config.log.max_log_days);
+                    per_run_appender(dir)
+                } else {
+                    tracing_appender::rolling::daily(dir, LOG_NAME)
+                };
+                worker_guard = Some(LogGuard(()));
+                tracing_subscriber::fmt()
+                    .with_env_filter(filter)
+                    .with_writer(buffered_writer(file_appender))
+                    .with_target(config.log.target)
+                    .without_time()
+                    .with_ansi(false)
+                    .init();
+            }
+
+            None => {
+                tracing_subscriber::fmt()
+                    .with_env_filter(filter)
+                    .with_target(config.log.target)
+                    .without_time()
+                    .with_ansi(false)
+                    .init();
+            }
+        }
+
+        drop(bootstrap);
+
+        if fell_back {
+            let location = dir
+                .as_ref()
+                .map(|d| d.display().to_string())
+                .unwrap_or_else(|| "stdout".to_owned());
+
+            warn!(logs_dir = %logs_dir.display(), %location, "logs dir is not writable; using fallback log location");
+
+            warn_popup(
+                "Logs folder not writable",
+                format!(
+                    "The logs folder at\n{}\nis not writable, so logs will be written to\n{location}\n\nThis does not affect plugin loading.",
+                    logs_dir.display()
+                ),
+            );
+        }
+    }
+
+    if let Some(e) = filter_err {
+        warn!(directives, %e, "invalid log filter; falling back to info");
     }
 
     Ok(worker_guard)
 }
+
+/// A log file just for this run, e.g. `ya-bg3-native-mod-loader.run-2025-02-01_13-05-09-1234.log`
+fn per_run_appender(dir: &Path) -> RollingFileAppender {
+    let time = unsafe { GetLocalTime() };
+
+    let name = format!(
+        "{PER_RUN_PREFIX}{:04}-{:02}-{:02}_{:02}-{:02}-{:02}-{}.log",
+        time.wYear,
+        time.wMonth,
+        time.wDay,
+        time.wHour,
+        time.wMinute,
+        time.wSecond,
+        process::id()
+    );
+
+    tracing_appender::rolling::never(dir, name)
+}
+
+/// Remove per-run log files older than `max_days`
+fn prune_run_logs(dir: &Path, max_days: u64) {
+    if max_days == 0 {
+        return;
+    }
+
+    let max_age = Duration::from_secs(max_days * 24 * 60 * 60);
+
+    let Ok(read_dir) = fs::read_dir(dir) else {
+        return;
+    };
+
+    for entry in read_dir.flatten() {
+        let is_run_log = entry
+            .file_name()
+            .to_string_lossy()
+            .starts_with(PER_RUN_PREFIX);
+        if !is_run_log {
+            continue;
+        }
+
+        let age = entry
+            .metadata()
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|m| SystemTime::now().duration_since(m).ok());
+
+        if age.is_some_and(|age| age > max_age) {
+            // logging isn't set up yet, so there's nowhere to report failures to
+            _ = fs::remove_file(entry.path());
+        }
+    }
+}
+
+/// The dir the log files are written to, for files that belong with them. If logging doesn't write to a
+/// dir, it's picked the same way logging picks it. Always exists
+pub fn logs_dir() -> Result<PathBuf> {
+    let dir = match LOGS_DIR.get() {
+        Some(dir) => dir.clone(),
+        None => {
+            let logs_dir = get_bg3_plugins_dir()?.join("logs");
+            log_dir(&logs_dir, &fallback_log_dir())
+                .0
+                .ok_or_eyre("neither the logs dir nor the fallback log dir is writable")?
+        }
+    };
+
+    // it may have been removed since
+    fs::create_dir_all(&dir)?;
+
+    Ok(dir)
+}
+
+/// Where logs go when the logs dir isn't writable
+fn fallback_log_dir() -> PathBuf {
+    env::temp_dir().join("yabg3nml-logs")
+}
+
+/// The dir to write logs to: `logs_dir`, or `fallback` if it isn't writable. None if neither is.
+/// Also whether it fell back
+fn log_dir(logs_dir: &Path, fallback: &Path) -> (Option<PathBuf>, bool) {
+    match check_writable(logs_dir) {
+        Ok(_) => (Some(logs_dir.to_owned()), false),
+        Err(_) if check_writable(fallback).is_ok() => (Some(fallback.to_owned()), true),
+        Err(_) => (None, true),
+    }
+}
+
+/// Make sure `dir` exists and files can be created in it
+fn check_writable(dir: &Path) -> std::io::Result<()> {
+    fs::create_dir_all(dir)?;
+
+    let probe = dir.join(".write-test");
+    fs::write(&probe, [])?;
+    _ = fs::remove_file(probe);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::Command;
+
+    use shared::exit::exit;
+    use tempfile::tempdir;
+    use tracing::info;
+
+    use super::*;
+
+    /// set for the child process the test runs, to the log file it writes
+    const CHILD_LOG: &str = "YABG3NML_TEST_EXIT_LOG";
+
+    #[test]
+    fn writable_logs_dir_is_used() {
+        let tmp = tempdir().unwrap();
+        let dir = tmp.path();
+        let logs_dir = dir.join("logs");
+
+        assert_eq!(
+            log_dir(&logs_dir, &dir.join("fallback")),
+            (Some(logs_dir), false)
+        );
+    }
+
+    #[test]
+    fn unwritable_logs_dir_falls_back() {
+        let tmp = tempdir().unwrap();
+        let dir = tmp.path();
+
+        // a file is in the way, so the logs dir can't be created
+        let blocker = dir.join("plugins");
+        fs::write(&blocker, []).unwrap();
+        let logs_dir = blocker.join("logs");
+
+        let fallback = dir.join("fallback");
+        assert_eq!(log_dir(&logs_dir, &fallback), (Some(fallback), true));
+
+        // and if the fallback can't be created either, it's stdout
+        let fallback = blocker.join("fallback");
+        assert_eq!(log_dir(&logs_dir, &fallback), (None, true));
+    }
+
+    #[test]
+    fn fatal_exit_flushes_logs() {
+        // the child logs, then exits like a fatal error does, which skips every destructor
+        if let Some(path) = env::var_os(CHILD_LOG) {
+            let file = fs::File::create(path).unwrap();
+            let subscriber = tracing_subscriber::fmt()
+                .with_writer(buffered_writer(file))
+                .with_ansi(false)
+                .finish();
+
+            tracing::subscriber::set_global_default(subscriber).unwrap();
+
+            info!("logged right before exiting");
+            exit(3);
+        }
+
+        let tmp = tempdir().unwrap();
+        let path = tmp.path().join("exit.log");
+
+        let status = Command::new(env::current_exe().unwrap())
+            .args(["--exact", "logging::tests::fatal_exit_flushes_logs"])
+            .env(CHILD_LOG, &path)
+            .status()
+            .unwrap();
+
+        assert_eq!(status.code(), Some(3));
+
+        let log = fs::read_to_string(&path).unwrap();
+        assert!(log.contains("logged right before exiting"), "{log}");
+    }
+}
@@ -0,0 +1,233 @@
+use std::{
+    fs::{self, File},
+    os::windows::io::AsRawHandle as _,
+    path::PathBuf,
+    ptr, thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use eyre::Result;
+use shared::utils::OwnedHandle;
+use tracing::{error, info, trace, trace_span, warn};
+use windows::Win32::{
+    Foundation::{
+        CloseHandle, DBG_CONTINUE, DBG_EXCEPTION_NOT_HANDLED, ERROR_SEM_TIMEOUT,
+        EXCEPTION_BREAKPOINT, HANDLE,
+    },
+    System::{
+        Diagnostics::Debug::{
+            ContinueDebugEvent, DebugActiveProcess, DebugActiveProcessStop,
+            DebugSetProcessKillOnExit, GetThreadContext, MiniDumpWithIndirectlyReferencedMemory,
+            MiniDumpWithThreadInfo, MiniDumpWriteDump, WaitForDebugEvent, CONTEXT,
+            CONTEXT_ALL_AMD64, CREATE_PROCESS_DEBUG_EVENT, DEBUG_EVENT, EXCEPTION_DEBUG_EVENT,
+            EXCEPTION_POINTERS, EXCEPTION_RECORD, EXIT_PROCESS_DEBUG_EVENT, LOAD_DLL_DEBUG_EVENT,
+            MINIDUMP_EXCEPTION_INFORMATION, MINIDUMP_TYPE,
+        },
+        Threading::{
+            OpenProcess, OpenThread, PROCESS_QUERY_INFORMATION, PROCESS_VM_READ,
+            THREAD_GET_CONTEXT, THREAD_QUERY_INFORMATION,
+        },
+    },
+};
+
+use crate::{logging::logs_dir, process_watcher::Pid};
+
+/// how long after injection a crash is still blamed on it
+const WINDOW: Duration = Duration::from_secs(60);
+const POLL_RATE: Duration = Duration::from_millis(250);
+
+/// Watch the game for a crash shortly after injection, and write a minidump of it to the logs dir if it does.
+/// This runs on its own thread, and never fails loudly
+///
+/// The game is watched as a debugger, since that's the only way to see an unhandled exception while the game
+/// still exists. Nothing else may be debugging the game, and it's detached from again after the window
+pub fn watch(pid: Pid) {
+    thread::spawn(move || {
+        let span = trace_span!("minidump");
+        let _guard = span.enter();
+
+        if let Err(e) = watch_process(pid) {
+            warn!(%e, pid, "failed to watch game for a crash; is another debugger attached?");
+        }
+    });
+}
+
+fn watch_process(pid: Pid) -> Result<()> {
+    let process: OwnedHandle =
+        unsafe { OpenProcess(PROCESS_QUERY_INFORMATION | PROCESS_VM_READ, false, pid)? }.into();
+
+    // fails if another debugger is already attached
+    unsafe { DebugActiveProcess(pid)? };
+
+    // detaching must never take the game down with it
+    if let Err(e) = unsafe { DebugSetProcessKillOnExit(false) } {
+        warn!(%e, "DebugSetProcessKillOnExit failed");
+    }
+
+    trace!(pid, window = ?WINDOW, "watching game for a crash");
+
+    let res = debug_loop(&process, pid);
+
+    // must be called from the same thread which attached. fails if the game already exited, which is fine
+    _ = unsafe { DebugActiveProcessStop(pid) };
+
+    res
+}
+
+/// Handle the game's debug events until it crashes or exits, or the window is over
+fn debug_loop(process: &OwnedHandle, pid: Pid) -> Result<()> {
+    let start = Instant::now();
+    let mut attached = false;
+
+    while start.elapsed() < WINDOW {
+        let mut event = DEBUG_EVENT::default();
+        match unsafe { WaitForDebugEvent(&mut event, POLL_RATE.as_millis() as u32) } {
+            Ok(()) => (),
+            Err(e) if e.code() == ERROR_SEM_TIMEOUT.to_hresult() => continue,
+            Err(e) => return Err(e.into()),
+        }
+
+        let mut status = DBG_CONTINUE;
+        let mut crashed = false;
+
+        // the file handles of these events are ours to close
+        match event.dwDebugEventCode {
+            CREATE_PROCESS_DEBUG_EVENT => {
+                let file = unsafe { event.u.CreateProcessInfo.hFile };
+                if !file.is_invalid() {
+                    _ = unsafe { CloseHandle(file) };
+                }
+            }
+
+            LOAD_DLL_DEBUG_EVENT => {
+                let file = unsafe { event.u.LoadDll.hFile };
+                if !file.is_invalid() {
+                    _ = unsafe { CloseHandle(file) };
+                }
+            }
+
+            EXCEPTION_DEBUG_EVENT => {
+                let info = unsafe { event.u.Exception };
+                let code = info.ExceptionRecord.ExceptionCode;
+
+                if !attached && code == EXCEPTION_BREAKPOINT {
+                    // the breakpoint Windows raises once attached
+                    attached = true;
+                } else if info.dwFirstChance == 0 {
+                    // the game didn't handle it, so it's about to die. every thread is stopped until it's continued
+                    warn!(pid, code = %format!("0x{:X}", code.0), "Game crashed shortly after injection");
+                    dump(process, pid, event.dwThreadId, info.ExceptionRecord);
+                    status = DBG_EXCEPTION_NOT_HANDLED;
+                    crashed = true;
+                } else {
+                    // the game gets to handle its own exceptions
+                    status = DBG_EXCEPTION_NOT_HANDLED;
+                }
+            }
+
+            EXIT_PROCESS_DEBUG_EVENT => {
+                let code = unsafe { event.u.ExitProcess.dwExitCode };
+                trace!(pid, code, "game exited");
+                return Ok(());
+            }
+
+            _ => (),
+        }
+
+        unsafe { ContinueDebugEvent(pid, event.dwThreadId, status)? };
+
+        if crashed {
+            return Ok(());
+        }
+    }
+
+    trace!(pid, "game didn't crash shortly after injection");
+
+    Ok(())
+}
+
+/// Write a minidump of the game to the logs dir, logging where it went
+fn dump(process: &OwnedHandle, pid: Pid, thread_id: u32, record: EXCEPTION_RECORD) {
+    match write_dump(process, pid, thread_id, record) {
+        Ok(path) => info!(path = %path.display(), "Wrote minidump of the game"),
+        Err(e) => error!(%e, pid, "failed to write minidump of the game"),
+    }
+}
+
+fn write_dump(
+    process: &OwnedHandle,
+    pid: Pid,
+    thread_id: u32,
+    mut record: EXCEPTION_RECORD,
+) -> Result<PathBuf> {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+
+    let path = logs_dir()?.join(format!("crash-{pid}-{timestamp}.dmp"));
+
+    let file = File::create(&path)?;
+
+    let dump_type =
+        MINIDUMP_TYPE(MiniDumpWithIndirectlyReferencedMemory.0 | MiniDumpWithThreadInfo.0);
+
+    // the dump still works without the exception, it just doesn't point at the crash
+    let mut context = thread_context(thread_id)
+        .inspect_err(|e| warn!(%e, "failed to get the crashed thread's context"))
+        .ok();
+
+    let mut pointers = EXCEPTION_POINTERS {
+        ExceptionRecord: &mut record,
+        ContextRecord: context.as_mut().map_or(ptr::null_mut(), ptr::from_mut),
+    };
+
+    let exception = MINIDUMP_EXCEPTION_INFORMATION {
+        ThreadId: thread_id,
+        ExceptionPointers: &mut pointers,
+        // they point into this process, not the game
+        ClientPointers: false.into(),
+    };
+
+    let res = unsafe {
+        MiniDumpWriteDump(
+            process.as_raw_handle(),
+            pid,
+            HANDLE(file.as_raw_handle()),
+            dump_type,
+            context.is_some().then_some(ptr::from_ref(&exception)),
+            None,
+            None,
+        )
+    };
+
+    if let Err(e) = res {
+        // don't leave an empty dump behind
+        drop(file);
+        _ = fs::remove_file(&path);
+        return Err(e.into());
+    }
+
+    Ok(path)
+}
+
+/// The registers of a thread in the game, which must be stopped
+fn thread_context(thread_id: u32) -> Result<CONTEXT> {
+    let thread: OwnedHandle = unsafe {
+        OpenThread(
+            THREAD_GET_CONTEXT | THREAD_QUERY_INFORMATION,
+            false,
+            thread_id,
+        )?
+    }
+    .into();
+
+    let mut context = CONTEXT {
+        ContextFlags: CONTEXT_ALL_AMD64,
+        ..Default::default()
+    };
+
+    unsafe { GetThreadContext(thread.as_raw_handle(), &mut context)? };
+
+    Ok(context)
+}
@@ -1,12 +1,76 @@
 use std::{
-    fmt::Display,
+    env,
+    fmt::{Display, Write as _},
     fs,
     path::{Path, PathBuf},
+    sync::OnceLock,
+    thread,
+    time::{Duration, Instant},
 };
 
-use shared::{config::Config, popup::fatal_popup};
-use tracing::{error, trace};
+use shared::{
+    config::Config,
+    paths::{bg3_plugins_dir_path, LAUNCHER_EXE},
+    popup::fatal_popup,
+    utils::OwnedHandle,
+};
+use tracing::{error, info, trace, warn};
 use unicase::UniCase;
+use windows::{
+    core::HSTRING,
+    Win32::{
+        Storage::FileSystem::{GetDriveTypeW, GetLogicalDrives},
+        System::Threading::{OpenProcess, PROCESS_QUERY_LIMITED_INFORMATION},
+    },
+};
+
+use crate::wapi::{
+    enum_processes::EnumProcessesRs, query_full_process_image_name::QueryFullProcessImageNameRs,
+};
+
+/// how often to look for the game binaries again while waiting for them
+const BINARY_POLL: Duration = Duration::from_secs(1);
+
+/// the drive type of hard drives and ssds, from GetDriveTypeW
+const DRIVE_FIXED: u32 = 3;
+
+/// the install root of a game which was already running when it was first needed. kept for the whole session
+static RUNNING_ROOT: OnceLock<PathBuf> = OnceLock::new();
+
+/// The game's install root. If the game was already running the first time its binaries were looked for,
+/// this is where that game is installed, instead of `install_root`
+pub fn install_root(config: &'static Config) -> &'static Path {
+    RUNNING_ROOT
+        .get()
+        .map(PathBuf::as_path)
+        .unwrap_or(&config.core.install_root)
+}
+
+/// Find the install root of an already running game, from its exe path (`<root>\bin\bg3.exe`)
+///
+/// Game exes are recognized the same way [`get_game_binary_for`] maps them, which can't be used here
+/// since it resolves the exes through this
+fn running_game_root() -> Option<PathBuf> {
+    let mut pid_buf = vec![0u32; 1024];
+    let mut path_buf = Vec::new();
+
+    EnumProcessesRs(&mut pid_buf).iter().find_map(|&pid| {
+        let process = unsafe { OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, false, pid) }.ok()?;
+        let process = OwnedHandle::new(process);
+
+        let path = QueryFullProcessImageNameRs(&process, &mut path_buf).ok()?;
+        let path = PathBuf::from(path.to_os_string());
+
+        if matches!(Bg3Exe::from(path.as_path()), Bg3Exe::None) {
+            return None;
+        }
+
+        let root = path.parent()?.parent()?.to_owned();
+        trace!(pid, root = %root.display(), "found running game");
+
+        Some(root)
+    })
+}
 
 #[allow(dead_code)]
 pub struct Bg3Exes {
@@ -17,50 +81,27 @@ pub struct Bg3Exes {
 #[allow(dead_code)]
 pub fn get_game_binary_paths(config: &Config) -> Bg3Exes {
     let bin = config.core.install_root.join("bin");
+    let timeout = Duration::from_millis(config.core.game_binary_timeout);
+    let start = Instant::now();
+    let mut waiting = false;
 
-    // first check current directory or 1 directory up for exes before using config value
-    let check_dirs = [".", "..", &bin.to_string_lossy()];
-    for dir in check_dirs {
-        let path = Path::new(dir);
-
-        let bg3 = path.join("bg3.exe");
-        let bg3_dx11 = path.join("bg3_dx11.exe");
-
-        if bg3.is_file() && bg3_dx11.is_file() {
-            let bg3 = match fs::canonicalize(&bg3) {
-                Ok(p) => p,
-                Err(e) => {
-                    error!(error = %e, path = %bg3.display(), "failed to canonicalize");
-                    continue;
-                }
-            };
-
-            let bg3_dx11 = match fs::canonicalize(&bg3_dx11) {
-                Ok(p) => p,
-                Err(e) => {
-                    error!(error = %e, path = %bg3_dx11.display(), "failed to canonicalize");
-                    continue;
-                }
-            };
-
-            // canonicalize adds this to the prefix, but we don't want it
-            let bg3 = bg3
-                .to_string_lossy()
-                .strip_prefix(r"\\?\")
-                .map(ToOwned::to_owned)
-                .unwrap_or_else(|| bg3.to_string_lossy().to_string());
-
-            let bg3_dx11 = bg3_dx11
-                .to_string_lossy()
-                .strip_prefix(r"\\?\")
-                .map(ToOwned::to_owned)
-                .unwrap_or_else(|| bg3_dx11.to_string_lossy().to_string());
-
-            trace!(path = %bg3, "Looking for bg3");
-            trace!(path = %bg3_dx11, "Looking for bg3_dx11");
-
-            return Bg3Exes { bg3, bg3_dx11 };
+    loop {
+        if let Some(exes) = search_game_binaries(&bin) {
+            return exes;
         }
+
+        // the game may be mid-update, with its exes temporarily gone. if the bin dir itself is missing,
+        // install_root is just wrong, and there's nothing to wait for
+        if !bin.is_dir() || start.elapsed() >= timeout {
+            break;
+        }
+
+        if !waiting {
+            waiting = true;
+            info!(dir = %bin.display(), ?timeout, "Game binaries not found; waiting for them to appear");
+        }
+
+        thread::sleep(BINARY_POLL);
     }
 
     fatal_popup(
@@ -69,6 +110,206 @@ pub fn get_game_binary_paths(config: &Config) -> Bg3Exes {
     );
 }
 
+/// Every path the tools resolve, one per line, for checking path resolution. Nothing is waited on,
+/// and paths which can't be resolved are reported instead of being fatal
+pub fn describe_paths(config: &Config, loader: &Path) -> String {
+    let mut out = String::new();
+
+    let mut line = |name: &str, value: &dyn Display| {
+        _ = writeln!(out, "{name}: {value}");
+    };
+
+    line("install_root", &config.core.install_root.display());
+
+    match search_game_binaries(&config.core.install_root.join("bin")) {
+        Some(exes) => {
+            line("bg3.exe", &exes.bg3);
+            line("bg3_dx11.exe", &exes.bg3_dx11);
+        }
+
+        None => {
+            line("bg3.exe", &"not found");
+            line("bg3_dx11.exe", &"not found");
+        }
+    }
+
+    if let Some(root) = RUNNING_ROOT.get() {
+        line("running game root", &root.display());
+    }
+
+    // nothing is created, since this only reports where things are
+    let missing = |path: &Path| {
+        if path.is_dir() {
+            ""
+        } else {
+            " (doesn't exist yet)"
+        }
+    };
+
+    match bg3_plugins_dir_path() {
+        Ok(dir) => {
+            let logs = dir.join("logs");
+            line(
+                "plugins dir",
+                &format!("{}{}", dir.display(), missing(&dir)),
+            );
+            line("logs dir", &format!("{}{}", logs.display(), missing(&logs)));
+        }
+
+        Err(e) => {
+            line("plugins dir", &format!("not found ({e})"));
+            line("logs dir", &"not found");
+        }
+    }
+
+    let exists = if loader.is_file() { "" } else { " (not found)" };
+    line("loader", &format!("{}{exists}", loader.display()));
+
+    out
+}
+
+/// Look for the game exes in every place they may be
+fn search_game_binaries(bin: &Path) -> Option<Bg3Exes> {
+    // a game the user already started is the one that should be injected, wherever it's installed
+    if RUNNING_ROOT.get().is_none() {
+        if let Some(root) = running_game_root() {
+            if find_in(&root.join("bin")).is_some() {
+                info!(root = %root.display(), "Resolved install_root from the running game process; using it instead of the config for this session");
+                _ = RUNNING_ROOT.set(root);
+            }
+        }
+    }
+
+    let running_bin = RUNNING_ROOT.get().map(|root| root.join("bin"));
+
+    // first check current directory or 1 directory up for exes before using config value
+    let check_dirs = [
+        ("current dir", Some(PathBuf::from("."))),
+        ("parent dir", Some(PathBuf::from(".."))),
+        ("running game", running_bin),
+        ("install_root", Some(bin.to_owned())),
+    ];
+
+    for (source, dir) in check_dirs {
+        let Some(dir) = dir else {
+            continue;
+        };
+
+        if let Some(exes) = find_in(&dir) {
+            info!(source, dir = %dir.display(), "Found game binaries");
+            return Some(exes);
+        }
+    }
+
+    // the Steam/GOG copy always wins, so the store is only searched as a fallback
+    for dir in store_dirs() {
+        if let Some(exes) = find_in(&dir) {
+            info!(source = "microsoft store", dir = %dir.display(), "Found game binaries");
+            warn!("This is the Microsoft Store (Game Pass) version of the game. Its files are protected by Windows, so injection may require running this as admin");
+            return Some(exes);
+        }
+    }
+
+    None
+}
+
+/// The Larian launcher, which `launcher_plugins` are loaded into
+pub fn get_launcher_path(config: &'static Config) -> String {
+    let path = install_root(config).join("Launcher").join(LAUNCHER_EXE);
+
+    if !path.is_file() {
+        warn!(path = %path.display(), "launcher not found; launcher plugins won't be loaded until it exists");
+        return path.to_string_lossy().into_owned();
+    }
+
+    canonicalize(&path).unwrap_or_else(|| path.to_string_lossy().into_owned())
+}
+
+/// Look for both game exes in `dir`
+fn find_in(dir: &Path) -> Option<Bg3Exes> {
+    let bg3 = dir.join("bg3.exe");
+    let bg3_dx11 = dir.join("bg3_dx11.exe");
+
+    if !(bg3.is_file() && bg3_dx11.is_file()) {
+        return None;
+    }
+
+    let bg3 = canonicalize(&bg3)?;
+    let bg3_dx11 = canonicalize(&bg3_dx11)?;
+
+    trace!(path = %bg3, "Looking for bg3");
+    trace!(path = %bg3_dx11, "Looking for bg3_dx11");
+
+    Some(Bg3Exes { bg3, bg3_dx11 })
+}
+
+fn canonicalize(path: &Path) -> Option<String> {
+    let canonical = match fs::canonicalize(path) {
+        Ok(p) => p,
+        Err(e) => {
+            error!(error = %e, path = %path.display(), "failed to canonicalize");
+            return None;
+        }
+    };
+
+    // canonicalize adds this to the prefix, but we don't want it
+    let canonical = canonical
+        .to_string_lossy()
+        .strip_prefix(r"\\?\")
+        .map(ToOwned::to_owned)
+        .unwrap_or_else(|| canonical.to_string_lossy().to_string());
+
+    Some(canonical)
+}
+
+/// Bin dirs where the Microsoft Store (Game Pass) version of the game may be installed
+///
+/// The Xbox app installs to `<drive>:\XboxGames` by default, while older installs are in the
+/// `WindowsApps` folder, which usually can't be listed without admin
+fn store_dirs() -> Vec<PathBuf> {
+    let mut dirs = fixed_drives()
+        .into_iter()
+        .map(|root| root.join(r"XboxGames\Baldur's Gate 3\Content\bin"))
+        .collect::<Vec<_>>();
+
+    let program_files = env::var_os("ProgramFiles")
+        .map_or_else(|| PathBuf::from(r"C:\Program Files"), PathBuf::from);
+    let windows_apps = program_files.join("WindowsApps");
+
+    match fs::read_dir(&windows_apps) {
+        Ok(entries) => {
+            let packages = entries.flatten().map(|e| e.path()).filter(|p| {
+                p.file_name()
+                    .is_some_and(|n| n.to_string_lossy().to_lowercase().contains("baldursgate3"))
+            });
+
+            for package in packages {
+                dirs.push(package.join("bin"));
+                dirs.push(package.join(r"Content\bin"));
+            }
+        }
+
+        Err(e) => trace!(%e, dir = %windows_apps.display(), "unable to list WindowsApps"),
+    }
+
+    trace!(?dirs, "microsoft store dirs");
+
+    dirs
+}
+
+/// The root of every fixed drive. Network and removable drives are left out, since checking them can block
+/// for a long time when they're disconnected
+fn fixed_drives() -> Vec<PathBuf> {
+    let mask = unsafe { GetLogicalDrives() };
+
+    ('A'..='Z')
+        .enumerate()
+        .filter(|&(i, _)| mask & (1 << i) != 0)
+        .map(|(_, drive)| PathBuf::from(format!(r"{drive}:\")))
+        .filter(|root| unsafe { GetDriveTypeW(&HSTRING::from(root.as_path())) } == DRIVE_FIXED)
+        .collect()
+}
+
 #[allow(dead_code)]
 #[derive(Copy, Clone)]
 pub enum Bg3Exe {
@@ -0,0 +1,84 @@
+use std::sync::Mutex;
+
+use shared::utils::SuperLock as _;
+use tracing::{info, warn};
+use windows::Win32::System::Threading::{
+    GetCurrentProcess, GetCurrentThread, GetPriorityClass, GetThreadPriority, SetPriorityClass,
+    SetThreadPriority, HIGH_PRIORITY_CLASS, PROCESS_CREATION_FLAGS, THREAD_PRIORITY,
+    THREAD_PRIORITY_HIGHEST,
+};
+
+/// how many boosts are active, and the priority class to restore once the last one is dropped.
+/// injections can overlap, and the first one to finish must not drop the others' boost
+static BOOSTS: Mutex<(usize, Option<PROCESS_CREATION_FLAGS>)> = Mutex::new((0, None));
+
+/// Raises this process' priority class, and the current thread's priority, until dropped.
+/// Failing to change the priority only logs a warning
+pub struct PriorityBoost {
+    thread: Option<THREAD_PRIORITY>,
+}
+
+impl PriorityBoost {
+    pub fn new() -> Self {
+        {
+            let mut boosts = BOOSTS.super_lock();
+
+            if boosts.0 == 0 {
+                let process = unsafe { GetCurrentProcess() };
+                let original = PROCESS_CREATION_FLAGS(unsafe { GetPriorityClass(process) });
+
+                match unsafe { SetPriorityClass(process, HIGH_PRIORITY_CLASS) } {
+                    Ok(_) => {
+                        info!(
+                            from = original.0,
+                            to = HIGH_PRIORITY_CLASS.0,
+                            "Raised process priority for injection"
+                        );
+                        boosts.1 = Some(original);
+                    }
+
+                    Err(e) => warn!(%e, "failed to raise process priority"),
+                }
+            }
+
+            boosts.0 += 1;
+        }
+
+        let thread = unsafe { GetCurrentThread() };
+        let original = THREAD_PRIORITY(unsafe { GetThreadPriority(thread) });
+
+        let thread = match unsafe { SetThreadPriority(thread, THREAD_PRIORITY_HIGHEST) } {
+            Ok(_) => Some(original),
+            Err(e) => {
+                warn!(%e, "failed to raise thread priority");
+                None
+            }
+        };
+
+        Self { thread }
+    }
+}
+
+impl Drop for PriorityBoost {
+    fn drop(&mut self) {
+        if let Some(original) = self.thread {
+            if let Err(e) = unsafe { SetThreadPriority(GetCurrentThread(), original) } {
+                warn!(%e, "failed to restore thread priority");
+            }
+        }
+
+        let mut boosts = BOOSTS.super_lock();
+        boosts.0 -= 1;
+
+        if boosts.0 > 0 {
+            return;
+        }
+
+        if let Some(original) = boosts.1.take() {
+            match unsafe { SetPriorityClass(GetCurrentProcess(), original) } {
+                Ok(_) => info!(to = original.0, "Restored process priority after injection"),
+                Err(e) => warn!(%e, "failed to restore process priority"),
+            }
+        }
+    }
+}
@@ -1,31 +1,39 @@
 use std::{
     collections::HashSet,
+    path::Path,
+    process,
     sync::{
         atomic::{AtomicBool, Ordering},
-        mpsc::{channel, RecvTimeoutError},
+        mpsc::{channel, Receiver, RecvTimeoutError},
         Arc, LazyLock, Mutex,
     },
     thread::{self, JoinHandle},
-    time::{Duration, Instant},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
-use shared::utils::{OwnedHandle, SuperLock};
-use tracing::{trace, trace_span, Span};
-use unicase::UniCase;
-use windows::Win32::{
-    Foundation::MAX_PATH,
-    System::Threading::{OpenProcess, PROCESS_QUERY_INFORMATION},
+use shared::{
+    paths::LAUNCHER_EXE,
+    utils::{OwnedHandle, SuperLock},
 };
+use tracing::{error, info, trace, trace_span, warn, Span};
+use unicase::UniCase;
+use windows::Win32::System::Threading::{OpenProcess, PROCESS_QUERY_INFORMATION};
 
 use crate::{
+    status::WatcherStatus,
     stop_token::StopToken,
     wapi::{
-        enum_processes::EnumProcessesRs, query_full_process_image_name::QueryFullProcessImageNameRs,
+        enum_processes::EnumProcessesRs, get_parent_pid::GetParentPidRs,
+        query_full_process_image_name::QueryFullProcessImageNameRs,
+        query_process_command_line::QueryProcessCommandLineRs,
     },
 };
 
 pub type Pid = u32;
 
+/// the game's Steam and GOG AppIDs, used to recognize it by its command line
+const GAME_APP_IDS: &[&str] = &["1086940", "1456460669"];
+
 pub static CURRENT_PID: LazyLock<Mutex<Span>> = LazyLock::new(|| Mutex::new(Span::none()));
 
 #[derive(Debug)]
@@ -34,7 +42,7 @@ pub enum CallType {
     Timeout,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Timeout {
     None,
     Duration(Duration),
@@ -53,33 +61,128 @@ pub struct ProcessWatcherResults {
     pub watcher_handle: JoinHandle<()>,
 }
 
+/// xorshift64; plenty for spreading out polls
+#[derive(Debug)]
+struct Rng(u64);
+
+impl Rng {
+    fn new() -> Self {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos() as u64);
+
+        // must never be 0, or it only ever returns 0
+        Self((time ^ u64::from(process::id()).rotate_left(32)) | 1)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        let mut x = self.0;
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.0 = x;
+        x
+    }
+}
+
+/// a new process which was recognized as the game
+struct Match {
+    /// index of the matched exe in `processes`
+    priority: usize,
+    pid: Pid,
+    target: String,
+}
+
+impl Match {
+    /// Matches found in the same poll are handled in this order: by their exe's place in `processes`, then by pid.
+    /// The order pids are enumerated in is arbitrary, so this makes it deterministic
+    fn order(&self) -> (usize, Pid) {
+        (self.priority, self.pid)
+    }
+}
+
+/// a watched exe's own polling schedule
+#[derive(Debug)]
+struct Schedule {
+    interval: Duration,
+    next_poll: Instant,
+}
+
 #[derive(Debug)]
 pub struct ProcessWatcher {
     processes: Vec<UniCase<String>>,
+    /// one for each of `processes`
+    schedules: Vec<Schedule>,
+    /// lowercase root dir processes must be in to be matched by command line. None if disabled
+    cmd_line_root: Option<String>,
     polling_rate: Duration,
+    /// each poll is randomly up to this much earlier or later
+    jitter: Duration,
+    rng: Rng,
     timeout: Timeout,
     state: HashSet<u32>,
+    inject_all: bool,
     oneshot: bool,
+    /// whether launchers are recognized, so the game they start is followed instead of them being matched
+    follow_launcher: bool,
+    /// every launcher which was found and is still running
+    launchers: HashSet<Pid>,
+    status: WatcherStatus,
 }
 
 impl ProcessWatcher {
     /// timeout is in ms
     /// processes must be full path to exe
+    /// when several game processes are found at once, they're handled in the order of `processes`
+    /// (then by pid), with command line matches last. each one is handled on its own
+    /// if cmd_line_root is set, processes inside of it whose command line has the game's AppID are also matched
+    /// if inject_all is false, new game processes are skipped while an injected one is still running
+    /// target_polling_rates is in the same order as processes, and None uses polling_rate
+    /// every poll is randomly moved by up to jitter in either direction
+    /// if follow_launcher is true, the launcher is never matched, and games started by it are logged as followed
+    #[allow(clippy::too_many_arguments)]
     pub fn new<S: AsRef<str>>(
         processes: &[S],
+        cmd_line_root: Option<&Path>,
         polling_rate: Duration,
+        target_polling_rates: &[Option<Duration>],
+        jitter: Duration,
         timeout: Timeout,
+        inject_all: bool,
         oneshot: bool,
+        follow_launcher: bool,
+        status: WatcherStatus,
     ) -> Self {
         Self {
             processes: processes
                 .iter()
                 .map(|p| UniCase::new(p.as_ref().to_owned()))
                 .collect(),
+            cmd_line_root: cmd_line_root.map(|root| {
+                let root = root.to_string_lossy().to_lowercase();
+                // so C:\Game doesn't also match C:\Game2
+                format!("{}\\", root.trim_end_matches(['\\', '/']))
+            }),
+            schedules: (0..processes.len())
+                .map(|i| Schedule {
+                    interval: target_polling_rates
+                        .get(i)
+                        .copied()
+                        .flatten()
+                        .unwrap_or(polling_rate),
+                    next_poll: Instant::now(),
+                })
+                .collect(),
             state: HashSet::new(),
+            inject_all,
             polling_rate,
+            jitter,
+            rng: Rng::new(),
             timeout,
             oneshot,
+            follow_launcher,
+            launchers: HashSet::new(),
+            status,
         }
     }
 
@@ -117,87 +220,252 @@ impl ProcessWatcher {
             None
         };
 
-        let handle = thread::spawn(move || {
-            // we can avoid unsafe length setting shenanigans by prefilling it, instead of set_len
-            let mut pid_buf = vec![0u32; 1024];
-            let mut new_pid_buf = vec![0u32; 1024];
-            // important to prefill it, that way len() returns the full amount for any ffi calls
-            let mut path_buf = vec![0u16; MAX_PATH as usize];
+        // restarting it if it dies is up to the supervisor
+        let handle = thread::spawn(move || self.watch(&cb, &recv, &timed_out));
+
+        let watcher_token = StopToken::new(sender);
+
+        ProcessWatcherResults {
+            watcher_token,
+            timeout_token,
+            watcher_handle: handle,
+        }
+    }
+
+    /// the main watcher loop. returns when stopped, timed out, or done (if oneshot)
+    fn watch(&mut self, cb: &impl Fn(CallType), recv: &Receiver<()>, timed_out: &AtomicBool) {
+        // we can avoid unsafe length setting shenanigans by prefilling it, instead of set_len
+        let mut pid_buf = vec![0u32; 1024];
+        let mut new_pid_buf = vec![0u32; 1024];
+        // grown as needed, and reused for every process
+        let mut path_buf = Vec::new();
+
+        let own_pid = process::id();
+
+        'run: loop {
+            self.status.polled();
 
-            'run: loop {
-                let pids = EnumProcessesRs(&mut pid_buf);
+            let pids = EnumProcessesRs(&mut pid_buf);
 
-                // process list of pids, compare to last cached copy, find new ones and process those
-                self.process_pids(pids, &mut new_pid_buf);
+            // process list of pids, compare to last cached copy, find new ones and process those
+            self.process_pids(pids, &mut new_pid_buf);
 
-                'pid_loop: for pid in new_pid_buf.iter().copied() {
-                    let span_pid_loop = trace_span!("pid_loop", pid = pid);
-                    let _guard = span_pid_loop.enter();
+            // every new game process found in this poll
+            let mut matches = Vec::new();
 
-                    *CURRENT_PID.super_lock() = span_pid_loop.clone();
+            for pid in new_pid_buf.iter().copied() {
+                let span_pid_loop = trace_span!("pid_loop", pid = pid);
+                let _guard = span_pid_loop.enter();
 
-                    let process = {
-                        let res = unsafe { OpenProcess(PROCESS_QUERY_INFORMATION, false, pid) };
+                let process = {
+                    let res = unsafe { OpenProcess(PROCESS_QUERY_INFORMATION, false, pid) };
 
-                        match res {
-                            Ok(v) => OwnedHandle::new(v),
-                            Err(e) => {
-                                // failed to open process; probably we don't have correct perms to open it
-                                // there is a risk here that we don't have permission to open the game process, so it's skipped
-                                // in such a case, this tool should be run as admin. we have no way of knowing if that happened
+                    match res {
+                        Ok(v) => OwnedHandle::new(v),
+                        Err(e) => {
+                            // failed to open process; probably we don't have correct perms to open it
+                            // there is a risk here that we don't have permission to open the game process, so it's skipped
+                            // in such a case, this tool should be run as admin. we have no way of knowing if that happened
 
-                                trace!(err = %e, "failed to open process");
+                            trace!(err = %e, "failed to open process");
 
-                                continue;
-                            }
+                            continue;
                         }
-                    };
+                    }
+                };
+
+                let Ok(path) = QueryFullProcessImageNameRs(&process, &mut path_buf) else {
+                    continue;
+                };
+
+                let new_process_path = UniCase::new(path.to_string_lossy());
+
+                trace!(process = %new_process_path, "found");
+
+                let is_launcher = self.follow_launcher
+                    && Path::new(&**new_process_path).file_name().is_some_and(|n| {
+                        UniCase::new(&*n.to_string_lossy()) == UniCase::new(LAUNCHER_EXE)
+                    });
+
+                let priority = if let Some(priority) =
+                    self.processes.iter().position(|p| p == &new_process_path)
+                {
+                    trace!(path = %self.processes[priority], "found process match");
+                    priority
+                } else if is_launcher {
+                    // its command line can look like the game's, but it only starts the game
+                    info!(%new_process_path, "Found the game launcher; waiting for the game it starts");
+                    self.launchers.insert(pid);
+                    continue;
+                } else if self.is_cmd_line_match(&process, &path.to_string_lossy()) {
+                    // after every listed exe
+                    self.processes.len()
+                } else {
+                    continue;
+                };
+
+                // never inject ourselves, whatever our exe happens to be named
+                if pid == own_pid {
+                    warn!(%new_process_path, "this process matched as the game; ignoring it");
+                    continue;
+                }
 
-                    let Ok(path) = QueryFullProcessImageNameRs(&process, &mut path_buf) else {
-                        continue;
-                    };
+                if !self.launchers.is_empty() {
+                    if let Some(launcher) =
+                        GetParentPidRs(pid).filter(|parent| self.launchers.contains(parent))
+                    {
+                        info!(
+                            launcher,
+                            pid, "Followed the launcher to the game process it started"
+                        );
+                    }
+                }
 
-                    let new_process_path = UniCase::new(path.to_string_lossy());
+                matches.push(Match {
+                    priority,
+                    pid,
+                    target: new_process_path.into_inner(),
+                });
+            }
 
-                    trace!(process = %new_process_path, "found");
+            matches.sort_by_key(Match::order);
 
-                    for process_path in &self.processes {
-                        if process_path == &new_process_path {
-                            trace!(path = %process_path, "found process match");
+            self.retain_due(&mut matches, Instant::now());
 
-                            cb(CallType::Pid(pid));
+            if self.inject(matches, cb) {
+                break 'run;
+            }
 
-                            if self.oneshot {
-                                break 'run;
-                            }
+            // poll as often as the most eager target needs
+            let tick = self
+                .schedules
+                .iter()
+                .map(|s| s.interval)
+                .fold(self.polling_rate, Duration::min);
+            let tick = self.jittered(tick);
 
-                            // there can only be one match per pid, so..
-                            continue 'pid_loop;
-                        }
-                    }
+            let signal = recv.recv_timeout(tick);
+            if matches!(signal, Ok(_) | Err(RecvTimeoutError::Disconnected)) {
+                trace!(?signal, "signal exited");
+
+                // if we have a timeout running and it timed out, wait before quitting
+                if self.timeout.is_timeout() && timed_out.load(Ordering::Relaxed) {
+                    cb(CallType::Timeout);
                 }
 
-                let signal = recv.recv_timeout(self.polling_rate);
-                if matches!(signal, Ok(_) | Err(RecvTimeoutError::Disconnected)) {
-                    trace!(?signal, "signal exited");
+                break;
+            }
+        }
+    }
 
-                    // if we have a timeout running and it timed out, wait before quitting
-                    if self.timeout.is_timeout() && timed_out.load(Ordering::Relaxed) {
-                        cb(CallType::Timeout);
-                    }
+    /// Inject every match which should be, in order. Returns whether the watcher is done, if oneshot
+    fn inject(&mut self, matches: Vec<Match>, cb: &impl Fn(CallType)) -> bool {
+        for Match { pid, target, .. } in matches {
+            let span_pid_loop = trace_span!("pid_loop", pid = pid);
+            let _guard = span_pid_loop.enter();
 
-                    break;
+            *CURRENT_PID.super_lock() = span_pid_loop.clone();
+
+            {
+                // each game instance is only ever injected once, even by a restarted watcher
+                let claimed = self.status.claimed();
+                if claimed.contains(&pid) {
+                    continue;
                 }
+
+                if !self.inject_all && !claimed.is_empty() {
+                    info!(%target, injected = ?*claimed, "another game instance is already injected; skipping this one");
+                    continue;
+                }
+            }
+
+            // the pid was seen, so this instance stays vanilla even after resuming
+            if self.status.is_paused() {
+                info!(%target, "Found game process, but injection is paused; leaving it alone");
+                continue;
             }
+
+            info!(%target, "Found game process");
+
+            // injecting can take however long, so it doesn't count as stalling
+            self.status.not_polling();
+            cb(CallType::Pid(pid));
+            self.status.polled();
+            self.status.claimed().insert(pid);
+
+            if self.oneshot {
+                return true;
+            }
+        }
+
+        false
+    }
+
+    /// Drop the matches whose target isn't due for a poll yet, and schedule the next poll of every target which is.
+    /// Dropped processes are found as new again on a later poll. Command line matches have no schedule, so they're
+    /// always due
+    fn retain_due(&mut self, matches: &mut Vec<Match>, now: Instant) {
+        matches.retain(|m| {
+            let due = self
+                .schedules
+                .get(m.priority)
+                .is_none_or(|s| now >= s.next_poll);
+
+            if !due {
+                self.state.remove(&m.pid);
+            }
+
+            due
         });
 
-        let watcher_token = StopToken::new(sender);
+        for schedule in self.schedules.iter_mut().filter(|s| now >= s.next_poll) {
+            schedule.next_poll = now + schedule.interval;
+        }
+    }
 
-        ProcessWatcherResults {
-            watcher_token,
-            timeout_token,
-            watcher_handle: handle,
+    /// moves the interval randomly by up to the jitter, but never below 1ms
+    fn jittered(&mut self, interval: Duration) -> Duration {
+        let jitter = self.jitter.as_millis() as u64;
+        if jitter == 0 {
+            return interval;
         }
+
+        let offset = Duration::from_millis(self.rng.next_u64() % (jitter + 1));
+
+        let interval = if self.rng.next_u64() % 2 == 0 {
+            interval.saturating_add(offset)
+        } else {
+            interval.saturating_sub(offset)
+        };
+
+        interval.max(Duration::from_millis(1))
+    }
+
+    /// checks if the process is the game by its command line, for when the exe was renamed
+    fn is_cmd_line_match(&self, process: &OwnedHandle, path: &str) -> bool {
+        let Some(root) = &self.cmd_line_root else {
+            return false;
+        };
+
+        if !path.to_lowercase().starts_with(root) {
+            return false;
+        }
+
+        let cmd_line = match QueryProcessCommandLineRs(process) {
+            Ok(v) => v,
+            Err(e) => {
+                trace!(%e, "failed to query command line");
+                return false;
+            }
+        };
+
+        let Some(app_id) = GAME_APP_IDS.iter().find(|id| cmd_line.contains(**id)) else {
+            return false;
+        };
+
+        info!(%path, %cmd_line, app_id, "found process match by command line");
+
+        true
     }
 
     /// processes pids and detects which processes are new
@@ -219,6 +487,26 @@ impl ProcessWatcher {
             trace!(pids = ?buffer, "found new pids to check");
         }
 
+        // launchers which exited
+        self.launchers.retain(|pid| pids.contains(pid));
+
+        // injected games which exited
+        self.status.claimed().retain(|pid| {
+            let running = pids.contains(pid);
+            if !running {
+                trace!(pid, "injected process exited");
+            }
+
+            running
+        });
+
+        // the injected game exited. whoever injects a game reports it as injected once it actually is
+        if let Some(pid) = self.status.injected_pid() {
+            if !pids.contains(&pid) {
+                self.status.set_injected(None);
+            }
+        }
+
         // this is important. It erases all the old entries in the table
         // clear the table to keep backing memory
         self.state.clear();
@@ -226,3 +514,197 @@ impl ProcessWatcher {
         self.state.extend(pids);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    fn matched(priority: usize, pid: Pid) -> Match {
+        Match {
+            priority,
+            pid,
+            target: format!("target{priority}"),
+        }
+    }
+
+    fn watcher(processes: &[&str], target_polling_rates: &[Option<Duration>]) -> ProcessWatcher {
+        ProcessWatcher::new(
+            processes,
+            None,
+            Duration::from_secs(2),
+            target_polling_rates,
+            Duration::ZERO,
+            Timeout::None,
+            true,
+            false,
+            false,
+            WatcherStatus::new(),
+        )
+    }
+
+    #[test]
+    fn matches_are_ordered_by_priority_then_pid() {
+        let mut matches = vec![matched(1, 4), matched(0, 12), matched(1, 2), matched(0, 8)];
+        matches.sort_by_key(Match::order);
+
+        let order = matches.iter().map(Match::order).collect::<Vec<_>>();
+        assert_eq!(order, [(0, 8), (0, 12), (1, 2), (1, 4)]);
+    }
+
+    #[test]
+    fn targets_are_polled_at_their_own_interval() {
+        let lazy = Duration::from_secs(60);
+        let mut watcher = watcher(&["lazy.exe", "eager.exe"], &[Some(lazy)]);
+
+        // every target is due on the first poll
+        let start = Instant::now();
+        let mut matches = vec![matched(0, 4), matched(1, 8), matched(2, 12)];
+        watcher.retain_due(&mut matches, start);
+
+        assert_eq!(matches.len(), 3);
+        assert_eq!(watcher.schedules[0].next_poll, start + lazy);
+        assert_eq!(
+            watcher.schedules[1].next_poll,
+            start + Duration::from_secs(2)
+        );
+
+        // only the eager target, and command line matches, are due again before the lazy one
+        let later = start + Duration::from_secs(5);
+        watcher.state.extend([16, 20, 24]);
+        let mut matches = vec![matched(0, 16), matched(1, 20), matched(2, 24)];
+        watcher.retain_due(&mut matches, later);
+
+        let pids = matches.iter().map(|m| m.pid).collect::<Vec<_>>();
+        assert_eq!(pids, [20, 24]);
+        // so the lazy target's process is found again once it's due
+        assert!(!watcher.state.contains(&16));
+        assert_eq!(watcher.schedules[0].next_poll, start + lazy);
+        assert_eq!(
+            watcher.schedules[1].next_poll,
+            later + Duration::from_secs(2)
+        );
+
+        let mut matches = vec![matched(0, 16)];
+        watcher.retain_due(&mut matches, start + lazy);
+        assert_eq!(matches.len(), 1);
+    }
+
+    #[test]
+    fn jitter_stays_within_bounds() {
+        let interval = Duration::from_millis(1000);
+        let jitter = Duration::from_millis(50);
+
+        let mut watcher = watcher(&["game.exe"], &[]);
+        watcher.jitter = jitter;
+
+        let intervals = (0..1000)
+            .map(|_| watcher.jittered(interval))
+            .collect::<HashSet<_>>();
+
+        assert!(intervals
+            .iter()
+            .all(|&i| i >= interval - jitter && i <= interval + jitter));
+        // it actually varies, both ways
+        assert!(intervals.iter().any(|&i| i < interval));
+        assert!(intervals.iter().any(|&i| i > interval));
+    }
+
+    #[test]
+    fn jitter_never_goes_below_1ms() {
+        let mut watcher = watcher(&["game.exe"], &[]);
+        watcher.jitter = Duration::from_millis(50);
+
+        for _ in 0..1000 {
+            assert!(watcher.jittered(Duration::from_millis(10)) >= Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn no_jitter_keeps_interval() {
+        let mut watcher = watcher(&["game.exe"], &[]);
+        let interval = Duration::from_millis(1000);
+
+        assert!((0..100).all(|_| watcher.jittered(interval) == interval));
+    }
+
+    #[test]
+    fn never_matches_own_process() {
+        let exe = env::current_exe().unwrap().to_string_lossy().to_string();
+
+        // only a real test if this process would otherwise match
+        let own = unsafe { OpenProcess(PROCESS_QUERY_INFORMATION, false, process::id()) }.unwrap();
+        let own_path =
+            QueryFullProcessImageNameRs(&OwnedHandle::new(own), &mut Vec::new()).unwrap();
+        assert_eq!(
+            UniCase::new(own_path.to_string_lossy().to_string()),
+            UniCase::new(exe.clone())
+        );
+
+        let calls = Arc::new(Mutex::new(Vec::new()));
+
+        let results = {
+            let calls = calls.clone();
+            // the first poll happens right away
+            watcher(&[exe.as_str()], &[]).run(move |call| calls.super_lock().push(call))
+        };
+
+        thread::sleep(Duration::from_millis(300));
+        results.watcher_token.stop();
+        results.watcher_handle.join().unwrap();
+
+        let own_pid = process::id();
+        let calls = calls.super_lock();
+        assert!(!calls
+            .iter()
+            .any(|c| matches!(c, CallType::Pid(pid) if *pid == own_pid)));
+    }
+
+    /// The pids `watcher` injects out of `matches`
+    fn injected(watcher: &mut ProcessWatcher, matches: Vec<Match>) -> Vec<Pid> {
+        let calls = Mutex::new(Vec::new());
+        watcher.inject(matches, &|call| {
+            if let CallType::Pid(pid) = call {
+                calls.super_lock().push(pid);
+            }
+        });
+
+        calls.into_inner().unwrap()
+    }
+
+    #[test]
+    fn every_instance_is_injected_once() {
+        let mut watcher = watcher(&["bg3.exe"], &[]);
+
+        let pids = injected(&mut watcher, vec![matched(0, 100), matched(0, 200)]);
+        assert_eq!(pids, [100, 200]);
+        assert_eq!(*watcher.status.claimed(), HashSet::from([100, 200]));
+
+        // seen again while still running, e.g. after being dropped as not due yet
+        let pids = injected(&mut watcher, vec![matched(0, 100), matched(0, 200)]);
+        assert!(pids.is_empty());
+    }
+
+    #[test]
+    fn only_first_instance_without_inject_all() {
+        let mut watcher = watcher(&["bg3.exe"], &[]);
+        watcher.inject_all = false;
+
+        let pids = injected(&mut watcher, vec![matched(0, 100), matched(0, 200)]);
+        assert_eq!(pids, [100]);
+    }
+
+    #[test]
+    fn restarted_watcher_does_not_reinject() {
+        let mut first = watcher(&["bg3.exe"], &[]);
+        assert_eq!(injected(&mut first, vec![matched(0, 100)]), [100]);
+
+        // a restarted watcher finds every running game again, but shares the status
+        let mut restarted = watcher(&["bg3.exe"], &[]);
+        restarted.status = first.status.clone();
+
+        let pids = injected(&mut restarted, vec![matched(0, 100), matched(0, 200)]);
+        assert_eq!(pids, [200]);
+    }
+}
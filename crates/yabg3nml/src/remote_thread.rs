@@ -1,30 +1,106 @@
-use std::ffi::c_void;
+use std::{ffi::c_void, mem, ptr, sync::OnceLock, thread, time::Duration};
 
-use shared::utils::OwnedHandle;
-use tracing::error;
+use shared::{config::InjectionTrigger, utils::OwnedHandle};
+use tracing::{error, trace, warn};
 use windows::{
-    core::Error,
+    core::{s, w, Error},
     Win32::{
-        Foundation::{GetLastError, HANDLE, WAIT_OBJECT_0, WIN32_ERROR},
-        System::Threading::{
-            CreateRemoteThread, WaitForSingleObject, INFINITE, LPTHREAD_START_ROUTINE,
+        Foundation::{GetLastError, HANDLE, NTSTATUS, WAIT_OBJECT_0, WAIT_TIMEOUT, WIN32_ERROR},
+        System::{
+            LibraryLoader::{GetModuleHandleW, GetProcAddress},
+            Threading::{
+                CreateRemoteThread, GetExitCodeThread, WaitForSingleObject, INFINITE,
+                LPTHREAD_START_ROUTINE, THREAD_ALL_ACCESS,
+            },
         },
     },
 };
 
+type FarProc = unsafe extern "system" fn() -> isize;
+
+/// how many times creating a remote thread is attempted before giving up
+pub const SPAWN_ATTEMPTS: u32 = 3;
+/// delay before the first retry; doubled on every retry after
+const SPAWN_BACKOFF: Duration = Duration::from_millis(100);
+
+// https://ntdoc.m417z.com/ntcreatethreadex
+type NtCreateThreadExFn = unsafe extern "system" fn(
+    thread_handle: *mut HANDLE,
+    desired_access: u32,
+    object_attributes: *const c_void,
+    process_handle: HANDLE,
+    start_routine: *const c_void,
+    argument: *const c_void,
+    create_flags: u32,
+    zero_bits: usize,
+    stack_size: usize,
+    maximum_stack_size: usize,
+    attribute_list: *const c_void,
+) -> NTSTATUS;
+
 pub struct RemoteThread(HANDLE);
 
 impl RemoteThread {
     pub fn spawn(
         process: &OwnedHandle,
+        trigger: InjectionTrigger,
         addr: LPTHREAD_START_ROUTINE,
         lpparameter: Option<*const c_void>,
     ) -> Result<Self, Error> {
-        let res = unsafe {
-            CreateRemoteThread(process.as_raw_handle(), None, 0, addr, lpparameter, 0, None)
-        };
+        trace!(%trigger, "spawning remote thread");
+
+        match trigger {
+            InjectionTrigger::CreateRemoteThread => {
+                let res = unsafe {
+                    CreateRemoteThread(process.as_raw_handle(), None, 0, addr, lpparameter, 0, None)
+                };
+
+                res.map(Self)
+            }
+
+            InjectionTrigger::NtCreateThreadEx => {
+                #[allow(non_snake_case)]
+                let NtCreateThreadEx = get_nt_create_thread_ex()?;
+
+                let start = addr.map_or(ptr::null(), |f| f as *const c_void);
+                let mut handle = HANDLE::default();
 
-        res.map(Self)
+                let status = unsafe {
+                    NtCreateThreadEx(
+                        &mut handle,
+                        THREAD_ALL_ACCESS.0,
+                        ptr::null(),
+                        process.as_raw_handle(),
+                        start,
+                        lpparameter.unwrap_or(ptr::null()),
+                        0,
+                        0,
+                        0,
+                        0,
+                        ptr::null(),
+                    )
+                };
+
+                status.ok().map(|_| Self(handle))
+            }
+        }
+    }
+
+    /// Like [`Self::spawn`], but retries with backoff. Thread creation can fail transiently,
+    /// e.g. when antivirus is inspecting the process
+    pub fn spawn_with_retry(
+        process: &OwnedHandle,
+        trigger: InjectionTrigger,
+        addr: LPTHREAD_START_ROUTINE,
+        lpparameter: Option<*const c_void>,
+    ) -> Result<Self, Error> {
+        with_backoff(
+            |attempt, delay, e| {
+                warn!(%e, %trigger, attempt, max = SPAWN_ATTEMPTS, ?delay, "failed to create remote thread; retrying");
+                thread::sleep(delay);
+            },
+            || Self::spawn(process, trigger, addr, lpparameter),
+        )
     }
 
     pub fn wait(&self) -> Result<(), WIN32_ERROR> {
@@ -37,4 +113,125 @@ impl RemoteThread {
             Err(err)
         }
     }
+
+    /// Like [`Self::wait`], but gives up after `timeout`. Returns whether the thread exited
+    pub fn wait_timeout(&self, timeout: Duration) -> Result<bool, WIN32_ERROR> {
+        let millis = u32::try_from(timeout.as_millis()).unwrap_or(INFINITE - 1);
+
+        let res = unsafe { WaitForSingleObject(self.0, millis) };
+        if res == WAIT_OBJECT_0 {
+            Ok(true)
+        } else if res == WAIT_TIMEOUT {
+            Ok(false)
+        } else {
+            let err = unsafe { GetLastError() };
+            error!(state = ?res, ?err, "object in wrong state");
+            Err(err)
+        }
+    }
+
+    /// The thread's exit code, i.e. what its start routine returned. Only meaningful once it exited
+    pub fn exit_code(&self) -> Result<u32, Error> {
+        let mut code = 0;
+        unsafe { GetExitCodeThread(self.0, &mut code)? };
+        Ok(code)
+    }
+}
+
+/// NtCreateThreadEx is undocumented and not part of the windows crate, so it must be resolved at runtime
+fn get_nt_create_thread_ex() -> Result<NtCreateThreadExFn, Error> {
+    static CACHE: OnceLock<NtCreateThreadExFn> = OnceLock::new();
+
+    if let Some(f) = CACHE.get() {
+        return Ok(*f);
+    }
+
+    let handle = unsafe { GetModuleHandleW(w!("ntdll"))? };
+
+    let addr = unsafe { GetProcAddress(handle, s!("NtCreateThreadEx")) };
+    let addr = addr.ok_or_else(Error::from_win32)?;
+
+    let f = unsafe { mem::transmute::<FarProc, NtCreateThreadExFn>(addr) };
+    _ = CACHE.set(f);
+
+    Ok(f)
+}
+
+/// Call `f` until it succeeds, at most [`SPAWN_ATTEMPTS`] times. Before every retry, `wait` is given the failed
+/// attempt, how long to wait, and the error
+fn with_backoff<T>(
+    mut wait: impl FnMut(u32, Duration, &Error),
+    mut f: impl FnMut() -> Result<T, Error>,
+) -> Result<T, Error> {
+    let mut delay = SPAWN_BACKOFF;
+
+    for attempt in 1.. {
+        match f() {
+            Ok(t) => return Ok(t),
+
+            Err(e) if attempt < SPAWN_ATTEMPTS => {
+                wait(attempt, delay, &e);
+                delay *= 2;
+            }
+
+            Err(e) => return Err(e),
+        }
+    }
+
+    unreachable!()
+}
+
+#[cfg(test)]
+mod tests {
+    use windows::Win32::Foundation::E_ACCESSDENIED;
+
+    use super::*;
+
+    #[test]
+    fn retries_until_success() {
+        let mut calls = 0;
+        let mut waits = Vec::new();
+
+        let res = with_backoff(
+            |attempt, delay, _| waits.push((attempt, delay)),
+            || {
+                calls += 1;
+                if calls < SPAWN_ATTEMPTS {
+                    Err(Error::from(E_ACCESSDENIED))
+                } else {
+                    Ok(calls)
+                }
+            },
+        );
+
+        assert_eq!(res.unwrap(), SPAWN_ATTEMPTS);
+        assert_eq!(waits, [(1, SPAWN_BACKOFF), (2, SPAWN_BACKOFF * 2)]);
+    }
+
+    #[test]
+    fn gives_up_after_max_attempts() {
+        let mut calls = 0;
+        let mut waits = 0;
+
+        let res = with_backoff(
+            |_, _, _| waits += 1,
+            || -> Result<(), Error> {
+                calls += 1;
+                Err(Error::from(E_ACCESSDENIED))
+            },
+        );
+
+        assert_eq!(res.unwrap_err().code(), E_ACCESSDENIED);
+        assert_eq!(calls, SPAWN_ATTEMPTS);
+        assert_eq!(waits, SPAWN_ATTEMPTS - 1);
+    }
+
+    #[test]
+    fn first_success_is_not_retried() {
+        let mut waits = 0;
+        let res = with_backoff(|_, _, _| waits += 1, || Ok(()));
+
+        assert!(res.is_ok());
+        assert_eq!(waits, 0);
+    }
 }
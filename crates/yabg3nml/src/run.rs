@@ -1,18 +1,56 @@
-use std::time::Duration;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::Ordering,
+        mpsc::{Receiver, RecvTimeoutError},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
 
-use eyre::Result;
-use shared::popup::{display_popup, fatal_popup, MessageBoxIcon};
-use tracing::{error, trace};
+use eyre::{bail, eyre, Result};
+use shared::{
+    config::{
+        current_config, disable_config_writeback, get_config, peek_config, quiet_from_env,
+        restore_config, Config,
+    },
+    discovery::plugins_dir_for,
+    paths::{get_plugin_override_path, set_local_appdata_override},
+    popup::{disable_popups, display_popup, fatal_popup, warn_popup, MessageBoxIcon},
+    signature::{self, Signature},
+    thread_data::LoadPhase,
+    utils::{process_creation_time, OwnedHandle},
+    version_info::VersionInfo,
+};
+use tracing::{error, info, trace, trace_span, warn};
+use windows::Win32::System::Threading::{OpenProcess, PROCESS_QUERY_LIMITED_INFORMATION};
 
 #[allow(unused_imports)]
 use crate::{
+    clean::clean,
+    cli::Args,
+    completions,
+    console::{attach_parent_console, set_ctrl_handler, ShutdownGuard},
+    control,
+    diagnostics::collect_diagnostics,
     event::Event,
-    loader::run_loader,
+    event_log, foreground,
+    loader::{self, run_loader},
     paths,
-    process_watcher::{CallType, ProcessWatcher, ProcessWatcherResults, Timeout},
-    setup::init,
+    process_watcher::{CallType, Pid, ProcessWatcher, ProcessWatcherResults, Timeout},
+    server, service,
+    setup::{init, log_banner, set_portable},
     single_instance::SingleInstance,
+    status::{self, WatcherStatus},
+    stop_token::StopToken,
+    supervisor,
+    tmp_loader::{shipped_loader_path, Loader},
     tray::AppTray,
+    validate::validate_plugins,
+    wait::{wait_for_game, wait_for_main_menu},
+    wapi::query_full_process_image_name::QueryFullProcessImageNameRs,
 };
 
 #[derive(Copy, Clone, Debug)]
@@ -21,19 +59,217 @@ pub enum RunType {
     Injector,
 }
 
+/// Options for embedding the process watcher
+#[derive(Debug, Default)]
+pub struct RunOptions {
+    /// The command line arguments to run with
+    pub args: Args,
+    /// Reports the watcher's state
+    pub status: WatcherStatus,
+    /// Stop the watcher once this much time has passed
+    pub timeout: Option<Duration>,
+    /// Stop the watcher when a message is received, or when the sender is dropped
+    pub cancel: Option<Receiver<()>>,
+}
+
 /// Process watcher entry point
-pub fn run(run_type: RunType) -> Result<()> {
+///
+/// Blocks until the watcher is stopped through the tray (or is done, in injector mode)
+pub fn run(run_type: RunType, args: Args) -> Result<()> {
+    run_with(
+        run_type,
+        RunOptions {
+            args,
+            ..Default::default()
+        },
+    )
+}
+
+/// Process watcher entry point, which can additionally be stopped programmatically through
+/// `options`. Without a timeout or cancel receiver, this behaves exactly like [`run`]
+pub fn run_with(run_type: RunType, options: RunOptions) -> Result<()> {
+    let RunOptions {
+        mut args,
+        status,
+        timeout: run_timeout,
+        cancel,
+    } = options;
+
+    // for automated setups which can't pass arguments
+    let quiet_from_env = !args.quiet && quiet_from_env();
+    args.quiet |= quiet_from_env;
+
+    if let Some(shell) = args.generate_completions {
+        attach_parent_console();
+        print!("{}", completions::generate(shell)?);
+        return Ok(());
+    }
+
+    let service_flags = args.install_service || args.uninstall_service || args.run_as_service;
+    if service_flags && !matches!(run_type, RunType::Watcher) {
+        bail!("the service options only work with the watcher tool");
+    }
+
+    // errors are shown by the caller
+    if args.install_service || args.uninstall_service {
+        let (title, message) = if args.install_service {
+            service::install()?;
+            (
+                "Install Service",
+                "Installed the watcher service. It starts at boot",
+            )
+        } else {
+            service::uninstall()?;
+            ("Uninstall Service", "Uninstalled the watcher service")
+        };
+
+        attach_parent_console();
+        println!("{message}");
+
+        if !args.quiet {
+            display_popup(title, message, MessageBoxIcon::Info);
+        }
+
+        return Ok(());
+    }
+
+    // the service control manager calls back into this fn from its own thread
+    if args.run_as_service && !service::in_service() {
+        return service::run(args);
+    }
+
+    if args.run_as_service {
+        // nobody can see or dismiss popups or a tray icon from a service
+        args.no_tray = true;
+        args.quiet = true;
+        disable_popups();
+    }
+
+    if let Some(path) = args.local_appdata.clone() {
+        set_local_appdata_override(path);
+    }
+
+    set_portable(args.portable);
+
+    // must be done before the config is first read
+    if args.no_config_writeback {
+        disable_config_writeback();
+    }
+
+    // done before anything else touches the game, so it also works when injection wouldn't. it only looks,
+    // so the config and plugins dir are read without being created
+    if args.show_paths {
+        let config = &peek_config()?;
+
+        let loader = match args.loader.as_ref().or(config.core.loader_path.as_ref()) {
+            Some(path) => path.clone(),
+            None => shipped_loader_path()?,
+        };
+
+        let message = paths::describe_paths(config, &loader);
+
+        attach_parent_console();
+        print!("{message}");
+
+        if !args.quiet {
+            display_popup("Show Paths", message, MessageBoxIcon::Info);
+        }
+
+        return Ok(());
+    }
+
     // This prohibits multiple app instances
     let _singleton = SingleInstance::new();
+
+    // done after the single instance check, so files which are in use by another instance aren't touched
+    if args.clean {
+        let summary = clean(args.clean_logs);
+
+        attach_parent_console();
+        print!("{summary}");
+
+        if !args.quiet {
+            display_popup("Clean", summary.to_string(), MessageBoxIcon::Info);
+        }
+
+        return Ok(());
+    }
+
+    // done before init, since a broken config would stop init
+    if args.restore_config {
+        restore_config()?;
+
+        let message = "Restored config.toml from the last config that loaded successfully";
+
+        attach_parent_console();
+        println!("{message}");
+
+        if !args.quiet {
+            display_popup("Restore Config", message, MessageBoxIcon::Info);
+        }
+
+        return Ok(());
+    }
+
+    if args.validate_plugins {
+        let config = get_config()?.get();
+        let summary = validate_plugins(config)?;
+
+        attach_parent_console();
+        print!("{summary}");
+
+        if !args.quiet {
+            let icon = if summary.has_problems() {
+                MessageBoxIcon::Warn
+            } else {
+                MessageBoxIcon::Info
+            };
+
+            display_popup("Validate Plugins", summary.to_string(), icon);
+        }
+
+        return Ok(());
+    }
+
+    if let Some(out) = &args.collect_diagnostics {
+        let config = get_config()?.get();
+        let entries = collect_diagnostics(config, out)?;
+
+        let message = format!(
+            "Wrote diagnostics to {}\n\nIncluded:\n{}",
+            out.display(),
+            entries.join("\n")
+        );
+
+        attach_parent_console();
+        println!("{message}");
+
+        if !args.quiet {
+            display_popup("Collect Diagnostics", message, MessageBoxIcon::Info);
+        }
+
+        return Ok(());
+    }
+
     let _event = Event::new()?;
 
-    #[cfg(feature = "test-injection")]
-    let args: crate::cli::Args = argh::from_env();
+    // dropped last, after the logs are flushed
+    let _shutdown = ShutdownGuard;
 
-    let mut init = init()?;
+    let mut init = init(&args)?;
     let _loader_lock = init.loader.file.take();
     let _worker_guard = init.worker.take();
 
+    let mode = match run_type {
+        RunType::Watcher => "watcher",
+        RunType::Injector => "injector",
+    };
+    log_banner(init.config, mode);
+
+    if quiet_from_env {
+        info!("--quiet was enabled by the environment variable YABG3NML_QUIET");
+    }
+
     #[cfg(not(feature = "test-injection"))]
     let processes = {
         use paths::{get_game_binary_paths, Bg3Exes};
@@ -44,6 +280,47 @@ pub fn run(run_type: RunType) -> Result<()> {
     #[cfg(feature = "test-injection")]
     let processes = &[args.inject];
 
+    #[cfg(not(feature = "test-injection"))]
+    let cmd_line_root = init
+        .config
+        .core
+        .match_command_line
+        .then(|| paths::install_root(init.config));
+
+    #[cfg(feature = "test-injection")]
+    let cmd_line_root = None;
+
+    let force = args.force;
+    if force && !init.config.core.supported_game_versions.is_empty() {
+        warn!("--force was given; plugins are loaded into the game regardless of supported_game_versions");
+    }
+
+    // the game's working dir is different, so these must be absolute
+    let plugin_overrides = args
+        .plugin
+        .iter()
+        .map(|p| {
+            fs::canonicalize(p)
+                .ok()
+                .filter(|p| p.is_file())
+                .ok_or_else(|| eyre!("--plugin {} does not exist or is not a file", p.display()))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    if !plugin_overrides.is_empty() {
+        info!(plugins = ?plugin_overrides, "Only loading plugins given with --plugin");
+    }
+
+    loader::QUIET.store(args.quiet, Ordering::Relaxed);
+    loader::FORCE.store(force, Ordering::Relaxed);
+
+    if matches!(run_type, RunType::Watcher) {
+        let confirm = init.config.core.confirm_inject && !args.quiet;
+        server::CONFIRM_INJECT.store(confirm, Ordering::Relaxed);
+    }
+
+    info!(strategy = %init.config.core.wait_strategy, "Using wait strategy");
+
     let (polling_rate, timeout, oneshot, wait_for_init) = if matches!(run_type, RunType::Watcher) {
         // watcher tool
         (Duration::from_secs(2), Timeout::None, false, false)
@@ -57,31 +334,178 @@ pub fn run(run_type: RunType) -> Result<()> {
         )
     };
 
-    let ProcessWatcherResults {
-        watcher_token: token,
-        watcher_handle,
-        timeout_token,
-    } = ProcessWatcher::new(processes, polling_rate, timeout, oneshot).run(
-        move |call| match call {
-            CallType::Pid(pid) => {
-                trace!(pid, "Received callback for pid, now loading");
-                let res = run_loader(init.config, pid, &init.loader, true, wait_for_init);
-                if let Err(e) = res {
-                    error!(err = %e, "run_loader failed");
-                    fatal_popup(
-                        "run loader failed",
-                        format!(
-                            "run_loader unexpectedly failed. You should report this.\n\nError: {e}"
-                        ),
-                    );
+    // only the watcher polls for long, so only it gets to be lazy about some exes
+    let target_polling_rates = processes
+        .iter()
+        .map(|p| {
+            let exe = Path::new(p).file_name()?.to_str()?;
+            let interval = init.config.core.poll_interval(exe)?;
+
+            if matches!(run_type, RunType::Watcher) {
+                trace!(exe, ?interval, "using custom poll interval");
+                Some(interval)
+            } else {
+                None
+            }
+        })
+        .collect::<Vec<_>>();
+
+    // a separate watcher, so the launcher never counts as an injected game
+    #[cfg(not(feature = "test-injection"))]
+    let launcher_token = (matches!(run_type, RunType::Watcher)
+        && init.config.core.inject_into_launcher)
+        .then(|| {
+            let loader = Loader {
+                rva: init.loader.rva,
+                path: init.loader.path.clone(),
+                file: None,
+            };
+
+            watch_launcher(init.config, loader)
+        });
+
+    #[cfg(feature = "test-injection")]
+    let launcher_token: Option<StopToken> = None;
+
+    // for the control pipe's reinject command
+    let reinject = {
+        let loader = Loader {
+            rva: init.loader.rva,
+            path: init.loader.path.clone(),
+            file: None,
+        };
+
+        let config = init.config;
+        let overridden = !plugin_overrides.is_empty();
+
+        move |pid| {
+            if overridden {
+                bail!("reinjecting can't be used with --plugin");
+            }
+
+            let config = current_config().unwrap_or(config);
+            run_loader(config, pid, &loader, None, false, true, LoadPhase::Reinject).map(|_| ())
+        }
+    };
+
+    // loads plugins into a game the watcher found
+    let watcher_status = status.clone();
+    let inject = Arc::new(move |pid: Pid| {
+        // the control pipe may have reloaded it
+        let config = current_config().unwrap_or(init.config);
+
+        if config.core.verify_game_signature && !verify_game_signature(pid) {
+            return;
+        }
+
+        let gated = !force && !config.core.supported_game_versions.is_empty();
+        if gated && !is_game_version_supported(config, pid) {
+            return;
+        }
+
+        if !config.core.target_plugin_dirs.is_empty() {
+            log_target_plugin_dir(config, pid);
+        }
+
+        // everything which doesn't need the game to be ready is done before waiting on it,
+        // so loading can start right away once the wait is over
+        // not fatal; run_loader opens it itself then
+        let prepared = match loader::prepare(pid) {
+            Ok(v) => Some(v),
+            Err(e) => {
+                trace!(%e, pid, "failed to open game process ahead of time");
+                None
+            }
+        };
+
+        // not fatal; at worst the injection happens earlier than asked for
+        if let Err(e) = wait_for_game(&config.core.wait_strategy, pid) {
+            warn!(%e, "failed waiting for game; injecting anyways");
+        }
+
+        if config.core.wait_for_main_menu {
+            let core = &config.core;
+            let res = wait_for_main_menu(
+                pid,
+                Duration::from_millis(core.main_menu_settle),
+                Duration::from_millis(core.main_menu_timeout),
+            );
+
+            if let Err(e) = res {
+                warn!(%e, "failed waiting for main menu; injecting anyways");
+            }
+        }
+
+        // written last, so it's only left behind when loading fails after this
+        let overrides = if plugin_overrides.is_empty() {
+            None
+        } else {
+            match write_plugin_overrides(pid, &plugin_overrides) {
+                Ok(path) => Some(path),
+                Err(e) => {
+                    error!(%e, pid, "failed to pass --plugin list to the loader; not loading plugins into this process");
+                    return;
                 }
             }
+        };
 
-            // only fires with injector
-            CallType::Timeout => {
-                display_popup(
-                    "Timed Out",
-                    r"Game process was not found.
+        trace!(pid, "Received callback for pid, now loading");
+        let res = run_loader(
+            config,
+            pid,
+            &init.loader,
+            prepared,
+            true,
+            wait_for_init,
+            LoadPhase::All,
+        );
+
+        // only reported as injected once the loader was actually started in it
+        if matches!(res, Ok(true)) {
+            watcher_status.set_injected(Some(pid));
+        }
+
+        // the loader removes it once it read it. it's keyed by the process' creation time, so if
+        // loading was skipped and it stays behind, no other process ever reads it
+        if let (Err(_), Some(path)) = (&res, &overrides) {
+            _ = fs::remove_file(path);
+        }
+
+        if let Err(e) = res {
+            error!(err = %e, "run_loader failed");
+            status::set_last_result(format!("failed to inject pid {pid}"));
+
+            let message =
+                format!("run_loader unexpectedly failed. You should report this.\n\nError: {e}");
+            event_log::report("run loader failed", &message);
+            fatal_popup("run loader failed", message);
+        }
+
+        loader::selftest(config, pid);
+    });
+
+    // handles what the watcher finds. shared by every watcher the supervisor starts
+    let on_call = Arc::new(move |call: CallType| match call {
+        // the injector stops once it's done with the one game, so it has to wait for it
+        CallType::Pid(pid) if oneshot => inject(pid),
+
+        // every game gets a thread of its own, so waiting for one to be ready never keeps the watcher
+        // from finding others
+        CallType::Pid(pid) => {
+            let inject = inject.clone();
+            thread::spawn(move || {
+                let span = trace_span!("inject", pid);
+                let _guard = span.enter();
+
+                inject(pid);
+            });
+        }
+
+        // only fires with injector
+        CallType::Timeout => {
+            display_popup(
+                "Timed Out",
+                r"Game process was not found.
 
 This can happen for 1 of 3 reasons:
 
@@ -90,20 +514,330 @@ This can happen for 1 of 3 reasons:
 2. The game wasn't detected because your `install_root` config value isn't correct
 
 3. In rare cases, it could be that the program doesn't have permission to open the game process, so it never sees it. In such a case, you should run this as admin (only as a last resort; in normal cases this is not needed)",
-                    MessageBoxIcon::Error,
-                );
+                MessageBoxIcon::Error,
+            );
+        }
+    });
+
+    let jitter = Duration::from_millis(init.config.core.poll_jitter);
+
+    // polls are never further apart than this, so missing a number of them in a row means it's stuck
+    let longest_poll = target_polling_rates
+        .iter()
+        .flatten()
+        .copied()
+        .fold(polling_rate, Duration::max);
+    let stall_after = (longest_poll + jitter) * supervisor::STALL_POLLS;
+
+    let ProcessWatcherResults {
+        watcher_token: token,
+        watcher_handle,
+        timeout_token,
+    } = {
+        let watched = processes.to_vec();
+        let config = init.config;
+        let status = status.clone();
+
+        supervisor::spawn(status.clone(), polling_rate, stall_after, move || {
+            let on_call = on_call.clone();
+
+            ProcessWatcher::new(
+                &watched,
+                cmd_line_root,
+                polling_rate,
+                &target_polling_rates,
+                jitter,
+                timeout,
+                config.core.inject_all_instances,
+                oneshot,
+                config.core.follow_launcher,
+                status.clone(),
+            )
+            .run(move |call| on_call(call))
+        })
+    };
+
+    let watcher_token = token.clone();
+
+    #[cfg(not(feature = "test-injection"))]
+    if args.launch {
+        if matches!(run_type, RunType::Watcher) {
+            use crate::launch::launch_if_not_running;
+
+            // [bg3, bg3_dx11]; the watcher picks it up once it's running
+            let processes = processes.clone();
+            let config = init.config;
+            thread::spawn(move || launch_if_not_running(config, &processes, &processes[0]));
+        } else {
+            warn!("--launch only works with the watcher tool; ignoring it");
+        }
+    }
+
+    // the console status line replaces the tray
+    let (tray, _timeout_token) = if args.foreground {
+        foreground::init();
+        // the timeout is cancelled if this is dropped, so keep it around
+        (None, timeout_token)
+    } else if args.no_tray || !init.config.core.show_tray {
+        // so Ctrl+C works when started from a terminal
+        attach_parent_console();
+        info!("Tray is disabled. Stop with Ctrl+C, or the control pipe's stop command");
+        (None, timeout_token)
+    } else {
+        if init.config.core.tray_on_inject {
+            // until the game is injected, the same goes as for a disabled tray
+            attach_parent_console();
+        }
+
+        let tray = AppTray::run(init.config, status.clone(), token, timeout_token, run_type);
+        (Some(tray), None)
+    };
+
+    // makes run return
+    let stop = {
+        let watcher_token = watcher_token.clone();
+        let tray_exit = tray.as_ref().map(|t| t.exit_handle());
+
+        let launcher_token = launcher_token.clone();
+
+        move || {
+            watcher_token.stop();
+
+            if let Some(launcher_token) = &launcher_token {
+                launcher_token.stop();
             }
-        },
-    );
 
-    let tray = AppTray::run(token, timeout_token, run_type);
+            if let Some(tray_exit) = tray_exit {
+                tray_exit.exit();
+            }
+        }
+    };
+
+    // with a console, Ctrl+C or closing it would otherwise kill us without flushing the logs. without a tray,
+    // it's also one of the only ways to stop
+    set_ctrl_handler(stop.clone());
+
     if matches!(run_type, RunType::Watcher) {
+        control::spawn(status.clone(), reinject, stop);
+    }
+
+    if run_timeout.is_some() || cancel.is_some() {
+        let deadline = run_timeout.map(|t| Instant::now() + t);
+
+        // poll so that the watcher finishing on its own is noticed too
+        while !watcher_handle.is_finished() {
+            if deadline.is_some_and(|d| Instant::now() >= d) {
+                trace!("run timed out");
+                break;
+            }
+
+            let wait = deadline
+                .map(|d| d.saturating_duration_since(Instant::now()))
+                .unwrap_or(Duration::MAX)
+                .min(Duration::from_millis(100));
+
+            if let Some(cancel) = cancel.as_ref() {
+                let signal = cancel.recv_timeout(wait);
+                if matches!(signal, Ok(_) | Err(RecvTimeoutError::Disconnected)) {
+                    trace!(?signal, "run cancelled");
+                    break;
+                }
+            } else {
+                thread::sleep(wait);
+            }
+        }
+
+        watcher_token.stop();
+
+        if let Some(launcher_token) = &launcher_token {
+            launcher_token.stop();
+        }
+
+        if let Some(tray) = tray {
+            tray.exit();
+            _ = tray.join();
+        }
+
+        _ = watcher_handle.join();
+
+        return Ok(());
+    }
+
+    match tray {
         // will exit when Quit clicked
-        _ = tray.join();
+        Some(tray) if matches!(run_type, RunType::Watcher) => {
+            _ = tray.join();
+        }
+
+        Some(_) => (),
+        // will exit on Ctrl+C
+        None if args.foreground => foreground::status_line(&status, processes, &watcher_handle),
+        // will exit on Ctrl+C or the control pipe's stop command
+        None => (),
     }
 
     // will exit when signal sent
     _ = watcher_handle.join();
 
+    info!("watcher stopped; exiting");
+
     Ok(())
 }
+
+/// Watch for the launcher, and load the launcher plugins into it
+#[cfg(not(feature = "test-injection"))]
+fn watch_launcher(config: &'static shared::config::Config, loader: Loader) -> StopToken {
+    let launcher = paths::get_launcher_path(config);
+
+    info!(%launcher, plugins = ?config.core.launcher_plugins, "Watching for the launcher");
+
+    let poll = Duration::from_secs(2);
+    let jitter = Duration::from_millis(config.core.poll_jitter);
+
+    let on_call = move |call: CallType| {
+        let CallType::Pid(pid) = call else {
+            return;
+        };
+
+        info!(pid, plugins = ?config.core.launcher_plugins, "Found launcher; loading launcher plugins into it");
+
+        // the wait strategy is meant for the game, so the launcher is loaded into right away
+        let res = run_loader(config, pid, &loader, None, true, false, LoadPhase::All);
+        if let Err(e) = res {
+            error!(err = %e, "run_loader failed for the launcher");
+        }
+    };
+    let on_call = Arc::new(on_call);
+
+    let stall_after = (poll + jitter) * supervisor::STALL_POLLS;
+
+    // only the supervisor looks at it
+    let status = WatcherStatus::default();
+
+    let ProcessWatcherResults { watcher_token, .. } =
+        supervisor::spawn(status.clone(), poll, stall_after, move || {
+            let on_call = on_call.clone();
+
+            ProcessWatcher::new(
+                &[&launcher],
+                None,
+                poll,
+                &[],
+                jitter,
+                Timeout::None,
+                true,
+                false,
+                // the launcher is what's being watched for here
+                false,
+                status.clone(),
+            )
+            .run(move |call| on_call(call))
+        });
+
+    watcher_token
+}
+
+/// The full path of a process' exe
+fn game_exe_path(pid: Pid) -> Result<PathBuf> {
+    let process = unsafe { OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, false, pid)? };
+    let process = OwnedHandle::new(process);
+
+    let mut buf = Vec::new();
+    let path = QueryFullProcessImageNameRs(&process, &mut buf)?;
+    Ok(PathBuf::from(path.to_os_string()))
+}
+
+/// Log which plugins dir the loader will use for this game, by `target_plugin_dirs`
+fn log_target_plugin_dir(config: &Config, pid: Pid) {
+    let exe = game_exe_path(pid)
+        .ok()
+        .and_then(|path| path.file_name().map(|n| n.to_string_lossy().into_owned()))
+        .unwrap_or_default();
+
+    match plugins_dir_for(config, &exe) {
+        Ok(dir) => info!(pid, %exe, dir = %dir.display(), "Resolved plugins dir for this game"),
+        Err(e) => warn!(%e, "failed to find plugins dir"),
+    }
+}
+
+/// Whether the game's version is in `supported_game_versions`. Shows a popup if it isn't
+fn is_game_version_supported(config: &Config, pid: Pid) -> bool {
+    let version = game_exe_path(pid)
+        .ok()
+        .and_then(VersionInfo::from_file)
+        .and_then(|v| v.fixed_version);
+
+    let Some(version) = version else {
+        warn!(pid, "failed to detect game version; not loading plugins into it, since supported_game_versions is set");
+        warn_popup(
+            "Unknown game version",
+            "`supported_game_versions` is set in config.toml, but the game's version could not be detected, so no plugins have been loaded into it.\n\nTo load plugins anyways, start this tool with --force.",
+        );
+        return false;
+    };
+
+    if let Some(range) = config.core.supported_game_range(version) {
+        info!(%version, %range, "Game version is supported");
+        return true;
+    }
+
+    warn!(pid, %version, supported = ?config.core.supported_game_versions, "game version is not in supported_game_versions; not loading plugins into it");
+    warn_popup(
+        "Unsupported game version",
+        format!("This game version ({version}) is not in `supported_game_versions` in config.toml, so no plugins have been loaded into it. The mod loader and your plugins have not been checked against this version, and may crash it.\n\nOnce you've checked that they work, add this version to `supported_game_versions`, or start this tool with --force to load plugins anyways."),
+    );
+
+    false
+}
+
+/// Check the game exe's Authenticode signature, so a fake or tampered game isn't loaded into.
+/// Shows a popup when it fails
+fn verify_game_signature(pid: Pid) -> bool {
+    let path = game_exe_path(pid);
+
+    let reason = match path {
+        Ok(path) => match signature::verify(&path) {
+            Signature::Valid(signer) => {
+                info!(path = %path.display(), ?signer, "Game exe has a valid signature");
+                return true;
+            }
+
+            Signature::Invalid(reason) => reason,
+        },
+
+        Err(e) => format!("the game exe could not be found: {e}"),
+    };
+
+    warn!(pid, %reason, "game failed signature verification; not loading plugins into it");
+    warn_popup(
+        "Untrusted game",
+        format!("`verify_game_signature` is enabled in config.toml, but the game failed signature verification, so no plugins have been loaded into it.\n\nIf your game is a legitimate copy, you can disable `verify_game_signature`.\n\nReason: {reason}"),
+    );
+
+    false
+}
+
+/// Hand the `--plugin` list to the loader in the game process. Returns where it was written
+fn write_plugin_overrides(pid: Pid, plugins: &[PathBuf]) -> Result<PathBuf> {
+    let process = unsafe { OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, false, pid)? };
+    let process = OwnedHandle::new(process);
+
+    let created = process_creation_time(process.as_raw_handle())?;
+    let path = get_plugin_override_path(pid, created)?;
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    let list = plugins
+        .iter()
+        .map(|p| p.to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    fs::write(&path, list)?;
+
+    trace!(path = %path.display(), "wrote --plugin list");
+
+    Ok(path)
+}
@@ -1,23 +1,42 @@
 use std::{
+    collections::HashMap,
     convert::Infallible,
     io,
-    sync::atomic::{AtomicU32, AtomicU64, Ordering},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        LazyLock, Mutex,
+    },
+    thread,
 };
 
-use shared::pipe::{
-    commands::{Level, Receive},
-    Server,
+use shared::{
+    config::get_config,
+    pipe::{
+        commands::{Level, LoadedMsg, Receive},
+        Auth, Server,
+    },
+    popup::{display_popup, MessageBoxIcon},
+    utils::SuperLock as _,
 };
 use tracing::{debug, error, info, trace, trace_span, warn};
 
-pub static AUTH: AtomicU64 = AtomicU64::new(0);
-pub static PID: AtomicU32 = AtomicU32::new(0);
+use crate::{process_watcher::Pid, status::set_last_result, unload_watch, webhook};
+
+/// the auth code each game's loader must connect with, by the game's pid. Several games may be injected at once
+static AUTH: LazyLock<Mutex<HashMap<Pid, Auth>>> = LazyLock::new(Mutex::default);
+/// Whether to show the `confirm_inject` popup the next time plugins were loaded. Only ever shown once
+pub static CONFIRM_INJECT: AtomicBool = AtomicBool::new(false);
+
+/// Let the loader injected into `pid` connect with `auth`. Each code is only good for one connection
+pub fn expect_loader(pid: Pid, auth: Auth) {
+    AUTH.super_lock().insert(pid, auth);
+}
 
 pub fn server() -> io::Result<Infallible> {
-    let mut server = Server::new();
+    let server = Server::new();
 
-    let cb = |cmd| {
-        let span = trace_span!("dll");
+    let cb = |pid: Pid, cmd| {
+        let span = trace_span!("dll", pid);
         let _guard = span.enter();
 
         match cmd {
@@ -54,19 +73,61 @@ pub fn server() -> io::Result<Infallible> {
                     }
                 }
             }
+
+            Receive::Loaded(msg) => {
+                trace!(pid, ?msg, "loader finished loading plugins");
+
+                set_last_result(format!(
+                    "pid {pid}: {} loaded, {} failed",
+                    msg.loaded, msg.failed
+                ));
+
+                let config = get_config().ok().map(|c| c.get());
+
+                if msg.loaded > 0 && config.is_some_and(|c| c.core.watch_unloads) {
+                    unload_watch::watch(pid);
+                }
+
+                let url = config.and_then(|c| c.core.on_inject_url.clone());
+
+                // once per game, and only if it actually got plugins
+                if let Some(url) = url.filter(|_| msg.last_phase && msg.loaded > 0) {
+                    webhook::on_inject(url, pid, &msg);
+                }
+
+                if msg.loaded > 0 && CONFIRM_INJECT.swap(false, Ordering::Relaxed) {
+                    confirm_popup(&msg);
+                }
+            }
         }
     };
 
     let auth = |pid, code| {
-        let ppid = PID.load(Ordering::Relaxed);
-
-        // load current auth code; also change auth code to always keep it randomized
-        let passcode = AUTH.swap(rand::random::<u64>(), Ordering::Relaxed);
+        let expected = AUTH.super_lock().remove(&pid);
 
-        trace!(pid, ppid, code, passcode, "verifying pid and auth code");
+        trace!(pid, code, ?expected, "verifying pid and auth code");
 
-        ppid == pid && passcode == code
+        expected == Some(code)
     };
 
     server.recv_all(cb, auth)
 }
+
+/// Let the user know modding worked, without them having to look at the logs
+fn confirm_popup(msg: &LoadedMsg) {
+    let mut message = format!(
+        "Loaded {} plugins into the game:\n\n{}",
+        msg.loaded,
+        msg.names.join("\n")
+    );
+
+    if msg.failed > 0 {
+        message.push_str(&format!(
+            "\n\n{} plugins failed to load. See the log for details.",
+            msg.failed
+        ));
+    }
+
+    // the popup blocks, and the pipe must keep being read
+    thread::spawn(move || display_popup("Plugins loaded", message, MessageBoxIcon::Info));
+}
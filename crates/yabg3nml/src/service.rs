@@ -0,0 +1,291 @@
+use std::{
+    env,
+    ffi::c_void,
+    ptr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{channel, Sender},
+        Mutex, OnceLock,
+    },
+    time::Duration,
+};
+
+use eyre::{Context as _, Result};
+use shared::{paths::get_local_appdata_dir, utils::SuperLock as _};
+use tracing::{error, info, trace};
+use windows::{
+    core::{w, HSTRING, PCWSTR, PWSTR},
+    Win32::{
+        Foundation::{ERROR_CALL_NOT_IMPLEMENTED, ERROR_SERVICE_SPECIFIC_ERROR, NO_ERROR},
+        System::Services::{
+            ChangeServiceConfig2W, CloseServiceHandle, ControlService, CreateServiceW,
+            DeleteService, OpenSCManagerW, OpenServiceW, RegisterServiceCtrlHandlerExW,
+            SetServiceStatus, StartServiceCtrlDispatcherW, SC_ACTION, SC_ACTION_RESTART, SC_HANDLE,
+            SC_MANAGER_CONNECT, SC_MANAGER_CREATE_SERVICE, SERVICE_ACCEPT_STOP, SERVICE_ALL_ACCESS,
+            SERVICE_AUTO_START, SERVICE_CONFIG_FAILURE_ACTIONS,
+            SERVICE_CONFIG_FAILURE_ACTIONS_FLAG, SERVICE_CONTROL_INTERROGATE, SERVICE_CONTROL_STOP,
+            SERVICE_ERROR_NORMAL, SERVICE_FAILURE_ACTIONSW, SERVICE_FAILURE_ACTIONS_FLAG,
+            SERVICE_RUNNING, SERVICE_START_PENDING, SERVICE_STATUS, SERVICE_STATUS_CURRENT_STATE,
+            SERVICE_STATUS_HANDLE, SERVICE_STOPPED, SERVICE_STOP_PENDING, SERVICE_TABLE_ENTRYW,
+            SERVICE_WIN32_OWN_PROCESS,
+        },
+    },
+};
+
+use crate::{
+    cli::Args,
+    run::{run_with, RunOptions, RunType},
+};
+
+const NAME: PCWSTR = w!("yabg3nml-watcher");
+const DISPLAY_NAME: PCWSTR = w!("Yet Another BG3 Native Mod Loader (watcher)");
+
+/// how long the SCM waits before restarting the service after it failed
+const RESTART_DELAY: Duration = Duration::from_secs(5);
+/// failures are only counted for this long, so an occasional failure can't use up the restarts
+const FAILURE_RESET: Duration = Duration::from_secs(24 * 60 * 60);
+
+static IN_SERVICE: AtomicBool = AtomicBool::new(false);
+static STATUS_HANDLE: OnceLock<StatusHandle> = OnceLock::new();
+static STOP: Mutex<Option<Sender<()>>> = Mutex::new(None);
+/// the args this process was started with, for the service control manager's callback
+static ARGS: Mutex<Option<Args>> = Mutex::new(None);
+
+struct StatusHandle(SERVICE_STATUS_HANDLE);
+
+// SAFETY: service status handles may be used from any thread
+unsafe impl Send for StatusHandle {}
+unsafe impl Sync for StatusHandle {}
+
+struct ScHandle(SC_HANDLE);
+
+impl Drop for ScHandle {
+    fn drop(&mut self) {
+        _ = unsafe { CloseServiceHandle(self.0) };
+    }
+}
+
+/// Whether this process was started by the service control manager
+pub fn in_service() -> bool {
+    IN_SERVICE.load(Ordering::Relaxed)
+}
+
+/// Register the watcher as a service, which starts at boot and restarts on failure. Requires admin
+///
+/// Services don't run as the user, so the user's AppData folder is remembered, to find the plugins folder from
+pub fn install() -> Result<()> {
+    let exe = env::current_exe().context("unable to find current exe path")?;
+    let appdata = get_local_appdata_dir()?;
+
+    let cmd = format!(
+        r#""{}" --run-as-service --local-appdata "{}""#,
+        exe.display(),
+        appdata.display()
+    );
+
+    trace!(cmd, "installing service");
+
+    let manager = open_manager(SC_MANAGER_CONNECT | SC_MANAGER_CREATE_SERVICE)?;
+
+    let cmd = HSTRING::from(cmd);
+    let service = unsafe {
+        CreateServiceW(
+            manager.0,
+            NAME,
+            DISPLAY_NAME,
+            SERVICE_ALL_ACCESS,
+            SERVICE_WIN32_OWN_PROCESS,
+            SERVICE_AUTO_START,
+            SERVICE_ERROR_NORMAL,
+            PCWSTR::from_raw(cmd.as_ptr()),
+            PCWSTR::null(),
+            None,
+            PCWSTR::null(),
+            PCWSTR::null(),
+            PCWSTR::null(),
+        )
+    };
+
+    let service = ScHandle(service.context("failed to create service; this requires admin")?);
+
+    let mut actions = [SC_ACTION {
+        Type: SC_ACTION_RESTART,
+        Delay: RESTART_DELAY.as_millis() as u32,
+    }; 3];
+
+    let failure_actions = SERVICE_FAILURE_ACTIONSW {
+        dwResetPeriod: FAILURE_RESET.as_secs() as u32,
+        lpRebootMsg: PWSTR::null(),
+        lpCommand: PWSTR::null(),
+        cActions: actions.len() as u32,
+        lpsaActions: actions.as_mut_ptr(),
+    };
+
+    // not fatal; the service just won't be restarted
+    let res = unsafe {
+        ChangeServiceConfig2W(
+            service.0,
+            SERVICE_CONFIG_FAILURE_ACTIONS,
+            Some(ptr::from_ref(&failure_actions).cast()),
+        )
+    };
+
+    if let Err(e) = res {
+        error!(%e, "failed to set service failure actions");
+    }
+
+    // without this, the service is only restarted when it crashes, and not when it stops with an error
+    let flag = SERVICE_FAILURE_ACTIONS_FLAG {
+        fFailureActionsOnNonCrashFailures: true.into(),
+    };
+
+    // not fatal; the service just won't be restarted after errors
+    let res = unsafe {
+        ChangeServiceConfig2W(
+            service.0,
+            SERVICE_CONFIG_FAILURE_ACTIONS_FLAG,
+            Some(ptr::from_ref(&flag).cast()),
+        )
+    };
+
+    if let Err(e) = res {
+        error!(%e, "failed to set service failure actions flag");
+    }
+
+    info!("Installed service");
+
+    Ok(())
+}
+
+/// Stop and remove the service. Requires admin
+pub fn uninstall() -> Result<()> {
+    let manager = open_manager(SC_MANAGER_CONNECT)?;
+
+    let service = unsafe { OpenServiceW(manager.0, NAME, SERVICE_ALL_ACCESS) };
+    let service =
+        ScHandle(service.context("failed to open service; is it installed? this requires admin")?);
+
+    // it may not be running
+    let mut status = SERVICE_STATUS::default();
+    _ = unsafe { ControlService(service.0, SERVICE_CONTROL_STOP, &mut status) };
+
+    unsafe { DeleteService(service.0) }.context("failed to delete service")?;
+
+    info!("Uninstalled service");
+
+    Ok(())
+}
+
+/// Hand this process over to the service control manager, which runs the watcher with `args`. Returns once the
+/// service stopped
+pub fn run(args: Args) -> Result<()> {
+    *ARGS.super_lock() = Some(args);
+
+    let table = [
+        SERVICE_TABLE_ENTRYW {
+            lpServiceName: PWSTR(NAME.as_ptr().cast_mut()),
+            lpServiceProc: Some(service_main),
+        },
+        // the table is terminated by an empty entry
+        SERVICE_TABLE_ENTRYW::default(),
+    ];
+
+    unsafe { StartServiceCtrlDispatcherW(table.as_ptr()) }
+        .context("failed to start service dispatcher; --run-as-service must only be used by the service control manager")?;
+
+    Ok(())
+}
+
+unsafe extern "system" fn service_main(_argc: u32, _argv: *mut PWSTR) {
+    IN_SERVICE.store(true, Ordering::Relaxed);
+
+    let handle = unsafe { RegisterServiceCtrlHandlerExW(NAME, Some(ctrl_handler), None) };
+    let handle = match handle {
+        Ok(h) => h,
+        Err(e) => {
+            error!(%e, "failed to register service ctrl handler");
+            return;
+        }
+    };
+
+    _ = STATUS_HANDLE.set(StatusHandle(handle));
+
+    set_status(SERVICE_START_PENDING, NO_ERROR.0, 0);
+
+    let (sender, cancel) = channel();
+    *STOP.super_lock() = Some(sender);
+
+    set_status(SERVICE_RUNNING, NO_ERROR.0, 0);
+
+    let options = RunOptions {
+        args: ARGS.super_lock().take().unwrap_or_default(),
+        cancel: Some(cancel),
+        ..Default::default()
+    };
+
+    // the args are the same ones this process was started with, so the options still apply
+    match run_with(RunType::Watcher, options) {
+        Ok(_) => set_status(SERVICE_STOPPED, NO_ERROR.0, 0),
+        Err(e) => {
+            error!(%e, "service failed");
+            set_status(SERVICE_STOPPED, ERROR_SERVICE_SPECIFIC_ERROR.0, 1);
+        }
+    }
+}
+
+unsafe extern "system" fn ctrl_handler(
+    control: u32,
+    _event_type: u32,
+    _event_data: *mut c_void,
+    _context: *mut c_void,
+) -> u32 {
+    match control {
+        SERVICE_CONTROL_STOP => {
+            info!("Service stop requested");
+            set_status(SERVICE_STOP_PENDING, NO_ERROR.0, 0);
+
+            if let Some(stop) = STOP.super_lock().take() {
+                _ = stop.send(());
+            }
+
+            NO_ERROR.0
+        }
+
+        SERVICE_CONTROL_INTERROGATE => NO_ERROR.0,
+
+        _ => ERROR_CALL_NOT_IMPLEMENTED.0,
+    }
+}
+
+fn set_status(state: SERVICE_STATUS_CURRENT_STATE, exit_code: u32, specific_exit_code: u32) {
+    let Some(handle) = STATUS_HANDLE.get() else {
+        return;
+    };
+
+    let controls_accepted = if state == SERVICE_RUNNING {
+        SERVICE_ACCEPT_STOP
+    } else {
+        0
+    };
+
+    let status = SERVICE_STATUS {
+        dwServiceType: SERVICE_WIN32_OWN_PROCESS,
+        dwCurrentState: state,
+        dwControlsAccepted: controls_accepted,
+        dwWin32ExitCode: exit_code,
+        dwServiceSpecificExitCode: specific_exit_code,
+        dwCheckPoint: 0,
+        dwWaitHint: 0,
+    };
+
+    if let Err(e) = unsafe { SetServiceStatus(handle.0, &status) } {
+        error!(%e, ?state, "failed to set service status");
+    }
+}
+
+fn open_manager(access: u32) -> Result<ScHandle> {
+    let manager = unsafe { OpenSCManagerW(PCWSTR::null(), PCWSTR::null(), access) };
+    let manager = manager.context("failed to open service control manager; this requires admin")?;
+
+    Ok(ScHandle(manager))
+}
@@ -1,47 +1,74 @@
-use std::{process, thread};
+use std::{env, path::Path, thread};
 
-use eyre::{Context as _, Result};
+use eyre::Result;
 use shared::{
-    config::{get_config, Config, ConfigState},
-    paths::{get_bg3_local_dir, get_bg3_plugins_dir},
-    popup::{display_popup, fatal_popup, MessageBoxIcon},
+    config::{backup_config, config_backup_path, get_config, Config, ConfigState},
+    exit::exit,
+    paths::{
+        get_bg3_local_dir, get_bg3_plugins_dir, get_portable_dir, set_portable_dir, PORTABLE_MARKER,
+    },
+    popup::{display_popup, fatal_popup, warn_popup, MessageBoxIcon},
 };
-use tracing::{error, trace, trace_span};
-use tracing_appender::non_blocking::WorkerGuard;
+use tracing::{error, info, trace, trace_span, warn};
 use windows::Win32::Security::SE_DEBUG_NAME;
 
 use crate::{
+    cli::Args,
+    event_log,
     is_admin::is_admin,
-    logging::setup_logs,
+    is_wine::is_wine,
+    is_wow64::is_wow64,
+    logging::{bootstrap_logs, setup_logs, LogGuard},
     panic::set_hook,
     privileges::set_privilege,
     server::server,
-    tmp_loader::{init_loader, Loader},
+    tmp_loader::{init_loader, Loader, LOADER_HASH},
 };
 
+/// Enable portable mode with `--portable`, or when the portable marker is next to the exe.
+/// Must be called before any paths are looked up
+pub fn set_portable(portable: bool) {
+    let Some(exe_dir) = env::current_exe()
+        .ok()
+        .and_then(|exe| exe.parent().map(Path::to_path_buf))
+    else {
+        return;
+    };
+
+    if portable || exe_dir.join(PORTABLE_MARKER).is_file() {
+        set_portable_dir(exe_dir);
+    }
+}
+
 pub struct InitData {
-    #[cfg_attr(feature = "test-injection", allow(dead_code))]
     pub config: &'static Config,
-    pub worker: Option<WorkerGuard>,
+    pub worker: Option<LogGuard>,
     pub loader: Loader,
 }
 
-pub fn init() -> Result<InitData> {
+pub fn init(args: &Args) -> Result<InitData> {
     let span = trace_span!("setup");
     let _guard = span.enter();
 
+    // the logger isn't up yet, so anything failing before then would otherwise leave no trace
+    let bootstrap = bootstrap_logs();
+
     // enable unfettered access through debug privilege if we have admin access
     if is_admin() {
-        set_privilege(SE_DEBUG_NAME, true)?;
+        set_privilege(SE_DEBUG_NAME, true)
+            .inspect_err(|e| error!(%e, "failed to enable debug privilege"))?;
     }
 
     // Nicely print any panic messages to the user
     set_hook();
 
     let first_time = 'f: {
-        let mut plugins_dir = match get_bg3_local_dir() {
-            Ok(v) => v,
-            Err(_) => break 'f false,
+        let mut plugins_dir = match get_portable_dir() {
+            Some(dir) => dir.to_owned(),
+            None => match get_bg3_local_dir() {
+                Ok(v) => v,
+                Err(_) => break 'f false,
+            },
         };
 
         plugins_dir.push("Plugins");
@@ -56,10 +83,15 @@ pub fn init() -> Result<InitData> {
         }
     };
 
+    // scripted setups don't want a blocking popup, so just continue on
+    let prompt = !(args.quiet || args.no_first_run_prompt);
+
     // get/create config
     let config = match get_config() {
         Ok(ConfigState::Exists(c)) => c,
 
+        Ok(ConfigState::New(c)) if !prompt => c,
+
         Ok(ConfigState::New(_)) if first_time => {
             display_popup(
                 "Finish Setup",
@@ -70,7 +102,7 @@ pub fn init() -> Result<InitData> {
                 MessageBoxIcon::Info,
             );
 
-            process::exit(0);
+            exit(0);
         }
 
         Ok(ConfigState::New(_)) => {
@@ -80,18 +112,106 @@ pub fn init() -> Result<InitData> {
                 MessageBoxIcon::Info,
             );
 
-            process::exit(0);
+            exit(0);
         }
 
         Err(e) => {
-            fatal_popup("Error reading config", format!("Failed to get config file. Most likely either it failed to read the file, or your config file is malformed.\n\nError: {e}"));
+            error!(%e, "failed to read config");
+            fatal_popup("Error reading config", format!("Failed to get config file. Most likely either it failed to read the file, or your config file is malformed.\n\nError: {e}{}", restore_hint()));
         }
     };
 
     // start logger
-    let worker_guard = setup_logs(config, &plugins_dir).context("Failed to set up logs")?;
+    // failing to log is not a good enough reason to stop injection
+    let worker_guard = match setup_logs(config, &plugins_dir, bootstrap) {
+        Ok(guard) => guard,
+        Err(e) => {
+            warn_popup(
+                "Failed to set up logs",
+                format!("Logging could not be set up, so no logs will be written. Plugins will still be loaded.\n\nError: {e}"),
+            );
+
+            None
+        }
+    };
+
+    for var in &config.env_overrides {
+        info!(var, "Config option overridden by environment variable");
+    }
+
+    // only an error with strict_config, which already failed in get_config
+    for key in &config.unknown_keys {
+        warn!(
+            key,
+            "unknown key in config.toml; it is ignored. Is it misspelled?"
+        );
+    }
+
+    if let Err(e) = config.validate() {
+        error!(%e, "config is invalid");
+        fatal_popup(
+            "Invalid config",
+            format!(
+                "`config.toml` has invalid options. Please fix them and try again.\n\n{e}{}",
+                restore_hint()
+            ),
+        );
+    }
+
+    // not fatal; there just won't be a backup to restore
+    if let Err(e) = backup_config() {
+        warn!(%e, "failed to back up config");
+    }
+
+    event_log::set_enabled(config.core.windows_event_log);
+
+    if matches!(get_config(), Ok(ConfigState::New(_))) {
+        let config_path = plugins_dir.join("config.toml");
+
+        if first_time {
+            info!(plugins_dir = %plugins_dir.display(), config = %config_path.display(), "First run; created the plugins folder and config. install_root in the config may need to be adjusted");
+        } else {
+            info!(config = %config_path.display(), "config.toml was missing and has been recreated from scratch");
+        }
+    }
+
+    // native injection semantics differ under wine, so rather than failing in some opaque way, refuse up front
+    match is_wine() {
+        Some(version) => {
+            error!(%version, "detected wine/proton, which is unsupported");
+
+            fatal_popup(
+                "Unsupported platform",
+                format!("Wine/Proton (version {version}) was detected. This mod loader only supports native Windows, and will not work under Wine/Proton (such as on Linux or the Steam Deck).\n\nIf you are on Linux, look for a mod loader made for Wine/Proton instead."),
+            );
+        }
+
+        None => trace!("wine not detected"),
+    }
+
+    // a 32-bit build sees redirected System32 and registry paths, and can't load anything into the 64-bit game,
+    // which otherwise just looks like nothing happening
+    match is_wow64() {
+        Some(machine) => {
+            error!(
+                machine = %format!("0x{:x}", machine.0),
+                "this process is running under WOW64; a 32-bit build is likely in use"
+            );
 
-    let loader = init_loader()?;
+            warn_popup(
+                "Mismatched build",
+                "This program is running as a 32-bit process, but it must be the 64-bit build to load plugins into the game. Nothing will be loaded into the game, and files may be read from or written to the wrong locations.\n\nPlease redownload the program, and make sure to use the 64-bit (x64) build.",
+            );
+        }
+
+        None => trace!("not running under WOW64"),
+    }
+
+    let custom_loader = args
+        .loader
+        .as_deref()
+        .or(config.core.loader_path.as_deref());
+    let loader = init_loader(custom_loader, config.core.secure_loader_acl)?;
 
     trace!("Got config: {config:?}");
 
@@ -112,3 +232,53 @@ pub fn init() -> Result<InitData> {
 
     Ok(init)
 }
+
+/// Log a single line describing this run, so that every log file is self-describing for bug reports
+pub fn log_banner(config: &Config, mode: &str) {
+    let plugins_dir = get_bg3_plugins_dir()
+        .map(|p| redact(&p))
+        .unwrap_or_else(|_| "unknown".to_owned());
+
+    let core = &config.core;
+
+    info!(
+        version = env!("CARGO_PKG_VERSION"),
+        loader_hash = LOADER_HASH,
+        mode,
+        %plugins_dir,
+        portable = get_portable_dir().is_some(),
+        install_root = %redact(&core.install_root),
+        enabled = core.enabled,
+        disabled_plugins = core.disabled_plugins.len(),
+        injection_trigger = %core.injection_trigger,
+        wait_strategy = %core.wait_strategy,
+        max_plugins = ?core.max_plugins,
+        parallel_inject = core.parallel_inject,
+        extensions = ?core.extensions,
+        stage_plugins = core.stage_plugins,
+        cli = core.cli,
+        log_level = %config.log.level,
+        "Yet Another BG3 Native Mod Loader"
+    );
+}
+
+/// Hide the user's name from paths under their profile folder
+fn redact(path: &Path) -> String {
+    let Some(profile) = env::var_os("USERPROFILE") else {
+        return path.display().to_string();
+    };
+
+    match path.strip_prefix(profile) {
+        Ok(rest) => Path::new("%USERPROFILE%").join(rest).display().to_string(),
+        Err(_) => path.display().to_string(),
+    }
+}
+
+/// Points at --restore-config, if there is a backup to restore
+fn restore_hint() -> &'static str {
+    if config_backup_path().is_ok_and(|p| p.is_file()) {
+        "\n\nTo go back to the last config that worked, run this tool with --restore-config."
+    } else {
+        ""
+    }
+}
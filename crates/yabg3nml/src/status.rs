@@ -0,0 +1,116 @@
+use std::{
+    collections::HashSet,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering},
+        Arc, LazyLock, Mutex, MutexGuard,
+    },
+    time::{Duration, Instant},
+};
+
+use shared::utils::SuperLock as _;
+
+use crate::process_watcher::Pid;
+
+/// what [`Inner::last_poll`] is measured from
+static EPOCH: LazyLock<Instant> = LazyLock::new(Instant::now);
+
+/// How the last injection went, e.g. "pid 1234: 3 loaded, 1 failed". Global, since the pipe server which hears
+/// how loading went has no [`WatcherStatus`]
+static LAST_RESULT: Mutex<Option<String>> = Mutex::new(None);
+
+/// How the last injection went, if anything was injected yet
+pub fn last_result() -> Option<String> {
+    LAST_RESULT.super_lock().clone()
+}
+
+pub(crate) fn set_last_result(result: String) {
+    *LAST_RESULT.super_lock() = Some(result);
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    running: AtomicBool,
+    // 0 means none; no real process can have pid 0
+    injected: AtomicU32,
+    // only for this session; it's never saved
+    paused: AtomicBool,
+    // ms since EPOCH, +1 so that 0 means the watcher isn't polling, e.g. while it's injecting
+    last_poll: AtomicU64,
+    // kept here so it outlives a restarted watcher
+    claimed: Mutex<HashSet<Pid>>,
+}
+
+/// Thread-safe handle reflecting the actual state of the process watcher.
+/// Cheap to clone; all clones share the same state
+#[derive(Debug, Clone, Default)]
+pub struct WatcherStatus(Arc<Inner>);
+
+impl WatcherStatus {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether the process watcher is currently active
+    pub fn is_running(&self) -> bool {
+        self.0.running.load(Ordering::Relaxed)
+    }
+
+    /// The pid of the game process which was injected, if it's still running
+    pub fn injected_pid(&self) -> Option<Pid> {
+        match self.0.injected.load(Ordering::Relaxed) {
+            0 => None,
+            pid => Some(pid),
+        }
+    }
+
+    /// Whether a game is currently injected
+    pub fn is_injected(&self) -> bool {
+        self.injected_pid().is_some()
+    }
+
+    /// Whether new game processes are left alone instead of injected
+    pub fn is_paused(&self) -> bool {
+        self.0.paused.load(Ordering::Relaxed)
+    }
+
+    /// How long ago the watcher last polled. None if it isn't polling right now, e.g. while it's injecting
+    pub(crate) fn since_poll(&self) -> Option<Duration> {
+        match self.0.last_poll.load(Ordering::Relaxed) {
+            0 => None,
+            ms => Some(
+                EPOCH
+                    .elapsed()
+                    .saturating_sub(Duration::from_millis(ms - 1)),
+            ),
+        }
+    }
+
+    /// The watcher is polling, and just did
+    pub(crate) fn polled(&self) {
+        let ms = EPOCH.elapsed().as_millis() as u64 + 1;
+        self.0.last_poll.store(ms, Ordering::Relaxed);
+    }
+
+    /// The watcher stopped polling for now, e.g. while it's injecting, which may take however long
+    pub(crate) fn not_polling(&self) {
+        self.0.last_poll.store(0, Ordering::Relaxed);
+    }
+
+    /// Every running game the watcher handed off to be injected. Kept across watcher restarts, so a restarted
+    /// watcher doesn't take the games which were already handled for new ones
+    pub(crate) fn claimed(&self) -> MutexGuard<'_, HashSet<Pid>> {
+        self.0.claimed.super_lock()
+    }
+
+    pub(crate) fn set_running(&self, running: bool) {
+        self.0.running.store(running, Ordering::Relaxed);
+    }
+
+    pub(crate) fn set_injected(&self, pid: Option<Pid>) {
+        self.0.injected.store(pid.unwrap_or(0), Ordering::Relaxed);
+    }
+
+    pub(crate) fn set_paused(&self, paused: bool) {
+        self.0.paused.store(paused, Ordering::Relaxed);
+    }
+}
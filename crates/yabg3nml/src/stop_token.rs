@@ -2,7 +2,7 @@ use std::sync::mpsc::Sender;
 
 use tracing::trace;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct StopToken {
     signal: Sender<()>,
 }
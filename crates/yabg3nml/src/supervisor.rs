@@ -0,0 +1,302 @@
+//! Keeps the process watcher running. If its thread panics or stops polling, a new watcher is started in
+//! its place, a few times at most, before giving up
+
+use std::{
+    mem,
+    sync::mpsc::{channel, Receiver, RecvTimeoutError},
+    thread,
+    time::Duration,
+};
+
+use shared::popup::fatal_popup;
+use tracing::{error, trace, trace_span};
+
+use crate::{process_watcher::ProcessWatcherResults, status::WatcherStatus, stop_token::StopToken};
+
+/// how many times the watcher may be restarted after unexpectedly dying
+pub const MAX_RESTARTS: u32 = 3;
+/// how many polls in a row the watcher may miss before it counts as stalled
+pub const STALL_POLLS: u32 = 10;
+/// how often the watcher is checked on
+const CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Why supervising ended
+#[derive(Debug, PartialEq)]
+enum Outcome {
+    /// it was told to stop
+    Stopped,
+    /// the watcher finished on its own, e.g. the injector found the game or timed out
+    Finished,
+    /// the watcher died more than [`MAX_RESTARTS`] times
+    GaveUp { failures: u32 },
+}
+
+/// Start a watcher with `start`, and restart it on a new one whenever it panics, or doesn't poll for
+/// `stall_after`. Waits `restart_delay` before each restart
+///
+/// Returns handles which stand in for the watcher's: the token stops whichever watcher is running, and the handle
+/// finishes once supervising ends. If the watcher keeps dying, a fatal popup is shown
+pub fn spawn(
+    status: WatcherStatus,
+    restart_delay: Duration,
+    stall_after: Duration,
+    mut start: impl FnMut() -> ProcessWatcherResults + Send + 'static,
+) -> ProcessWatcherResults {
+    let (sender, recv) = channel();
+
+    let mut first = start();
+    let timeout_token = first.timeout_token.take();
+
+    let handle = thread::spawn(move || {
+        let span = trace_span!("supervisor");
+        let _guard = span.enter();
+
+        status.set_running(true);
+
+        let outcome = supervise(
+            first,
+            &recv,
+            &status,
+            restart_delay,
+            stall_after,
+            &mut start,
+        );
+
+        trace!(?outcome, "stopped supervising the process watcher");
+
+        status.set_running(false);
+
+        if let Outcome::GaveUp { failures } = outcome {
+            fatal_popup(
+                "Process watcher failure",
+                format!("The process watcher unexpectedly crashed {failures} times and will not be restarted again. Please report this, and include your log file."),
+            );
+        }
+    });
+
+    ProcessWatcherResults {
+        watcher_token: StopToken::new(sender),
+        timeout_token,
+        watcher_handle: handle,
+    }
+}
+
+fn supervise(
+    mut current: ProcessWatcherResults,
+    stop: &Receiver<()>,
+    status: &WatcherStatus,
+    restart_delay: Duration,
+    stall_after: Duration,
+    start: &mut impl FnMut() -> ProcessWatcherResults,
+) -> Outcome {
+    let mut failures = 0;
+
+    loop {
+        let signal = stop.recv_timeout(CHECK_INTERVAL);
+        if matches!(signal, Ok(_) | Err(RecvTimeoutError::Disconnected)) {
+            trace!(?signal, "signal exited");
+
+            current.watcher_token.stop();
+            _ = current.watcher_handle.join();
+
+            return Outcome::Stopped;
+        }
+
+        if current.watcher_handle.is_finished() {
+            match current.watcher_handle.join() {
+                Ok(()) => return Outcome::Finished,
+
+                Err(e) => {
+                    // the payload may panic, so forget it
+                    // also, custom panic hook already logged it
+                    mem::forget(e);
+                    error!("process watcher panicked");
+                }
+            }
+        } else if status.since_poll().is_some_and(|d| d >= stall_after) {
+            error!(?stall_after, "process watcher stopped polling");
+
+            // it's left behind, but quits as soon as it gets unstuck, instead of running alongside the new one
+            current.watcher_token.stop();
+        } else {
+            continue;
+        }
+
+        failures += 1;
+        if failures > MAX_RESTARTS {
+            error!(failures, "process watcher unexpectedly died too many times");
+            return Outcome::GaveUp { failures };
+        }
+
+        error!(
+            failures,
+            max = MAX_RESTARTS,
+            "process watcher unexpectedly died; restarting it"
+        );
+
+        // give it some breathing room before restart, but still allow it to be stopped
+        let signal = stop.recv_timeout(restart_delay);
+        if matches!(signal, Ok(_) | Err(RecvTimeoutError::Disconnected)) {
+            trace!(?signal, "signal exited");
+            return Outcome::Stopped;
+        }
+
+        // the stalled watcher's last poll would count against the new one
+        status.not_polling();
+
+        current = start();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    };
+
+    use super::*;
+
+    const STALL: Duration = Duration::from_millis(300);
+
+    /// A watcher thread running `body`, which gets the watcher's stop signal
+    fn watcher(body: impl FnOnce(Receiver<()>) + Send + 'static) -> ProcessWatcherResults {
+        let (sender, recv) = channel();
+
+        ProcessWatcherResults {
+            watcher_token: StopToken::new(sender),
+            timeout_token: None,
+            watcher_handle: thread::spawn(move || body(recv)),
+        }
+    }
+
+    /// Supervise the watchers `start` makes, counting how many were started
+    fn run(
+        status: &WatcherStatus,
+        stop: &Receiver<()>,
+        start: impl Fn() -> ProcessWatcherResults,
+    ) -> (Outcome, u32) {
+        let starts = AtomicU32::new(1);
+
+        let mut restart = || {
+            starts.fetch_add(1, Ordering::Relaxed);
+            start()
+        };
+
+        let outcome = supervise(
+            start(),
+            stop,
+            status,
+            Duration::from_millis(1),
+            STALL,
+            &mut restart,
+        );
+
+        (outcome, starts.load(Ordering::Relaxed))
+    }
+
+    #[test]
+    fn restarts_panicking_watcher_then_gives_up() {
+        let (_sender, stop) = channel();
+
+        let (outcome, starts) = run(&WatcherStatus::new(), &stop, || {
+            watcher(|_| panic!("watcher panicked"))
+        });
+
+        assert_eq!(
+            outcome,
+            Outcome::GaveUp {
+                failures: MAX_RESTARTS + 1
+            }
+        );
+        assert_eq!(starts, MAX_RESTARTS + 1);
+    }
+
+    #[test]
+    fn restarted_watcher_keeps_running() {
+        let (sender, stop) = channel();
+        let panicked = Arc::new(AtomicU32::new(0));
+
+        // stops the supervisor once the restarted watcher is up
+        let sender = Arc::new(sender);
+
+        let (outcome, starts) = run(&WatcherStatus::new(), &stop, || {
+            let panicked = panicked.clone();
+            let sender = sender.clone();
+
+            watcher(move |recv| {
+                if panicked.fetch_add(1, Ordering::Relaxed) == 0 {
+                    panic!("watcher panicked");
+                }
+
+                _ = sender.send(());
+                _ = recv.recv();
+            })
+        });
+
+        assert_eq!(outcome, Outcome::Stopped);
+        assert_eq!(starts, 2);
+    }
+
+    #[test]
+    fn finished_watcher_is_not_restarted() {
+        let (_sender, stop) = channel();
+
+        let (outcome, starts) = run(&WatcherStatus::new(), &stop, || watcher(|_| ()));
+
+        assert_eq!(outcome, Outcome::Finished);
+        assert_eq!(starts, 1);
+    }
+
+    #[test]
+    fn restarts_stalled_watcher() {
+        let (_sender, stop) = channel();
+        let status = WatcherStatus::new();
+
+        let (outcome, starts) = run(&status, &stop, || {
+            let status = status.clone();
+
+            // polls once, then never again until it's stopped
+            watcher(move |recv| {
+                status.polled();
+                _ = recv.recv();
+            })
+        });
+
+        assert_eq!(
+            outcome,
+            Outcome::GaveUp {
+                failures: MAX_RESTARTS + 1
+            }
+        );
+        assert_eq!(starts, MAX_RESTARTS + 1);
+    }
+
+    #[test]
+    fn injecting_is_not_stalling() {
+        let (sender, stop) = channel();
+        let status = WatcherStatus::new();
+
+        let supervisor = {
+            let status = status.clone();
+
+            thread::spawn(move || {
+                run(&status, &stop, || {
+                    let status = status.clone();
+
+                    watcher(move |recv| {
+                        status.not_polling();
+                        _ = recv.recv();
+                    })
+                })
+            })
+        };
+
+        thread::sleep(STALL * 3);
+        sender.send(()).unwrap();
+
+        let (outcome, starts) = supervisor.join().unwrap();
+        assert_eq!(outcome, Outcome::Stopped);
+        assert_eq!(starts, 1);
+    }
+}
@@ -3,7 +3,7 @@ use std::{
     fs::{File, OpenOptions},
     io,
     os::windows::prelude::OpenOptionsExt,
-    path::PathBuf,
+    path::{Path, PathBuf},
 };
 
 use eyre::{Context, OptionExt as _, Result};
@@ -11,11 +11,14 @@ use pelite::{
     pe::{PeFile, Rva},
     pe64::exports::GetProcAddress,
 };
-use shared::popup::fatal_popup;
-use tracing::{error, trace, trace_span};
+use shared::{paths::get_portable_dir, pe::invalid_reason, popup::fatal_popup};
+use tracing::{error, info, trace, trace_span, warn};
+use unicase::UniCase;
 use windows::Win32::Storage::FileSystem::FILE_SHARE_READ;
 
-static LOADER_HASH: &str = env!("LOADER_HASH");
+use crate::acl::restrict_to_current_user;
+
+pub static LOADER_HASH: &str = env!("LOADER_HASH");
 
 #[derive(Debug)]
 pub struct Loader {
@@ -24,20 +27,33 @@ pub struct Loader {
     pub file: Option<File>,
 }
 
-pub fn init_loader() -> Result<Loader> {
+/// `custom` is a user-provided loader dll to use instead of the shipped one. It isn't hash checked.
+/// `secure_acl` restricts who can change the shipped one
+pub fn init_loader(custom: Option<&Path>, secure_acl: bool) -> Result<Loader> {
     let span = trace_span!("init_loader");
     let _guard = span.enter();
 
+    if let Some(path) = custom {
+        return init_custom_loader(path);
+    }
+
+    // a broken build would otherwise show up as every loader.dll failing the hash check
+    if !is_sha256(LOADER_HASH) {
+        error!(hash = %LOADER_HASH, "embedded loader hash is not a sha256 hash");
+
+        fatal_popup(
+            "Broken build",
+            "This program was built with an invalid loader.dll hash, so it can't verify loader.dll. Please redownload the program to get a fresh copy of the exe/dll.",
+        );
+    }
+
     let current_exe_path = env::current_exe().context("unable to find current exe path")?;
     let exe_name = current_exe_path
         .file_name()
         .ok_or_eyre("filename not found")?
         .to_string_lossy();
 
-    let loader_path = current_exe_path
-        .parent()
-        .ok_or_eyre("current exe parent dir not found")?
-        .join("loader.dll");
+    let loader_path = shipped_loader_path()?;
 
     if !loader_path.exists() {
         fatal_popup(
@@ -48,14 +64,30 @@ pub fn init_loader() -> Result<Loader> {
         );
     }
 
-    let mut file = OpenOptions::new()
-        .read(true)
-        // permit shared read, but no delete/rename or write until dropped
-        .share_mode(FILE_SHARE_READ.0)
-        .open(&loader_path)?;
+    // done before it's read and hash checked, so it can't be swapped out after
+    if secure_acl {
+        match restrict_to_current_user(&loader_path) {
+            Ok(true) => {
+                info!(path = %loader_path.display(), "Restricted loader dll permissions to the current user")
+            }
+            Ok(false) => {
+                info!(path = %loader_path.display(), "Not restricting loader dll permissions, since this runs as LocalSystem")
+            }
+            Err(e) => {
+                warn!(%e, path = %loader_path.display(), "failed to restrict loader dll permissions; continuing anyways")
+            }
+        }
+    }
 
-    let mut data = Vec::new();
-    io::copy(&mut file, &mut data)?;
+    // reading can fail transiently (e.g. antivirus scanning the file), so give it one more try with a fresh handle
+    let (file, data) = match read_loader(&loader_path) {
+        Ok(v) => v,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Err(e.into()),
+        Err(e) => {
+            warn!(%e, path = %loader_path.display(), "failed to read loader dll; trying again");
+            read_loader(&loader_path)?
+        }
+    };
 
     let hash = sha256::digest(&data);
 
@@ -79,6 +111,116 @@ pub fn init_loader() -> Result<Loader> {
     Ok(loader)
 }
 
+/// Where the loader.dll shipped with the tools is; next to the exe
+pub fn shipped_loader_path() -> Result<PathBuf> {
+    let current_exe_path = env::current_exe().context("unable to find current exe path")?;
+
+    let path = current_exe_path
+        .parent()
+        .ok_or_eyre("current exe parent dir not found")?
+        .join("loader.dll");
+
+    Ok(path)
+}
+
+/// Use a loader dll the user chose. It only has to be a valid 64-bit dll with an `Init` export
+fn init_custom_loader(path: &Path) -> Result<Loader> {
+    if !path.is_file() {
+        fatal_popup(
+            "Custom loader not found",
+            format!(
+                "The custom loader dll was not found at\n{}\n\nPlease check `--loader` or `loader_path` in config.toml.",
+                path.display()
+            ),
+        );
+    }
+
+    // it's injected into the game, which never sees a relative path
+    let path = std::path::absolute(path)?;
+
+    // in portable mode, the loader finds the portable dir by looking next to its own dll
+    if let Some(portable_dir) = get_portable_dir() {
+        let dir = path.parent().unwrap_or(&path).to_string_lossy();
+        if UniCase::new(dir) != UniCase::new(portable_dir.to_string_lossy()) {
+            error!(path = %path.display(), portable_dir = %portable_dir.display(), "custom loader is outside of the portable dir");
+
+            fatal_popup(
+                "Custom loader outside of portable dir",
+                format!(
+                    "In portable mode, the custom loader dll must be in the same folder as this program, since that's where it looks for the plugins folder.\n\nPath: {}\nPortable folder: {}",
+                    path.display(),
+                    portable_dir.display()
+                ),
+            );
+        }
+    }
+
+    let (file, data) = read_loader(&path)?;
+
+    if let Some(reason) = invalid_reason(&data) {
+        error!(path = %path.display(), %reason, "custom loader is not a usable dll");
+
+        fatal_popup(
+            "Invalid custom loader",
+            format!(
+                "The custom loader dll can't be used because {reason}.\n\nPath: {}",
+                path.display()
+            ),
+        );
+    }
+
+    let rva = get_init_rva(&data).inspect_err(|e| {
+        error!(%e, path = %path.display(), "custom loader has no Init export");
+    })?;
+
+    warn!(
+        path = %path.display(),
+        hash = %sha256::digest(&data),
+        "USING A CUSTOM LOADER DLL. It was not hash checked, and runs inside the game with full access to it. Only use loader dlls you trust"
+    );
+
+    Ok(Loader {
+        rva,
+        path,
+        file: Some(file),
+    })
+}
+
+/// Open the loader dll so it can't be changed, and read it. Errors say which step failed, and include the path
+fn read_loader(path: &Path) -> io::Result<(File, Vec<u8>)> {
+    let mut file = OpenOptions::new()
+        .read(true)
+        // permit shared read, but no delete/rename or write until dropped
+        .share_mode(FILE_SHARE_READ.0)
+        .open(path)
+        .map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!(
+                    "failed to open {}; it may be in use by another program, or you may not have permission to read it: {e}",
+                    path.display()
+                ),
+            )
+        })?;
+
+    let mut data = Vec::new();
+    io::copy(&mut file, &mut data).map_err(|e| {
+        io::Error::new(
+            e.kind(),
+            format!(
+                "opened {}, but failed to read it; the disk may be failing or the file may be locked: {e}",
+                path.display()
+            ),
+        )
+    })?;
+
+    Ok((file, data))
+}
+
+fn is_sha256(hash: &str) -> bool {
+    hash.len() == 64 && hash.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
 fn get_init_rva(data: &[u8]) -> Result<Rva> {
     let loader = PeFile::from_bytes(&data)?;
     let rva = loader
@@ -90,3 +232,32 @@ fn get_init_rva(data: &[u8]) -> Result<Rva> {
 
     Ok(rva)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sha256_hashes_are_recognized() {
+        assert!(is_sha256(&sha256::digest(b"loader")));
+        assert!(is_sha256(&"a".repeat(64)));
+        assert!(is_sha256(&"0123456789ABCDEF".repeat(4)));
+    }
+
+    #[test]
+    fn broken_hashes_are_detected() {
+        // e.g. the build script failed to set it
+        assert!(!is_sha256(""));
+        // too short and too long
+        assert!(!is_sha256(&"a".repeat(63)));
+        assert!(!is_sha256(&"a".repeat(65)));
+        // not hex
+        assert!(!is_sha256(&"g".repeat(64)));
+        assert!(!is_sha256(&format!("{} ", "a".repeat(63))));
+    }
+
+    #[test]
+    fn embedded_hash_is_sha256() {
+        assert!(is_sha256(LOADER_HASH));
+    }
+}
@@ -1,34 +1,110 @@
-use std::thread::{self, JoinHandle};
+use std::{
+    sync::mpsc::channel,
+    thread::{self, JoinHandle},
+    time::Duration,
+};
 
+use shared::config::Config;
+use tracing::{info, trace, warn};
 use tray_icon::{
-    menu::{AboutMetadata, Menu, MenuEvent, MenuItem, PredefinedMenuItem},
+    menu::{AboutMetadata, CheckMenuItem, Menu, MenuEvent, MenuItem, PredefinedMenuItem},
     Icon, TrayIconBuilder,
 };
 use windows::Win32::{
     Foundation::{LPARAM, WPARAM},
-    UI::WindowsAndMessaging::{GetClassNameW, PostMessageW, WM_CLOSE},
+    System::Threading::GetCurrentThreadId,
+    UI::WindowsAndMessaging::{
+        GetClassNameW, PeekMessageW, PostMessageW, PostThreadMessageW, MSG, PM_NOREMOVE, PM_REMOVE,
+        WM_CLOSE, WM_QUIT,
+    },
 };
 
 use crate::{
+    status::{last_result, WatcherStatus},
     stop_token::StopToken,
     wapi::{enum_windows::EnumWindowsRs, event_loop::EventLoop},
     RunType,
 };
 
+/// how often to check whether the game was injected yet, with `tray_on_inject`
+const INJECT_POLL: Duration = Duration::from_millis(250);
+
 pub struct AppTray;
 
+pub struct TrayHandle {
+    handle: JoinHandle<()>,
+    exit: TrayExit,
+}
+
+impl TrayHandle {
+    pub fn join(self) -> thread::Result<()> {
+        self.handle.join()
+    }
+
+    /// Remove the tray and stop its event loop. This does not stop the watcher
+    pub fn exit(&self) {
+        self.exit.exit();
+    }
+
+    /// Get a handle which can exit the tray from any thread
+    pub fn exit_handle(&self) -> TrayExit {
+        self.exit
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct TrayExit {
+    thread_id: Option<u32>,
+}
+
+impl TrayExit {
+    pub fn exit(&self) {
+        let Some(thread_id) = self.thread_id else {
+            return;
+        };
+
+        _ = unsafe { PostThreadMessageW(thread_id, WM_QUIT, WPARAM(0), LPARAM(0)) };
+    }
+}
+
 impl AppTray {
     pub fn run(
+        config: &'static Config,
+        status: WatcherStatus,
         watcher_token: StopToken,
         timeout_token: Option<StopToken>,
         kind: RunType,
-    ) -> JoinHandle<()> {
-        thread::spawn(move || {
-            let icon = Icon::from_resource(1, None).unwrap();
+    ) -> TrayHandle {
+        let (id_sender, id_recv) = channel();
+
+        // only the watcher injects more than once, so only it waits
+        let on_inject = config.core.tray_on_inject && matches!(kind, RunType::Watcher);
+
+        let handle = thread::spawn(move || {
+            if on_inject {
+                // exiting must work before the tray exists, so this thread needs a message queue right away
+                let mut msg = MSG::default();
+                _ = unsafe { PeekMessageW(&mut msg, None, 0, 0, PM_NOREMOVE) };
+                _ = id_sender.send(unsafe { GetCurrentThreadId() });
+
+                info!("Tray will be shown once the game was injected");
+
+                if !wait_for_inject(&status) {
+                    trace!("exited before the game was injected; tray was never shown");
+                    return;
+                }
+
+                info!("Game was injected; showing tray");
+            }
+
+            let icon = load_icon(config);
 
             let tray_menu = Menu::new();
 
             let quit_i = MenuItem::new("Quit", true, None);
+            // only the watcher finds more than one game
+            let pause_i = matches!(kind, RunType::Watcher)
+                .then(|| CheckMenuItem::new("Pause injection", true, status.is_paused(), None));
 
             let authors = env!("CARGO_PKG_AUTHORS")
                 .split(':')
@@ -40,7 +116,12 @@ impl AppTray {
                 RunType::Injector => "Injector".to_owned(),
             };
 
-            let title = format!("Yet Another BG3 Native Mod Loader - {kind}");
+            let title = config
+                .core
+                .tray_title
+                .as_deref()
+                .unwrap_or("Yet Another BG3 Native Mod Loader");
+            let title = format!("{title} - {kind}");
 
             tray_menu
                 .append_items(&[
@@ -59,21 +140,64 @@ impl AppTray {
                         }),
                     ),
                     &PredefinedMenuItem::separator(),
-                    &quit_i,
                 ])
                 .unwrap();
 
+            if let Some(pause_i) = pause_i.as_ref() {
+                tray_menu.append(pause_i).unwrap();
+            }
+
+            tray_menu.append(&quit_i).unwrap();
+
             let mut tray_icon = Some(
                 TrayIconBuilder::new()
-                    .with_tooltip(title)
+                    .with_tooltip(tooltip(&title, &status))
                     .with_menu(Box::new(tray_menu))
                     .with_icon(icon)
                     .build()
                     .unwrap(),
             );
 
+            // the tray's window was created, so this thread now has a message queue to post to
+            if !on_inject {
+                _ = id_sender.send(unsafe { GetCurrentThreadId() });
+            }
+
+            let mut last_status = (
+                status.is_running(),
+                status.injected_pid(),
+                status.is_paused(),
+                last_result(),
+            );
+
             EventLoop::new().run(move |event_loop, _| {
+                // keep the tooltip in sync with the real watcher state
+                let current = (
+                    status.is_running(),
+                    status.injected_pid(),
+                    status.is_paused(),
+                    last_result(),
+                );
+                if current != last_status {
+                    last_status = current;
+
+                    if let Some(tray_icon) = tray_icon.as_ref() {
+                        _ = tray_icon.set_tooltip(Some(tooltip(&title, &status)));
+                    }
+                }
+
                 if let Ok(event) = MenuEvent::receiver().try_recv() {
+                    if let Some(pause_i) = pause_i.as_ref().filter(|p| event.id == *p.id()) {
+                        let paused = pause_i.is_checked();
+                        status.set_paused(paused);
+
+                        if paused {
+                            info!("Injection paused; new game processes will be left alone");
+                        } else {
+                            info!("Injection resumed");
+                        }
+                    }
+
                     if event.id == quit_i.id() {
                         if let Some(token) = timeout_token.as_ref() {
                             token.stop();
@@ -113,6 +237,75 @@ impl AppTray {
                     }
                 }
             });
-        })
+        });
+
+        // if the tray failed to start, there's nothing to exit
+        let thread_id = id_recv.recv().ok();
+
+        TrayHandle {
+            handle,
+            exit: TrayExit { thread_id },
+        }
     }
 }
+
+/// Wait until the watcher injected the game. Returns false if the tray was exited before then
+fn wait_for_inject(status: &WatcherStatus) -> bool {
+    let mut msg = MSG::default();
+
+    loop {
+        while unsafe { PeekMessageW(&mut msg, None, 0, 0, PM_REMOVE) }.as_bool() {
+            if msg.message == WM_QUIT {
+                return false;
+            }
+        }
+
+        if status.is_injected() {
+            return true;
+        }
+
+        thread::sleep(INJECT_POLL);
+    }
+}
+
+fn tooltip(title: &str, status: &WatcherStatus) -> String {
+    let state = match (status.is_running(), status.injected_pid()) {
+        (_, Some(pid)) => format!("Injected (pid {pid})"),
+        (true, None) => "Waiting for game".to_owned(),
+        (false, None) => "Stopped".to_owned(),
+    };
+
+    let mut tooltip = if status.is_paused() {
+        format!("{title}\n{state} (injection paused)")
+    } else {
+        format!("{title}\n{state}")
+    };
+
+    if let Some(last) = last_result() {
+        tooltip.push_str(&format!("\nLast: {last}"));
+    }
+
+    tooltip
+}
+
+/// Load the custom tray icon if one was configured, otherwise the embedded one
+fn load_icon(config: &Config) -> Icon {
+    if let Some(path) = config.core.tray_icon_path.as_ref() {
+        if path.is_file() {
+            match Icon::from_path(path, None) {
+                Ok(icon) => {
+                    trace!(path = %path.display(), "loaded custom tray icon");
+                    return icon;
+                }
+
+                Err(e) => {
+                    warn!(%e, path = %path.display(), "failed to load tray_icon_path; falling back to default icon");
+                }
+            }
+        } else {
+            warn!(path = %path.display(), "tray_icon_path is not a file; falling back to default icon");
+        }
+    }
+
+    Icon::from_resource(1, None).unwrap()
+}
@@ -0,0 +1,120 @@
+use std::{
+    collections::HashSet,
+    path::Path,
+    sync::{LazyLock, Mutex},
+    thread,
+    time::Duration,
+};
+
+use eyre::Result;
+use shared::{
+    config::get_config,
+    discovery::all_plugin_dirs,
+    paths::get_staging_dir,
+    utils::{OwnedHandle, SuperLock as _},
+};
+use tracing::{info, trace, trace_span, warn};
+use windows::Win32::System::Threading::{OpenProcess, PROCESS_QUERY_INFORMATION, PROCESS_VM_READ};
+
+use crate::{
+    event_log,
+    process_watcher::Pid,
+    wapi::{
+        enum_process_modules::EnumProcessModulesExRs,
+        get_module_file_name_ex::GetModuleFileNameExRs,
+    },
+};
+
+const POLL_RATE: Duration = Duration::from_secs(5);
+
+/// pids which already have a watcher
+static WATCHED: LazyLock<Mutex<HashSet<Pid>>> = LazyLock::new(Mutex::default);
+
+/// Watch the game's loaded plugins, and warn when one of them gets unloaded again, e.g. by another mod.
+/// Plugins are recognized by being loaded from a plugins dir (including `target_plugin_dirs`) or the staging dir. Plugins loaded later on
+/// (e.g. `late_plugins`) are picked up too. Only one watcher runs per pid; it stops once the game exits
+pub fn watch(pid: Pid) {
+    if !WATCHED.super_lock().insert(pid) {
+        return;
+    }
+
+    thread::spawn(move || {
+        let span = trace_span!("unload_watch");
+        let _guard = span.enter();
+
+        if let Err(e) = watch_process(pid) {
+            trace!(%e, pid, "stopped watching for unloaded plugins");
+        }
+
+        // pids get reused
+        WATCHED.super_lock().remove(&pid);
+    });
+}
+
+fn watch_process(pid: Pid) -> Result<()> {
+    let process: OwnedHandle =
+        unsafe { OpenProcess(PROCESS_QUERY_INFORMATION | PROCESS_VM_READ, false, pid)? }.into();
+
+    let mut dirs = all_plugin_dirs(get_config()?.get())?;
+    dirs.push(get_staging_dir());
+
+    let dirs = dirs
+        .iter()
+        .map(|dir| dir.to_string_lossy().to_lowercase())
+        .collect::<Vec<_>>();
+
+    info!(pid, "Watching for plugins being unloaded from the game");
+
+    let mut buf = vec![0u16; 1024];
+    let mut loaded = HashSet::new();
+
+    loop {
+        // errors once the process exited
+        let current = plugin_modules(&process, &dirs, &mut buf)?;
+
+        for path in loaded.difference(&current) {
+            let path = Path::new(path);
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+
+            warn!(pid, path = %path.display(), "{name} was unloaded from the game; the game or another mod may have forced it out");
+
+            event_log::report(
+                "Plugin unloaded",
+                &format!("{name} was unloaded from the game (pid {pid}) after it was loaded. The game or another mod may have forced it out.\n\nPath: {}", path.display()),
+            );
+        }
+
+        for path in current.difference(&loaded) {
+            trace!(pid, path, "watching plugin");
+        }
+
+        loaded = current;
+
+        thread::sleep(POLL_RATE);
+    }
+}
+
+/// The lowercased paths of every module loaded from one of `dirs`
+fn plugin_modules(
+    process: &OwnedHandle,
+    dirs: &[String],
+    buf: &mut Vec<u16>,
+) -> Result<HashSet<String>> {
+    let mut modules = HashSet::new();
+
+    EnumProcessModulesExRs(process, |module| {
+        let path = GetModuleFileNameExRs(process, Some(module), &mut *buf)?;
+
+        // plugins are loaded with extended paths
+        let path = path.to_os_string().to_string_lossy().to_lowercase();
+        let path = path.strip_prefix(r"\\?\").unwrap_or(&path);
+
+        if dirs.iter().any(|dir| path.starts_with(dir.as_str())) {
+            modules.insert(path.to_owned());
+        }
+
+        Ok(true)
+    })?;
+
+    Ok(modules)
+}
@@ -0,0 +1,177 @@
+use std::{
+    env,
+    fmt::{self, Display},
+    fs,
+    path::PathBuf,
+};
+
+use eyre::Result;
+use shared::{
+    config::Config,
+    discovery::{all_plugin_dirs, list_plugin_files},
+    manifest::get_manifest,
+    paths::get_bg3_plugins_dir,
+    pe::{invalid_reason, read_imports},
+    signature,
+};
+
+/// The verdict for a single plugin
+#[derive(Debug)]
+pub struct PluginVerdict {
+    pub path: PathBuf,
+    /// Why it won't load. Empty if it passed every check
+    pub problems: Vec<String>,
+    /// Set if it's skipped anyways, so it wasn't checked
+    pub skipped: Option<&'static str>,
+}
+
+#[derive(Debug, Default)]
+pub struct ValidateSummary {
+    pub plugins: Vec<PluginVerdict>,
+}
+
+impl ValidateSummary {
+    pub fn has_problems(&self) -> bool {
+        self.plugins.iter().any(|p| !p.problems.is_empty())
+    }
+}
+
+impl Display for ValidateSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.plugins.is_empty() {
+            return writeln!(f, "No plugins found");
+        }
+
+        for plugin in &self.plugins {
+            let file_name = plugin
+                .path
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy();
+
+            match plugin.skipped {
+                Some(reason) => writeln!(f, "SKIP {file_name}: {reason}")?,
+                None if plugin.problems.is_empty() => writeln!(f, "OK   {file_name}")?,
+                None => {
+                    writeln!(f, "FAIL {file_name}")?;
+                    for problem in &plugin.problems {
+                        writeln!(f, "  {problem}")?;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Check every plugin the loader would find for whether it can load, without a game. Plugins are found
+/// the same way the loader finds them. Checks which need the game (its version, the launcher, load phases)
+/// aren't done
+pub fn validate_plugins(config: &Config) -> Result<ValidateSummary> {
+    let plugins_dir = get_bg3_plugins_dir()?;
+
+    // with a manifest, only the plugins listed in it are ever loaded
+    let candidates = match get_manifest()? {
+        Some(manifest) => manifest
+            .plugins
+            .iter()
+            .filter(|p| p.enabled)
+            .map(|p| plugins_dir.join(&p.file))
+            .collect::<Vec<_>>(),
+
+        // every exe's plugins
+        None => {
+            let mut candidates = Vec::new();
+            for dir in all_plugin_dirs(config)? {
+                candidates.extend(list_plugin_files(config, &dir)?);
+            }
+
+            candidates
+        }
+    };
+
+    // where the game looks for the dlls plugins import from
+    let search_dirs = [
+        Some(plugins_dir.clone()),
+        Some(config.core.install_root.join("bin")),
+        env::var_os("SystemRoot").map(|root| PathBuf::from(root).join("System32")),
+    ];
+
+    let plugins = candidates
+        .into_iter()
+        .map(|path| validate_plugin(config, path, &search_dirs))
+        .collect();
+
+    Ok(ValidateSummary { plugins })
+}
+
+fn validate_plugin(
+    config: &Config,
+    path: PathBuf,
+    search_dirs: &[Option<PathBuf>],
+) -> PluginVerdict {
+    let name = path
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .into_owned();
+
+    let mut verdict = PluginVerdict {
+        path,
+        problems: Vec::new(),
+        skipped: None,
+    };
+
+    if config.core.is_plugin_disabled(&name) {
+        verdict.skipped = Some("disabled in config.toml");
+        return verdict;
+    }
+
+    let data = match fs::read(&verdict.path) {
+        Ok(data) => data,
+        Err(e) => {
+            verdict.problems.push(format!("can't be read: {e}"));
+            return verdict;
+        }
+    };
+
+    if let Some(reason) = invalid_reason(&data) {
+        verdict.problems.push(reason);
+        // the rest of the checks need a valid dll
+        return verdict;
+    }
+
+    if config.core.require_signed {
+        if let Some(reason) = signature::untrusted_reason(&verdict.path, &config.core) {
+            verdict
+                .problems
+                .push(format!("failed signature verification: {reason}"));
+        }
+    }
+
+    for import in read_imports(&verdict.path) {
+        if !is_import_available(&import, search_dirs) {
+            verdict
+                .problems
+                .push(format!("imports from {import}, which was not found"));
+        }
+    }
+
+    verdict
+}
+
+/// Whether the game will be able to find a dll that's imported from
+fn is_import_available(import: &str, search_dirs: &[Option<PathBuf>]) -> bool {
+    // api sets are resolved by the OS, and never exist as files
+    let lower = import.to_ascii_lowercase();
+    if lower.starts_with("api-ms-win-") || lower.starts_with("ext-ms-") {
+        return true;
+    }
+
+    // paths are case-insensitive on windows
+    search_dirs
+        .iter()
+        .flatten()
+        .any(|dir| dir.join(import).is_file())
+}
@@ -0,0 +1,156 @@
+use std::{
+    path::Path,
+    thread,
+    time::{Duration, Instant},
+};
+
+use eyre::{bail, Result};
+use shared::{config::WaitStrategy, utils::OwnedHandle};
+use tracing::{info, trace, trace_span};
+use unicase::UniCase;
+use windows::Win32::{
+    System::Threading::{OpenProcess, PROCESS_QUERY_INFORMATION, PROCESS_VM_READ},
+    UI::WindowsAndMessaging::{GetWindowThreadProcessId, IsWindowVisible},
+};
+
+use crate::{
+    process_watcher::Pid,
+    wapi::{
+        enum_process_modules::EnumProcessModulesExRs, enum_windows::EnumWindowsRs,
+        get_module_file_name_ex::GetModuleFileNameExRs,
+    },
+};
+
+/// how long to wait for a module before giving up and injecting anyways
+const MODULE_TIMEOUT: Duration = Duration::from_secs(60);
+const MODULE_POLL: Duration = Duration::from_millis(100);
+const MAIN_MENU_POLL: Duration = Duration::from_millis(500);
+
+/// Wait until the game process is ready for injection, according to the strategy
+pub fn wait_for_game(strategy: &WaitStrategy, pid: Pid) -> Result<()> {
+    let span = trace_span!("wait_for_game");
+    let _guard = span.enter();
+
+    let start = Instant::now();
+
+    match strategy {
+        WaitStrategy::Immediate => return Ok(()),
+
+        WaitStrategy::Delay(ms) => thread::sleep(Duration::from_millis(*ms)),
+
+        WaitStrategy::Module(name) => wait_for_module(pid, name)?,
+    }
+
+    info!(%strategy, waited = ?start.elapsed(), "Finished waiting for game");
+
+    Ok(())
+}
+
+fn wait_for_module(pid: Pid, name: &str) -> Result<()> {
+    let process: OwnedHandle =
+        unsafe { OpenProcess(PROCESS_QUERY_INFORMATION | PROCESS_VM_READ, false, pid)? }.into();
+
+    let name = UniCase::new(name);
+    let start = Instant::now();
+    let mut buf = vec![0u16; 1024];
+
+    loop {
+        let mut found = false;
+
+        // errors if the process exited
+        EnumProcessModulesExRs(&process, |module| {
+            let path = GetModuleFileNameExRs(&process, Some(module), &mut buf)?;
+            let path = path.to_os_string();
+
+            let file_name = Path::new(&path).file_name().unwrap_or_default();
+            if UniCase::new(file_name.to_string_lossy()) == name {
+                found = true;
+                return Ok(false);
+            }
+
+            Ok(true)
+        })?;
+
+        if found {
+            trace!(module = %name, "found module");
+            return Ok(());
+        }
+
+        if start.elapsed() >= MODULE_TIMEOUT {
+            bail!("timed out waiting for module {name}");
+        }
+
+        thread::sleep(MODULE_POLL);
+    }
+}
+
+/// Wait until the game has most likely reached its main menu. There's no way to ask the game, so this goes by
+/// two signs: the game has a visible window, and then it hasn't loaded any new modules for `settle`, which is
+/// only true once the initial loading is done
+pub fn wait_for_main_menu(pid: Pid, settle: Duration, timeout: Duration) -> Result<()> {
+    let span = trace_span!("wait_for_main_menu");
+    let _guard = span.enter();
+
+    let process: OwnedHandle =
+        unsafe { OpenProcess(PROCESS_QUERY_INFORMATION | PROCESS_VM_READ, false, pid)? }.into();
+
+    let start = Instant::now();
+
+    while !has_visible_window(pid) {
+        if start.elapsed() >= timeout {
+            bail!("timed out waiting for the game window");
+        }
+
+        thread::sleep(MAIN_MENU_POLL);
+    }
+
+    info!(pid, waited = ?start.elapsed(), "Game window appeared; waiting for loading to settle");
+
+    let mut last_count = 0;
+    let mut settled_since = Instant::now();
+
+    loop {
+        // errors if the process exited
+        let mut count = 0;
+        EnumProcessModulesExRs(&process, |_| {
+            count += 1;
+            Ok(true)
+        })?;
+
+        if count != last_count {
+            trace!(modules = count, "module count changed");
+            last_count = count;
+            settled_since = Instant::now();
+        } else if settled_since.elapsed() >= settle {
+            break;
+        }
+
+        if start.elapsed() >= timeout {
+            bail!("timed out waiting for the game to finish loading");
+        }
+
+        thread::sleep(MAIN_MENU_POLL);
+    }
+
+    info!(pid, modules = last_count, waited = ?start.elapsed(), "Game reached main menu");
+
+    Ok(())
+}
+
+/// Whether `pid` has a visible top-level window
+fn has_visible_window(pid: Pid) -> bool {
+    let mut found = false;
+
+    EnumWindowsRs(|hwnd| {
+        let mut owner = 0;
+        unsafe { GetWindowThreadProcessId(hwnd, Some(&mut owner as *mut _)) };
+
+        if owner == pid && unsafe { IsWindowVisible(hwnd) }.as_bool() {
+            found = true;
+        }
+
+        Ok(())
+    });
+
+    found
+}
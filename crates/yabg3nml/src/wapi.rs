@@ -4,4 +4,7 @@ pub mod enum_windows;
 pub mod event_loop;
 pub mod get_module_base_ex;
 pub mod get_module_file_name_ex;
+pub mod get_parent_pid;
 pub mod query_full_process_image_name;
+pub mod query_process_command_line;
+pub mod resume_process;
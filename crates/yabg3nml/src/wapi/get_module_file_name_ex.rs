@@ -1,9 +1,9 @@
 use eyre::{bail, Result};
-use shared::utils::{OwnedHandle, SuperLock as _};
-use tracing::{error, trace, trace_span};
+use shared::utils::{fill_path_buf, OwnedHandle, SuperLock as _};
+use tracing::{error, trace_span};
 use widestring::U16Str;
 use windows::Win32::{
-    Foundation::{GetLastError, HMODULE, MAX_PATH},
+    Foundation::{GetLastError, HMODULE},
     System::ProcessStatus::GetModuleFileNameExW,
 };
 
@@ -20,39 +20,25 @@ pub fn GetModuleFileNameExRs<'a>(
 
     let module = module.unwrap_or_default();
 
-    let len = loop {
+    let len = fill_path_buf(buf, |buf| {
         let len =
             unsafe { GetModuleFileNameExW(process.as_raw_handle().into(), module.into(), buf) };
 
         // If the size of the file name is larger than the value of the nSize parameter, the function succeeds but the
         // file name is truncated and null-terminated.
         // If the function fails, the return value is 0 (zero). To get extended error information, call GetLastError.
-        if len as usize == buf.len() {
-            let new_len = buf.len() + MAX_PATH as usize;
-
-            // buffer size insufficient
-            trace!(
-                new_len,
-                "insufficient buffer size; increasing it and trying again"
-            );
-
-            buf.resize(new_len, 0u16);
-
-            continue;
+        if len == 0 {
+            return Err(unsafe { GetLastError() });
         }
 
-        if len == 0 {
-            let err = unsafe { GetLastError() };
+        Ok(Some(len as usize))
+    });
 
+    match len {
+        Ok(len) => Ok(U16Str::from_slice(&buf[..len])),
+        Err(err) => {
             error!("{err:?}");
-
             bail!("GetModuleFileNameExRs: {err:?}");
         }
-
-        break len;
-    };
-
-    let path = &buf[..len as usize];
-
-    Ok(U16Str::from_slice(path))
+    }
 }
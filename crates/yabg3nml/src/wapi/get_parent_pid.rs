@@ -0,0 +1,45 @@
+use eyre::Result;
+use shared::utils::OwnedHandle;
+use tracing::{trace_span, warn};
+use windows::Win32::System::Diagnostics::ToolHelp::{
+    CreateToolhelp32Snapshot, Process32FirstW, Process32NextW, PROCESSENTRY32W, TH32CS_SNAPPROCESS,
+};
+
+use crate::process_watcher::Pid;
+
+/// The pid of the process which started `pid`. Note that the parent may have exited since,
+/// and its pid may have been reused
+#[allow(non_snake_case)]
+pub fn GetParentPidRs(pid: Pid) -> Option<Pid> {
+    let span = trace_span!("GetParentPidRs");
+    let _guard = span.enter();
+
+    match parent_pid(pid) {
+        Ok(parent) => parent,
+        Err(e) => {
+            warn!(%e, pid, "failed to snapshot processes");
+            None
+        }
+    }
+}
+
+fn parent_pid(pid: Pid) -> Result<Option<Pid>> {
+    let snapshot: OwnedHandle = unsafe { CreateToolhelp32Snapshot(TH32CS_SNAPPROCESS, 0)? }.into();
+
+    let mut entry = PROCESSENTRY32W {
+        dwSize: size_of::<PROCESSENTRY32W>() as u32,
+        ..Default::default()
+    };
+
+    let mut next = unsafe { Process32FirstW(snapshot.as_raw_handle(), &mut entry) };
+
+    while next.is_ok() {
+        if entry.th32ProcessID == pid {
+            return Ok(Some(entry.th32ParentProcessID));
+        }
+
+        next = unsafe { Process32NextW(snapshot.as_raw_handle(), &mut entry) };
+    }
+
+    Ok(None)
+}
@@ -1,11 +1,11 @@
 use eyre::{bail, Result};
-use shared::utils::{OwnedHandle, SuperLock as _};
+use shared::utils::{fill_path_buf, OwnedHandle, SuperLock as _};
 use tracing::{error, trace, trace_span};
 use widestring::U16Str;
 use windows::{
     core::PWSTR,
     Win32::{
-        Foundation::{ERROR_INSUFFICIENT_BUFFER, MAX_PATH},
+        Foundation::ERROR_INSUFFICIENT_BUFFER,
         System::Threading::{QueryFullProcessImageNameW, PROCESS_NAME_WIN32},
     },
 };
@@ -20,7 +20,7 @@ pub fn QueryFullProcessImageNameRs<'a>(
     let span = trace_span!(parent: CURRENT_PID.super_lock().clone(), "QueryFullProcessImageNameRs");
     let _guard = span.enter();
 
-    loop {
+    let len = fill_path_buf(buf, |buf| {
         let mut size = buf.len() as u32;
 
         let res = unsafe {
@@ -32,30 +32,27 @@ pub fn QueryFullProcessImageNameRs<'a>(
             )
         };
 
-        if let Err(e) = res {
-            if e.code() == ERROR_INSUFFICIENT_BUFFER.to_hresult() {
-                let new_len = buf.len() + MAX_PATH as usize;
+        match res {
+            Ok(()) => Ok(Some(size as usize)),
 
-                // buffer size insufficient
+            Err(e) if e.code() == ERROR_INSUFFICIENT_BUFFER.to_hresult() => {
                 trace!(
-                    new_len,
+                    len = buf.len(),
                     "insufficient buffer size; increasing it and trying again"
                 );
 
-                buf.resize(new_len, 0u16);
-
-                continue;
+                Ok(None)
             }
 
+            Err(e) => Err(e),
+        }
+    });
+
+    match len {
+        Ok(len) => Ok(U16Str::from_slice(&buf[..len])),
+        Err(e) => {
             error!("{e}");
             bail!("QueryFullProcessImageNameW: {e}");
         }
-
-        let slice = {
-            let s = &buf[..size as usize];
-            U16Str::from_slice(s)
-        };
-
-        return Ok(slice);
     }
 }
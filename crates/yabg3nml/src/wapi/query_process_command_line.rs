@@ -0,0 +1,108 @@
+use std::{ffi::c_void, mem, ptr, slice, sync::OnceLock};
+
+use eyre::{bail, Result};
+use shared::utils::{OwnedHandle, SuperLock as _};
+use tracing::{trace, trace_span};
+use windows::{
+    core::{s, w, Error},
+    Win32::{
+        Foundation::{HANDLE, NTSTATUS, UNICODE_STRING},
+        System::LibraryLoader::{GetModuleHandleW, GetProcAddress},
+    },
+};
+
+use crate::process_watcher::CURRENT_PID;
+
+type FarProc = unsafe extern "system" fn() -> isize;
+
+// https://ntdoc.m417z.com/ntqueryinformationprocess
+type NtQueryInformationProcessFn = unsafe extern "system" fn(
+    process_handle: HANDLE,
+    process_information_class: u32,
+    process_information: *mut c_void,
+    process_information_length: u32,
+    return_length: *mut u32,
+) -> NTSTATUS;
+
+// https://ntdoc.m417z.com/processinfoclass
+// returns a UNICODE_STRING followed by its buffer, without having to read the PEB out of the process
+const PROCESS_COMMAND_LINE_INFORMATION: u32 = 60;
+const STATUS_INFO_LENGTH_MISMATCH: NTSTATUS = NTSTATUS(0xC0000004_u32 as i32);
+
+/// Get the command line a process was started with
+#[allow(non_snake_case)]
+pub fn QueryProcessCommandLineRs(process: &OwnedHandle) -> Result<String> {
+    let span = trace_span!(parent: CURRENT_PID.super_lock().clone(), "QueryProcessCommandLineRs");
+    let _guard = span.enter();
+
+    #[allow(non_snake_case)]
+    let NtQueryInformationProcess = get_nt_query_information_process()?;
+
+    // u64 so the UNICODE_STRING at the start is aligned
+    let mut buf = vec![0u64; 128];
+
+    loop {
+        let len = buf.len() * size_of::<u64>();
+        let mut needed = 0u32;
+
+        let status = unsafe {
+            NtQueryInformationProcess(
+                process.as_raw_handle(),
+                PROCESS_COMMAND_LINE_INFORMATION,
+                buf.as_mut_ptr().cast(),
+                len as u32,
+                &mut needed,
+            )
+        };
+
+        if status == STATUS_INFO_LENGTH_MISMATCH {
+            if needed as usize <= len {
+                bail!("NtQueryInformationProcess: buffer size mismatch, but {needed} bytes is not larger than {len}");
+            }
+
+            trace!(
+                needed,
+                "insufficient buffer size; increasing it and trying again"
+            );
+
+            buf.resize((needed as usize).div_ceil(size_of::<u64>()), 0);
+
+            continue;
+        }
+
+        if let Err(e) = status.ok() {
+            bail!("NtQueryInformationProcess: {e}");
+        }
+
+        let info = unsafe { ptr::read(buf.as_ptr().cast::<UNICODE_STRING>()) };
+        if info.Buffer.is_null() || info.Length == 0 {
+            return Ok(String::new());
+        }
+
+        // Length is in bytes; the buffer points right after the UNICODE_STRING inside of `buf`
+        let cmd_line = unsafe {
+            slice::from_raw_parts(info.Buffer.0, info.Length as usize / size_of::<u16>())
+        };
+
+        return Ok(String::from_utf16_lossy(cmd_line));
+    }
+}
+
+/// NtQueryInformationProcess is not part of the windows crate's Win32 api, so it must be resolved at runtime
+fn get_nt_query_information_process() -> Result<NtQueryInformationProcessFn, Error> {
+    static CACHE: OnceLock<NtQueryInformationProcessFn> = OnceLock::new();
+
+    if let Some(f) = CACHE.get() {
+        return Ok(*f);
+    }
+
+    let handle = unsafe { GetModuleHandleW(w!("ntdll"))? };
+
+    let addr = unsafe { GetProcAddress(handle, s!("NtQueryInformationProcess")) };
+    let addr = addr.ok_or_else(Error::from_win32)?;
+
+    let f = unsafe { mem::transmute::<FarProc, NtQueryInformationProcessFn>(addr) };
+    _ = CACHE.set(f);
+
+    Ok(f)
+}
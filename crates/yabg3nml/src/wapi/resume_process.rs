@@ -0,0 +1,30 @@
+use std::{
+    os::windows::{io::AsRawHandle as _, process::ChildExt as _},
+    process::Child,
+};
+
+use eyre::{bail, Result};
+use tracing::{trace, trace_span};
+use windows::{
+    core::Error,
+    Win32::{Foundation::HANDLE, System::Threading::ResumeThread},
+};
+
+/// Resume a process which was created suspended, by resuming its main thread. That's the only thread
+/// `CREATE_SUSPENDED` suspends; any other suspended thread was suspended by the process itself, so it's left alone
+#[allow(non_snake_case)]
+pub fn ResumeProcessRs(child: &Child) -> Result<()> {
+    let span = trace_span!("ResumeProcessRs");
+    let _guard = span.enter();
+
+    let thread = HANDLE(child.main_thread_handle().as_raw_handle());
+
+    let count = unsafe { ResumeThread(thread) };
+    if count == u32::MAX {
+        bail!("failed to resume main thread: {}", Error::from_win32());
+    }
+
+    trace!(prev_suspend_count = count, "resumed main thread");
+
+    Ok(())
+}
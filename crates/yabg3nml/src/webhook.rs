@@ -0,0 +1,193 @@
+use std::{
+    ffi::c_void,
+    ptr, thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use eyre::{bail, eyre, OptionExt as _, Result};
+use shared::pipe::commands::LoadedMsg;
+use tracing::{info, trace_span, warn};
+use windows::{
+    core::{w, Error, HSTRING, PCWSTR},
+    Win32::Networking::WinHttp::{
+        WinHttpCloseHandle, WinHttpConnect, WinHttpOpen, WinHttpOpenRequest, WinHttpQueryHeaders,
+        WinHttpReceiveResponse, WinHttpSendRequest, WinHttpSetTimeouts,
+        WINHTTP_ACCESS_TYPE_DEFAULT_PROXY, WINHTTP_FLAG_SECURE, WINHTTP_OPEN_REQUEST_FLAGS,
+        WINHTTP_QUERY_FLAG_NUMBER, WINHTTP_QUERY_STATUS_CODE,
+    },
+};
+
+use crate::process_watcher::Pid;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// POST the injection results to `on_inject_url`. This runs on its own thread, and never fails loudly.
+/// Responses which aren't 2xx count as failures
+pub fn on_inject(url: String, pid: Pid, msg: &LoadedMsg) {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+
+    let body = format!(
+        r#"{{"pid":{pid},"plugins":{},"failed":{},"timestamp":{timestamp}}}"#,
+        msg.loaded, msg.failed
+    );
+
+    thread::spawn(move || {
+        let span = trace_span!("webhook");
+        let _guard = span.enter();
+
+        match post_json(&url, &body) {
+            Ok(_) => info!(%url, "sent on_inject webhook"),
+            Err(e) => warn!(%e, %url, "on_inject webhook failed"),
+        }
+    });
+}
+
+/// Closes the WinHTTP handle on drop
+struct Internet(*mut c_void);
+
+impl Internet {
+    fn new(handle: *mut c_void) -> Result<Self, Error> {
+        if handle.is_null() {
+            Err(Error::from_win32())
+        } else {
+            Ok(Self(handle))
+        }
+    }
+}
+
+impl Drop for Internet {
+    fn drop(&mut self) {
+        _ = unsafe { WinHttpCloseHandle(self.0) };
+    }
+}
+
+struct Url<'a> {
+    secure: bool,
+    host: &'a str,
+    port: u16,
+    path: &'a str,
+}
+
+/// Only plain `http(s)://host[:port][/path]` urls are supported
+fn parse_url(url: &str) -> Result<Url<'_>> {
+    let (secure, rest) = if let Some(rest) = url.strip_prefix("https://") {
+        (true, rest)
+    } else if let Some(rest) = url.strip_prefix("http://") {
+        (false, rest)
+    } else {
+        bail!("url must start with http:// or https://");
+    };
+
+    let (authority, path) = match rest.find('/') {
+        Some(i) => rest.split_at(i),
+        None => (rest, "/"),
+    };
+
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) => (host, port.parse().map_err(|_| eyre!("invalid port"))?),
+        None => (authority, if secure { 443 } else { 80 }),
+    };
+
+    if host.is_empty() {
+        bail!("url has no host");
+    }
+
+    Ok(Url {
+        secure,
+        host,
+        port,
+        path,
+    })
+}
+
+fn post_json(url: &str, body: &str) -> Result<()> {
+    let Url {
+        secure,
+        host,
+        port,
+        path,
+    } = parse_url(url)?;
+
+    let agent = HSTRING::from(format!("yabg3nml/{}", env!("CARGO_PKG_VERSION")));
+    let host = HSTRING::from(host);
+    let path = HSTRING::from(path);
+
+    let session = Internet::new(unsafe {
+        WinHttpOpen(
+            &agent,
+            WINHTTP_ACCESS_TYPE_DEFAULT_PROXY,
+            PCWSTR::null(),
+            PCWSTR::null(),
+            0,
+        )
+    })?;
+
+    let timeout = TIMEOUT.as_millis() as i32;
+    unsafe {
+        WinHttpSetTimeouts(session.0, timeout, timeout, timeout, timeout)?;
+    }
+
+    let connection = Internet::new(unsafe { WinHttpConnect(session.0, &host, port, 0) })?;
+
+    let flags = if secure {
+        WINHTTP_FLAG_SECURE
+    } else {
+        WINHTTP_OPEN_REQUEST_FLAGS(0)
+    };
+
+    let request = Internet::new(unsafe {
+        WinHttpOpenRequest(
+            connection.0,
+            w!("POST"),
+            &path,
+            PCWSTR::null(),
+            PCWSTR::null(),
+            ptr::null(),
+            flags,
+        )
+    })?;
+
+    let headers = "Content-Type: application/json\r\n"
+        .encode_utf16()
+        .collect::<Vec<_>>();
+    let len = u32::try_from(body.len())
+        .ok()
+        .ok_or_eyre("body too large")?;
+
+    unsafe {
+        WinHttpSendRequest(
+            request.0,
+            Some(&headers),
+            Some(body.as_ptr().cast()),
+            len,
+            len,
+            0,
+        )?;
+    }
+
+    unsafe {
+        WinHttpReceiveResponse(request.0, ptr::null_mut())?;
+    }
+
+    let mut status = 0u32;
+    let mut size = size_of::<u32>() as u32;
+    unsafe {
+        WinHttpQueryHeaders(
+            request.0,
+            WINHTTP_QUERY_STATUS_CODE | WINHTTP_QUERY_FLAG_NUMBER,
+            PCWSTR::null(),
+            Some(ptr::from_mut(&mut status).cast()),
+            &mut size,
+            None,
+        )?;
+    }
+
+    if !(200..300).contains(&status) {
+        bail!("server responded with HTTP {status}");
+    }
+
+    Ok(())
+}